@@ -0,0 +1,41 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use super::Solve;
+
+/// A callback registered with `Solve::on_output`.
+pub struct Alert<C> {
+    pred: Arc<dyn Fn(&C) -> bool + Send + Sync>,
+    callback: Arc<dyn Fn(&C) + Send + Sync>,
+}
+
+impl <C> Clone for Alert<C> {
+    fn clone(&self) -> Self {
+        Alert { pred: self.pred.clone(), callback: self.callback.clone() }
+    }
+}
+
+impl <D,C> Solve<D,C> where C: Clone + Debug + Send + Sync {
+    /// Calls `callback` with the output after every update where it's
+    /// defined and satisfies `pred` -- every such update, not just the
+    /// first, so e.g. an alert on a running average over 100 fires for as
+    /// long as it stays there. Alerts survive `migrate` and are shared with
+    /// clones, but aren't part of a checkpoint.
+    pub fn on_output<P,F>(&mut self, pred: P, callback: F)
+        where P: Fn(&C) -> bool + Send + Sync + 'static, F: Fn(&C) + Send + Sync + 'static {
+        self.alerts.push(Alert { pred: Arc::new(pred), callback: Arc::new(callback) })
+    }
+
+    pub(super) fn fire_alerts(&self) {
+        if self.alerts.is_empty() {
+            return
+        };
+        if let Some(c) = self.defined_output() {
+            for a in &self.alerts {
+                if (a.pred)(&c) {
+                    (a.callback)(&c)
+                }
+            }
+        }
+    }
+}
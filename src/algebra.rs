@@ -0,0 +1,104 @@
+use std::ops::{BitOr, Shr};
+use std::sync::Arc;
+
+use super::{AppOp, QRE};
+use super::QRE::*;
+
+/// A query wrapped so it composes with operators, for writing small
+/// queries quickly: `a | b` is the `Choice` of the two, and `q >> agg`
+/// aggregates repeated matches of `q` (see `agg`). The combinators that
+/// need an op are methods. `query` unwraps the result for `Solve`.
+pub struct Q<D,C>(pub QRE<D,C>);
+
+/// Matches one element satisfying `phi`, valued `op` of it.
+pub fn sat<D,C>(phi: fn(&D) -> bool, op: fn(&D) -> C) -> Q<D,C> {
+    Q(Sat{phi, op})
+}
+
+/// Matches the empty stream, valued `c`.
+#[allow(dead_code)]
+pub fn eps<D,C>(c: C) -> Q<D,C> {
+    Q(Eps{c})
+}
+
+/// How `>>` aggregates: `q >> agg(op)` matches `q` one or more times,
+/// combining the values with `op`; `q >> agg_from(c, op)` matches it any
+/// number of times, starting from `c`.
+pub struct Agg<C> {
+    init: Option<C>,
+    op: fn(C,C) -> C,
+}
+
+pub fn agg<C>(op: fn(C,C) -> C) -> Agg<C> {
+    Agg { init: None, op }
+}
+
+#[allow(dead_code)]
+pub fn agg_from<C>(c: C, op: fn(C,C) -> C) -> Agg<C> {
+    Agg { init: Some(c), op }
+}
+
+impl <D,C> Q<D,C> {
+    pub fn query(self) -> QRE<D,C> {
+        self.0
+    }
+
+    /// `self` followed by `g`, valued `op` of the two (`Split`).
+    #[allow(dead_code)]
+    pub fn then(self, g: Q<D,C>, op: fn(C,C) -> C) -> Q<D,C> {
+        Q(Split{f: Arc::new(self.0), g: Arc::new(g.0), op})
+    }
+
+    /// `self` and `g` on the same stream, valued `op` of the two
+    /// (`Combine`).
+    #[allow(dead_code)]
+    pub fn and(self, g: Q<D,C>, op: fn(C,C) -> C) -> Q<D,C> {
+        Q(Combine{f: Arc::new(self.0), g: Arc::new(g.0), op})
+    }
+
+    /// `self`, with `f` applied to its value (`App`).
+    #[allow(dead_code)]
+    pub fn map<F>(self, f: F) -> Q<D,C> where F: Fn(C) -> C + Send + Sync + 'static {
+        Q(App{f: Arc::new(self.0), op: AppOp::Fn(Arc::new(f))})
+    }
+
+    /// `self`, its value reported as `name` by `Solve::captures`.
+    #[allow(dead_code)]
+    pub fn tag(self, name: &'static str) -> Q<D,C> {
+        Q(Tag{name, f: Arc::new(self.0)})
+    }
+}
+
+impl <D,C> From<QRE<D,C>> for Q<D,C> {
+    fn from(q: QRE<D,C>) -> Self {
+        Q(q)
+    }
+}
+
+impl <D,C> BitOr for Q<D,C> {
+    type Output = Q<D,C>;
+
+    /// Chains of `|` build one flat `Choice`.
+    fn bitor(self, g: Q<D,C>) -> Q<D,C> {
+        match self.0 {
+            Choice{mut v} => {
+                v.push(g.0);
+                Q(Choice{v})
+            },
+            f => Q(Choice{v: vec![f, g.0]})
+        }
+    }
+}
+
+impl <D,C: Clone> Shr<Agg<C>> for Q<D,C> {
+    type Output = Q<D,C>;
+
+    fn shr(self, a: Agg<C>) -> Q<D,C> {
+        let body = Arc::new(self.0);
+        let init = match a.init {
+            Some(c) => Arc::new(Eps{c}),
+            None => body.clone()
+        };
+        Q(Iter{init, body, op: a.op})
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+
+use checkpoint::{Persist, Reader};
+use scan::Scan;
+
+/// How a point is scored against the window before it.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Method {
+    /// Standard deviations from the mean.
+    ZScore,
+    /// Median absolute deviations from the median, scaled to agree with
+    /// the z-score on normal data. Robust to outliers in the window.
+    Mad,
+}
+
+/// Scores how anomalous each element is against the `size` before it, as
+/// a `Scan`. It also counts how many elements in a row have scored beyond
+/// `threshold`, so "z-score > 4 for 3 consecutive points" is `run() >= 3`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Anomaly {
+    method: Method,
+    size: usize,
+    threshold: f64,
+    recent: VecDeque<f64>,
+    latest: f64,
+    score: Option<f64>,
+    run: u64,
+}
+
+impl Anomaly {
+    #[allow(dead_code)]
+    pub fn zscore(size: usize, threshold: f64) -> Self {
+        Self::with(Method::ZScore, size, threshold)
+    }
+
+    pub fn mad(size: usize, threshold: f64) -> Self {
+        Self::with(Method::Mad, size, threshold)
+    }
+
+    fn with(method: Method, size: usize, threshold: f64) -> Self {
+        Anomaly {
+            method, size, threshold,
+            recent: VecDeque::with_capacity(size),
+            latest: 0.0,
+            score: None,
+            run: 0
+        }
+    }
+
+    /// The latest element's score; `None` until there are two earlier
+    /// elements to compare it with, or if they're all equal.
+    #[allow(dead_code)]
+    pub fn score(&self) -> Option<f64> {
+        self.score
+    }
+
+    /// How many of the latest elements in a row scored beyond the
+    /// threshold, in either direction.
+    pub fn run(&self) -> u64 {
+        self.run
+    }
+
+    fn score_of(&self, x: f64) -> Option<f64> {
+        let n = self.recent.len() as f64;
+        if n < 2.0 {
+            return None
+        };
+        let (center, spread) = match self.method {
+            Method::ZScore => {
+                let mean = self.recent.iter().sum::<f64>() / n;
+                let var = self.recent.iter().map(|y| (y - mean) * (y - mean)).sum::<f64>() / (n - 1.0);
+                (mean, var.sqrt())
+            },
+            Method::Mad => {
+                let med = median(self.recent.iter().cloned().collect());
+                let mad = median(self.recent.iter().map(|y| (y - med).abs()).collect());
+                (med, mad / 0.6745)
+            }
+        };
+        if spread > 0.0 { Some((x - center) / spread) } else { None }
+    }
+}
+
+fn median(mut xs: Vec<f64>) -> f64 {
+    xs.sort_by(|a, b| a.total_cmp(b));
+    let n = xs.len();
+    if n % 2 == 1 { xs[n / 2] } else { (xs[n / 2 - 1] + xs[n / 2]) / 2.0 }
+}
+
+impl Scan for Anomaly {
+    fn sample(x: f64) -> Self {
+        Anomaly { latest: x, ..Self::with(Method::ZScore, 0, 0.0) }
+    }
+
+    fn latest(&self) -> f64 {
+        self.latest
+    }
+
+    /// Scores `x` against the window, then moves it into the window.
+    fn push(&mut self, x: f64) {
+        self.score = self.score_of(x);
+        self.run = match self.score {
+            Some(z) if z.abs() > self.threshold => self.run + 1,
+            _ => 0
+        };
+        self.latest = x;
+        self.recent.push_back(x);
+        if self.recent.len() > self.size {
+            self.recent.pop_front();
+        }
+    }
+}
+
+impl Persist for Anomaly {
+    fn write(&self, w: &mut Vec<u8>) {
+        (self.method == Method::Mad).write(w);
+        self.size.write(w);
+        self.threshold.write(w);
+        self.recent.write(w);
+        self.latest.write(w);
+        self.score.write(w);
+        self.run.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let method = if bool::read(r)? { Method::Mad } else { Method::ZScore };
+        let a = Anomaly {
+            method,
+            size: usize::read(r)?,
+            threshold: f64::read(r)?,
+            recent: VecDeque::read(r)?,
+            latest: f64::read(r)?,
+            score: Option::read(r)?,
+            run: u64::read(r)?
+        };
+        if a.recent.len() > a.size {
+            return Err(format!("Anomaly: {} elements in a window of {}", a.recent.len(), a.size))
+        };
+        Ok(a)
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Solve;
+use isolate;
+use ops::{Group, Monoid};
+use window::{ApproxWindow, Window};
+
+/// Anything that can be fed a stream one element at a time.
+pub trait Feed<D>: Send {
+    fn feed(&mut self, d: &D);
+
+    /// How many residuals it holds, for `Quota::residuals`.
+    fn workingset(&self) -> u64 {
+        0
+    }
+
+    /// Roughly how many bytes it holds, for `Quota::memory`.
+    fn memory(&self) -> u64 {
+        0
+    }
+}
+
+impl <D,C> Feed<D> for Solve<D,C> where D: Send, C: Clone + Debug + Send + Sync {
+    fn feed(&mut self, d: &D) {
+        self.update(d)
+    }
+
+    fn workingset(&self) -> u64 {
+        self.state.len() as u64
+    }
+
+    fn memory(&self) -> u64 {
+        self.approx_memory()
+    }
+}
+
+impl <D,G> Feed<D> for Window<D,G> where D: Send, G: Group + Clone + Debug + Send + Sync {
+    fn feed(&mut self, d: &D) {
+        self.update(d)
+    }
+}
+
+impl <D,M> Feed<D> for ApproxWindow<D,M> where D: Send, M: Monoid + Clone + Debug + Send + Sync {
+    fn feed(&mut self, d: &D) {
+        self.update(d)
+    }
+}
+
+/// A subscriber that's also read from elsewhere, e.g. a query whose output
+/// another thread serves.
+impl <D, T: Feed<D>> Feed<D> for Arc<Mutex<T>> {
+    fn feed(&mut self, d: &D) {
+        self.lock().unwrap().feed(d)
+    }
+
+    fn workingset(&self) -> u64 {
+        self.lock().map_or(0, |t| t.workingset())
+    }
+
+    fn memory(&self) -> u64 {
+        self.lock().map_or(0, |t| t.memory())
+    }
+}
+
+/// Limits on one subscriber, so a pathological query doesn't keep slowing
+/// down the others sharing its `Broadcast`; see `Broadcast::add_with_quota`.
+/// Subscribers are fed in turn on the thread calling `push`, and a quota is
+/// only checked once an update returns: the update that overruns still
+/// holds up everyone after it (for good, if it never returns), it's just
+/// the last.
+#[derive(Clone,Copy,Debug)]
+pub struct Quota {
+    pub residuals: Option<u64>,
+    /// In bytes, as `Feed::memory` estimates them. That walks the
+    /// subscriber's state, so it's only checked every `MEMORY_CHECK_EVERY`
+    /// elements.
+    pub memory: Option<u64>,
+    /// For any one element, measured after the fact.
+    pub update_time: Option<Duration>,
+    pub overrun: Overrun,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota { residuals: None, memory: None, update_time: None, overrun: Overrun::Kill }
+    }
+}
+
+/// What happens to a subscriber that goes over its `Quota`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Overrun {
+    /// It misses the next `elements` elements, then carries on (and is
+    /// checked again). This sheds the time it takes, not what it holds,
+    /// and its output no longer covers the whole stream.
+    #[allow(dead_code)]
+    Throttle{elements: u64},
+    /// It's fed nothing more.
+    Kill,
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Status {
+    Running,
+    /// Missing elements under `Overrun::Throttle`, for the reason given.
+    Throttled(String),
+    /// For the reason given: an overrun under `Overrun::Kill`, or a panic.
+    Killed(String),
+}
+
+pub const MEMORY_CHECK_EVERY: u64 = 64;
+
+struct Sub<D> {
+    id: u64,
+    feed: Box<dyn Feed<D>>,
+    quota: Option<Quota>,
+    status: Status,
+    // Elements it's been fed, and how many more it's to miss.
+    fed: u64,
+    skip: u64,
+}
+
+impl <D> Sub<D> {
+    fn push(&mut self, d: &D) {
+        if let Status::Killed(_) = self.status {
+            return
+        };
+        if self.skip > 0 {
+            self.skip -= 1;
+            return
+        };
+        let quota = match self.quota {
+            Some(quota) => quota,
+            None => {
+                self.feed.feed(d);
+                return
+            }
+        };
+        let start = Instant::now();
+        let feed = &mut self.feed;
+        if isolate::catch(|| feed.feed(d)).is_none() {
+            self.status = Status::Killed("panicked".to_string());
+            return
+        };
+        self.fed += 1;
+        let elapsed = start.elapsed();
+        let over = match quota {
+            Quota{update_time: Some(t), ..} if elapsed > t => Some(format!("took {:?} over one element", elapsed)),
+            Quota{residuals: Some(n), ..} if self.feed.workingset() > n => {
+                Some(format!("holds {} residuals", self.feed.workingset()))
+            },
+            Quota{memory: Some(n), ..} if self.fed.is_multiple_of(MEMORY_CHECK_EVERY) && self.feed.memory() > n => {
+                Some(format!("holds about {} bytes", self.feed.memory()))
+            },
+            _ => None
+        };
+        self.status = match (over, quota.overrun) {
+            (None, _) => Status::Running,
+            (Some(why), Overrun::Throttle{elements}) => {
+                self.skip = elements;
+                Status::Throttled(why)
+            },
+            (Some(why), Overrun::Kill) => Status::Killed(why)
+        }
+    }
+}
+
+/// Feeds one stream to a changing set of subscribers. Each element is
+/// decoded once, by whoever calls `push`, and then handed to every
+/// subscriber by reference, so the ingest path's work is shared however
+/// many queries are attached.
+pub struct Broadcast<D> {
+    subs: Vec<Sub<D>>,
+    next_id: u64,
+}
+
+impl <D> Broadcast<D> {
+    pub fn new() -> Self {
+        Broadcast { subs: Vec::new(), next_id: 0 }
+    }
+
+    /// Starts feeding `sub` from the next element on. The id is for
+    /// `remove`.
+    pub fn add<F: Feed<D> + 'static>(&mut self, sub: F) -> u64 {
+        self.attach(Box::new(sub), None)
+    }
+
+    /// `add`, with `sub` held to `quota` and its panics caught: one that
+    /// panics is killed, and the other subscribers carry on. One that
+    /// hangs hangs them all, though; see `Quota`. Checking costs a clock
+    /// read or two per element.
+    pub fn add_with_quota<F: Feed<D> + 'static>(&mut self, sub: F, quota: Quota) -> u64 {
+        self.attach(Box::new(sub), Some(quota))
+    }
+
+    fn attach(&mut self, feed: Box<dyn Feed<D>>, quota: Option<Quota>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subs.push(Sub { id, feed, quota, status: Status::Running, fed: 0, skip: 0 });
+        id
+    }
+
+    /// Stops feeding the subscriber `add` returned `id` for, and hands it
+    /// back; `None` if it was already removed. Killed subscribers stay
+    /// attached, for `status`, until they're removed.
+    pub fn remove(&mut self, id: u64) -> Option<Box<dyn Feed<D>>> {
+        let i = self.subs.iter().position(|s| s.id == id)?;
+        Some(self.subs.remove(i).feed)
+    }
+
+    /// How the subscriber `id` is doing against its quota; always
+    /// `Running` without one.
+    pub fn status(&self, id: u64) -> Option<&Status> {
+        self.subs.iter().find(|s| s.id == id).map(|s| &s.status)
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.subs.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.subs.is_empty()
+    }
+
+    pub fn push(&mut self, d: &D) {
+        for s in &mut self.subs {
+            s.push(d)
+        }
+    }
+}
+
+impl <D> Default for Broadcast<D> {
+    fn default() -> Self {
+        Broadcast::new()
+    }
+}
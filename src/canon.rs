@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{AppOp, Pool, QRE};
+use super::QRE::*;
+#[cfg(feature = "symbolic")]
+use symbolic;
+
+/// Whether `q` has `Trigger`s or `Tag`s, whose actions and observers see
+/// each derivative of their body separately, so `Iter`s mentioning them
+/// mustn't be merged.
+pub fn has_hooks<D,C>(q: &QRE<D,C>) -> bool {
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => false,
+        Tag{..} | Trigger{..} => true,
+        Choice{v} => v.iter().any(has_hooks),
+        Split{f, g, ..} | Combine{f, g, ..} | TryCombine{f, g, ..} => has_hooks(f) || has_hooks(g),
+        Iter{init, body, ..} => has_hooks(init) || has_hooks(body),
+        App{f, ..} | Cap{f, ..} => has_hooks(f),
+    }
+}
+
+/// `q` with the parts that the declared predicates show can never match
+/// (see `symbolic::dead`) replaced by `Bot`, for `canon` to drop as the
+/// residuals reach them. That's worked out once, for the query, rather
+/// than for every residual on every update. Without the `symbolic`
+/// feature it's `q` as is.
+pub fn prune<D,C>(q: QRE<D,C>) -> QRE<D,C> where C: Clone {
+    #[cfg(feature = "symbolic")]
+    return symbolic::prune(&q);
+    #[cfg(not(feature = "symbolic"))]
+    q
+}
+
+/// Whether `q`, with its children already canonical, can never match.
+fn dead<D,C>(q: &QRE<D,C>) -> bool {
+    match q {
+        Bot => true,
+        Choice{v} => v.is_empty(),
+        _ => false
+    }
+}
+
+/// Rewrites a generation of residuals into canonical form: residuals that
+/// can no longer match are dropped (however deep the dead part), nested
+/// `Choice`s are flattened, chains of `App`s become one `App` applying
+/// each op in turn, and `Iter`s continuing the same body with the same op
+/// become one `Iter` over the choice of their inits, so the body's next
+/// iteration is derived once for all of them. None of this changes what
+/// the residuals match or output. Only nodes `state` owns outright are
+/// rewritten; anything shared (with a clone, or with the query) is left
+/// alone, so this costs about as much as the derivation that built it.
+pub fn canon_all<D,C>(state: &mut Vec<QRE<D,C>>, pool: &mut Pool<D,C>) where C: Clone {
+    let mut v = pool.buf();
+    for q in state.drain(..) {
+        push(q, pool, &mut v)
+    };
+    if pool.factor {
+        factor(&mut v, pool)
+    };
+    std::mem::swap(state, &mut v);
+    pool.recycle_buf(v)
+}
+
+/// Pushes `q` in canonical form, splicing in the branches of a `Choice`
+/// and leaving out dead ones.
+fn push<D,C>(q: QRE<D,C>, pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>) where C: Clone {
+    match canon(q, pool) {
+        Choice{mut v} => {
+            out.append(&mut v);
+            pool.recycle_buf(v)
+        },
+        q if dead(&q) => (),
+        q => out.push(q)
+    }
+}
+
+fn canon<D,C>(q: QRE<D,C>, pool: &mut Pool<D,C>) -> QRE<D,C> where C: Clone {
+    match q {
+        Choice{mut v} => {
+            let mut out = pool.buf();
+            for q in v.drain(..) {
+                push(q, pool, &mut out)
+            };
+            pool.recycle_buf(v);
+            if pool.factor {
+                factor(&mut out, pool)
+            };
+            if out.len() == 1 {
+                let q = out.pop().unwrap();
+                pool.recycle_buf(out);
+                q
+            }
+            else {
+                Choice{v: out}
+            }
+        },
+        Split{f, g, op} => {
+            let f = canon_node(f, pool);
+            if dead(&f) || dead(&g) { Bot } else { Split{f, g, op} }
+        },
+        Iter{init, body, op} => {
+            let init = canon_node(init, pool);
+            if dead(&init) { Bot } else { Iter{init, body, op} }
+        },
+        App{f, op} => {
+            let f = canon_node(f, pool);
+            match *f {
+                ref q if dead(q) => Bot,
+                App{f: ref inner, op: ref first} => App{f: inner.clone(), op: AppOp::Then(Box::new(first.clone()), Box::new(op))},
+                _ => App{f, op}
+            }
+        },
+        Combine{f, g, op} => {
+            let f = canon_node(f, pool);
+            let g = canon_node(g, pool);
+            if dead(&f) || dead(&g) { Bot } else { Combine{f, g, op} }
+        },
+        TryCombine{f, g, op} => {
+            let f = canon_node(f, pool);
+            let g = canon_node(g, pool);
+            if dead(&f) || dead(&g) { Bot } else { TryCombine{f, g, op} }
+        },
+        Tag{name, f} => {
+            let f = canon_node(f, pool);
+            if dead(&f) { Bot } else { Tag{name, f} }
+        },
+        Cap{f, caps, later} => {
+            let f = canon_node(f, pool);
+            if dead(&f) { Bot } else { Cap{f, caps, later} }
+        },
+        Trigger{body, action} => {
+            let body = canon_node(body, pool);
+            if dead(&body) { Bot } else { Trigger{body, action} }
+        },
+        q => q
+    }
+}
+
+/// Canonicalizes a child in place if nothing else holds it.
+fn canon_node<D,C>(mut a: Arc<QRE<D,C>>, pool: &mut Pool<D,C>) -> Arc<QRE<D,C>> where C: Clone {
+    if let Some(q) = Arc::get_mut(&mut a) {
+        let c = canon(std::mem::replace(q, Bot), pool);
+        *q = c
+    };
+    a
+}
+
+/// Merges the `Iter`s in `v` that share a body and op, keeping the first
+/// one's place.
+fn factor<D,C>(v: &mut Vec<QRE<D,C>>, pool: &mut Pool<D,C>) where C: Clone {
+    if v.len() < 2 {
+        return
+    };
+    let mut first: HashMap<(*const QRE<D,C>, usize), usize> = HashMap::new();
+    let mut merged: HashMap<usize, Vec<QRE<D,C>>> = HashMap::new();
+    let mut out = pool.buf();
+    for q in v.drain(..) {
+        let key = match q {
+            Iter{ref body, op, ..} => (Arc::as_ptr(body), op as usize),
+            q => { out.push(q); continue }
+        };
+        match first.get(&key) {
+            Some(i) => if let Iter{init, ..} = q {
+                merged.entry(*i).or_default().push(unwrap(init))
+            },
+            None => {
+                first.insert(key, out.len());
+                out.push(q)
+            }
+        }
+    };
+    for (i, mut inits) in merged {
+        if let Iter{init, body, op} = std::mem::replace(&mut out[i], Bot) {
+            let mut v = pool.buf();
+            match unwrap(init) {
+                Choice{v: mut w} => v.append(&mut w),
+                q => v.push(q)
+            };
+            for q in inits.drain(..) {
+                match q {
+                    Choice{v: mut w} => v.append(&mut w),
+                    q => v.push(q)
+                }
+            };
+            out[i] = Iter{init: pool.node(Choice{v}), body, op}
+        }
+    };
+    std::mem::swap(v, &mut out);
+    pool.recycle_buf(out)
+}
+
+fn unwrap<D,C: Clone>(a: Arc<QRE<D,C>>) -> QRE<D,C> {
+    Arc::try_unwrap(a).unwrap_or_else(|a| (*a).clone())
+}
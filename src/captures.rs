@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use super::{Solve, QRE};
+use super::QRE::*;
+use fallible;
+use smallvec::SmallVec;
+
+/// The values `Tag`ged sub-expressions took in (part of) a match, one
+/// per name. Like regex groups, a tag matched more than once (e.g. inside
+/// an `Iter`) keeps its last match, so this stays as small as the number
+/// of tags however long the match runs.
+#[derive(Clone,Debug)]
+pub struct Caps<C>(Vec<(&'static str, C)>);
+
+impl <C> Caps<C> {
+    pub fn new() -> Self {
+        Caps(Vec::new())
+    }
+
+    pub fn leaf(name: &'static str, c: C) -> Self {
+        Caps(vec![(name, c)])
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, (&'static str, C)> {
+        self.0.iter()
+    }
+
+    /// Adds a capture, replacing any earlier one of the same name.
+    pub fn push(&mut self, name: &'static str, c: C) {
+        match self.0.iter_mut().find(|(n, _)| *n == name) {
+            Some(e) => e.1 = c,
+            None => self.0.push((name, c))
+        }
+    }
+
+    /// `self`'s captures followed by `later`'s.
+    pub fn join(mut self, later: Self) -> Self {
+        if self.is_empty() {
+            return later
+        };
+        for (name, c) in later.0 {
+            self.push(name, c)
+        };
+        self
+    }
+}
+
+impl <C> Default for Caps<C> {
+    fn default() -> Self {
+        Caps::new()
+    }
+}
+
+/// Whether `q` has any `Tag`s, i.e. whether it's worth tracking captures.
+pub fn has_tags<D,C>(q: &QRE<D,C>) -> bool {
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => false,
+        Tag{..} => true,
+        Choice{v} => v.iter().any(has_tags),
+        Split{f, g, ..} | Combine{f, g, ..} | TryCombine{f, g, ..} => has_tags(f) || has_tags(g),
+        Iter{init, body, ..} => has_tags(init) || has_tags(body),
+        App{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => has_tags(f),
+    }
+}
+
+fn pairs<C: Clone, F>(xs: SmallVec<(C, Caps<C>)>, ys: SmallVec<(C, Caps<C>)>, op: F) -> SmallVec<(C, Caps<C>)>
+    where F: Fn(C, C) -> Result<C, fallible::Error> {
+    let mut acc = SmallVec::new();
+    for (x, cx) in &xs[..] {
+        for (y, cy) in &ys[..] {
+            match op(x.clone(), y.clone()) {
+                Ok(c) => acc.push((c, cx.clone().join(cy.clone()))),
+                Err(e) => fallible::raise(e)
+            }
+        }
+    };
+    acc
+}
+
+/// `epsilon`, with each value's captures alongside it.
+pub fn epsilon_caps<D,C>(q: &QRE<D,C>) -> SmallVec<(C, Caps<C>)> where C: Clone {
+    match q {
+        Bot | Sat{..} | TrySat{..} => SmallVec::new(),
+        Eps{c} => SmallVec::One([(c.clone(), Caps::new())]),
+        Choice{v} => {
+            let mut vnew = SmallVec::new();
+            for q in v {
+                vnew.extend(epsilon_caps(q))
+            };
+            vnew
+        },
+        Split{f, g, op} | Combine{f, g, op} => pairs(epsilon_caps(f), epsilon_caps(g), |x, y| Ok(op(x, y))),
+        TryCombine{f, g, op} => pairs(epsilon_caps(f), epsilon_caps(g), *op),
+        Iter{init, ..} => epsilon_caps(init),
+        App{f, op} => epsilon_caps(f).into_iter().filter_map(|(x, cx)| Some((op.apply(x)?, cx))).collect(),
+        Tag{name, f} => epsilon_caps(f).into_iter()
+            .map(|(x, cx)| (x.clone(), cx.join(Caps::leaf(name, x))))
+            .collect(),
+        Cap{f, caps, later} => epsilon_caps(f).into_iter()
+            .map(|(x, cx)| {
+                let cs = if *later { cx.join(caps.clone()) } else { caps.clone().join(cx) };
+                (x, cs)
+            })
+            .collect(),
+        Trigger{body, ..} => epsilon_caps(body),
+    }
+}
+
+impl <D,C> Solve<D,C> where C: Clone + Debug + Send + Sync {
+    /// The value of each `Tag`ged sub-expression in the match that
+    /// `output` is the value of.
+    pub fn captures(&self) -> Result<BTreeMap<&'static str, C>, String> {
+        if let Some((ref old, _)) = self.retiring {
+            return old.captures()
+        };
+        let mut cs = Vec::new();
+        for q in &self.state[..] {
+            cs.extend(epsilon_caps(q))
+        };
+        if cs.len() == 1 {
+            Ok(cs.pop().unwrap().1.0.into_iter().collect())
+        }
+        else {
+            Err("undefined".to_string())
+        }
+    }
+}
@@ -0,0 +1,183 @@
+/// What happened to a row.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Op {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Text(String),
+}
+
+impl Value {
+    /// Numbers, and text that parses as one (e.g. `numeric` columns).
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Num(x) => Some(*x),
+            Value::Text(s) => s.parse().ok(),
+            _ => None
+        }
+    }
+}
+
+/// One row change from Postgres logical decoding.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Change {
+    /// Schema-qualified, e.g. `public.orders`.
+    pub table: String,
+    pub op: Op,
+    /// The new row for inserts and updates; the key of the deleted row
+    /// for deletes.
+    pub columns: Vec<(String, Value)>,
+    /// For updates that change the key (or with `REPLICA IDENTITY FULL`),
+    /// the old key.
+    pub old_key: Vec<(String, Value)>,
+}
+
+impl Change {
+    pub fn get(&self, column: &str) -> Option<&Value> {
+        self.columns.iter().find(|(c, _)| c == column).map(|(_, v)| v)
+    }
+}
+
+/// Parses a line of the `test_decoding` output plugin, as printed by
+/// `pg_recvlogical --plugin=test_decoding -f -` or returned by
+/// `pg_logical_slot_get_changes`. Transaction markers (`BEGIN`, `COMMIT`)
+/// give `None`; other lines that aren't row changes (`TRUNCATE`s, logical
+/// decoding messages) are errors. Speaking the replication protocol itself is left to
+/// `pg_recvlogical`: pipe its output in and read it with any line source.
+pub fn parse_test_decoding(line: &str) -> Result<Option<Change>, String> {
+    let line = line.trim_end();
+    if line.starts_with("BEGIN") || line.starts_with("COMMIT") {
+        return Ok(None)
+    };
+    let rest = line.strip_prefix("table ").ok_or_else(|| format!("not a change: {}", line))?;
+    let (table, rest) = rest.split_once(": ").ok_or("no table name")?;
+    let (op, rest) = rest.split_once(':').ok_or("no operation")?;
+    let op = match op {
+        "INSERT" => Op::Insert,
+        "UPDATE" => Op::Update,
+        "DELETE" => Op::Delete,
+        _ => return Err(format!("unknown operation {}", op))
+    };
+    let rest = rest.trim_start();
+    let (old_key, columns) = match rest.strip_prefix("old-key: ") {
+        Some(rest) => {
+            let i = rest.find(" new-tuple: ").ok_or("old-key without new-tuple")?;
+            (columns(&rest[..i])?, columns(&rest[i + " new-tuple: ".len()..])?)
+        },
+        None => (Vec::new(), columns(rest)?)
+    };
+    Ok(Some(Change { table: table.to_string(), op, columns, old_key }))
+}
+
+/// `name[type]:value ...`, where text values are quoted with `'` (doubled
+/// inside), and a tuple with nothing to show is `(no-tuple-data)`.
+fn columns(s: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut out = Vec::new();
+    let mut rest = s.trim();
+    if rest == "(no-tuple-data)" {
+        return Ok(out)
+    };
+    while !rest.is_empty() {
+        let open = rest.find('[').ok_or_else(|| format!("bad column: {}", rest))?;
+        let name = rest[..open].to_string();
+        // Type names can contain brackets of their own (`integer[]`).
+        let close = rest[open..].find("]:").ok_or_else(|| format!("bad column: {}", rest))? + open;
+        let ty = &rest[open + 1..close];
+        rest = &rest[close + 2..];
+        let (value, tail) = if let Some(quoted) = rest.strip_prefix('\'') {
+            let mut text = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '\'')) => {
+                        if quoted[i + 1..].starts_with('\'') {
+                            text.push('\'');
+                            chars.next();
+                        }
+                        else {
+                            break i + 1
+                        }
+                    },
+                    Some((_, c)) => text.push(c),
+                    None => return Err(format!("unterminated value for {}", name))
+                }
+            };
+            (Value::Text(text), &quoted[end..])
+        }
+        else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let raw = &rest[..end];
+            let v = match raw {
+                "null" => Value::Null,
+                "true" if ty == "boolean" => Value::Bool(true),
+                "false" if ty == "boolean" => Value::Bool(false),
+                _ => raw.parse().map(Value::Num).unwrap_or_else(|_| Value::Text(raw.to_string()))
+            };
+            (v, &rest[end..])
+        };
+        out.push((name, value));
+        rest = tail.trim_start()
+    };
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::Value::*;
+
+    fn cols(cs: &[(&str, Value)]) -> Vec<(String, Value)> {
+        cs.iter().map(|(c, v)| (c.to_string(), v.clone())).collect()
+    }
+
+    fn change(table: &str, op: Op, columns: Vec<(String, Value)>, old_key: Vec<(String, Value)>) -> Option<Change> {
+        Some(Change { table: table.to_string(), op, columns, old_key })
+    }
+
+    // Lines as `pg_recvlogical --plugin=test_decoding` prints them.
+    #[test]
+    fn parses_test_decoding() {
+        let cases = [
+            ("BEGIN 690", None),
+            ("COMMIT 690", None),
+            ("table public.data: INSERT: id[integer]:1 data[text]:'1'",
+             change("public.data", Op::Insert, cols(&[("id", Num(1.0)), ("data", Text("1".into()))]), vec![])),
+            ("table public.data: DELETE: id[integer]:2",
+             change("public.data", Op::Delete, cols(&[("id", Num(2.0))]), vec![])),
+            ("table public.data: UPDATE: id[integer]:3 data[text]:'5'",
+             change("public.data", Op::Update, cols(&[("id", Num(3.0)), ("data", Text("5".into()))]), vec![])),
+            ("table public.table_with_pkey: UPDATE: old-key: id[integer]:1 new-tuple: id[integer]:-20 data[integer]:1",
+             change("public.table_with_pkey", Op::Update, cols(&[("id", Num(-20.0)), ("data", Num(1.0))]),
+                    cols(&[("id", Num(1.0))]))),
+            ("table public.table_without_key: DELETE: (no-tuple-data)",
+             change("public.table_without_key", Op::Delete, vec![], vec![])),
+            ("table public.orders: INSERT: id[bigint]:7 amount[numeric]:12.50 paid[boolean]:false note[text]:null \
+              tags[text[]]:'{a,b}' at[timestamp with time zone]:'2024-01-01 10:00:00+00' who[text]:'O''Brien'",
+             change("public.orders", Op::Insert, cols(&[
+                 ("id", Num(7.0)), ("amount", Num(12.5)), ("paid", Bool(false)), ("note", Null),
+                 ("tags", Text("{a,b}".into())), ("at", Text("2024-01-01 10:00:00+00".into())),
+                 ("who", Text("O'Brien".into()))]), vec![])),
+            ("table public.toasttable: UPDATE: id[integer]:1 toasted_col1[text]:unchanged-toast-datum",
+             change("public.toasttable", Op::Update,
+                    cols(&[("id", Num(1.0)), ("toasted_col1", Text("unchanged-toast-datum".into()))]), vec![])),
+        ];
+        for (line, want) in cases {
+            assert_eq!(parse_test_decoding(line).unwrap(), want, "{}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        for line in ["table public.data: TRUNCATE: (no-flags)", "table public.data: INSERT: data[text]:'open",
+                     "message: transactional: 1 prefix: p, sz: 1 content:x"] {
+            assert!(parse_test_decoding(line).is_err(), "{}", line)
+        }
+    }
+}
@@ -0,0 +1,842 @@
+use std::collections::{HashMap, VecDeque};
+use std::ptr;
+use std::sync::Arc;
+
+use super::{Action, AppOp, Caps, Solve, QRE};
+use super::QRE::*;
+use canon;
+use fallible;
+use ops::{Count, Delta, Max, Min, Rate, Regression, Sum, Summary, Timed};
+
+const MAGIC: &[u8; 4] = b"QREC";
+
+/// Version of the checkpoint layout itself. Bump it when the layout
+/// changes, and keep `read_state` able to read the older ones.
+/// 1: the query was checked only by how many `Sat`s, ops and `App` ops
+///    it has.
+/// 2: by its `fingerprint`.
+const FORMAT_VERSION: u32 = 2;
+
+/// Cost types that can be written into a checkpoint. Bump `VERSION`
+/// whenever `write`'s encoding changes, and implement `migrate` to read
+/// the values older builds wrote.
+pub trait Persist: Sized {
+    const VERSION: u32 = 1;
+
+    fn write(&self, w: &mut Vec<u8>);
+    fn read(r: &mut Reader) -> Result<Self, String>;
+
+    /// Reads a value written when this type was at `version` (which is
+    /// older than `VERSION`).
+    fn migrate(version: u32, _r: &mut Reader) -> Result<Self, String> {
+        Err(format!("no migration from cost type version {} to {}", version, Self::VERSION))
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl <'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("truncated checkpoint".to_string())
+        };
+        let (x, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(x)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+}
+
+pub fn write_u32(w: &mut Vec<u8>, x: u32) {
+    w.extend_from_slice(&x.to_le_bytes())
+}
+
+pub fn write_u64(w: &mut Vec<u8>, x: u64) {
+    w.extend_from_slice(&x.to_le_bytes())
+}
+
+impl Persist for f64 {
+    fn write(&self, w: &mut Vec<u8>) { write_u64(w, self.to_bits()) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(f64::from_bits(r.u64()?)) }
+}
+
+impl Persist for u64 {
+    fn write(&self, w: &mut Vec<u8>) { write_u64(w, *self) }
+    fn read(r: &mut Reader) -> Result<Self, String> { r.u64() }
+}
+
+impl Persist for i64 {
+    fn write(&self, w: &mut Vec<u8>) { write_u64(w, *self as u64) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(r.u64()? as i64) }
+}
+
+impl Persist for usize {
+    fn write(&self, w: &mut Vec<u8>) { write_u64(w, *self as u64) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(r.u64()? as usize) }
+}
+
+impl Persist for bool {
+    fn write(&self, w: &mut Vec<u8>) { w.push(*self as u8) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(r.u8()? != 0) }
+}
+
+impl Persist for String {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_u64(w, self.len() as u64);
+        w.extend_from_slice(self.as_bytes())
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let n = r.u64()? as usize;
+        String::from_utf8(r.take(n)?.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+impl <T: Persist> Persist for Vec<T> {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_u64(w, self.len() as u64);
+        for x in self {
+            x.write(w)
+        }
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let n = r.u64()?;
+        (0..n).map(|_| T::read(r)).collect()
+    }
+}
+
+impl <T: Persist> Persist for VecDeque<T> {
+    fn write(&self, w: &mut Vec<u8>) {
+        write_u64(w, self.len() as u64);
+        for x in self {
+            x.write(w)
+        }
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let n = r.u64()?;
+        (0..n).map(|_| T::read(r)).collect()
+    }
+}
+
+impl <T: Persist> Persist for Option<T> {
+    fn write(&self, w: &mut Vec<u8>) {
+        match self {
+            None => w.push(0),
+            Some(x) => { w.push(1); x.write(w) }
+        }
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        match r.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(T::read(r)?))
+        }
+    }
+}
+
+impl <A: Persist, B: Persist> Persist for (A, B) {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.0.write(w);
+        self.1.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let a = A::read(r)?;
+        Ok((a, B::read(r)?))
+    }
+}
+
+macro_rules! tuple_persist {
+    ($(($($t:ident $i:tt),*))*) => {$(
+        impl <$($t: Persist),*> Persist for ($($t,)*) {
+            fn write(&self, w: &mut Vec<u8>) {
+                $(self.$i.write(w);)*
+            }
+            fn read(r: &mut Reader) -> Result<Self, String> {
+                Ok(($($t::read(r)?,)*))
+            }
+        }
+    )*}
+}
+
+tuple_persist!((A 0, B 1, C 2) (A 0, B 1, C 2, D 3) (A 0, B 1, C 2, D 3, E 4) (A 0, B 1, C 2, D 3, E 4, F 5));
+
+impl <T: Persist> Persist for Sum<T> {
+    fn write(&self, w: &mut Vec<u8>) { self.0.write(w) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(Sum(T::read(r)?)) }
+}
+
+impl Persist for Count {
+    fn write(&self, w: &mut Vec<u8>) { self.0.write(w) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(Count(u64::read(r)?)) }
+}
+
+impl <T: Persist> Persist for Max<T> {
+    fn write(&self, w: &mut Vec<u8>) { self.0.write(w) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(Max(T::read(r)?)) }
+}
+
+impl <T: Persist> Persist for Min<T> {
+    fn write(&self, w: &mut Vec<u8>) { self.0.write(w) }
+    fn read(r: &mut Reader) -> Result<Self, String> { Ok(Min(T::read(r)?)) }
+}
+
+impl <T: Persist> Persist for Timed<T> {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.time.write(w);
+        self.value.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let time = f64::read(r)?;
+        Ok(Timed { time, value: T::read(r)? })
+    }
+}
+
+impl <T: Persist> Persist for Delta<T> {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.last.write(w);
+        self.change.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let last = Option::read(r)?;
+        Ok(Delta { last, change: Option::read(r)? })
+    }
+}
+
+impl Persist for Rate {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.last.write(w);
+        self.per_second.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let last = Option::read(r)?;
+        Ok(Rate { last, per_second: Option::read(r)? })
+    }
+}
+
+impl Persist for Regression {
+    fn write(&self, w: &mut Vec<u8>) {
+        for x in [self.n, self.sx, self.sy, self.sxy, self.sxx, self.syy] {
+            x.write(w)
+        }
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let mut x = [0.0; 6];
+        for x in x.iter_mut() {
+            *x = f64::read(r)?
+        };
+        let [n, sx, sy, sxy, sxx, syy] = x;
+        Ok(Regression { n, sx, sy, sxy, sxx, syy })
+    }
+}
+
+impl Persist for Summary {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.count.write(w);
+        self.sum.write(w);
+        self.min.write(w);
+        self.max.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let (count, sum) = (u64::read(r)?, f64::read(r)?);
+        let min = f64::read(r)?;
+        Ok(Summary { count, sum, min, max: f64::read(r)? })
+    }
+}
+
+type SatFns<D,C> = (fn(&D) -> bool, fn(&D) -> C);
+type TrySatFns<D,C> = (fn(&D) -> bool, fn(&D) -> Result<C, fallible::Error>);
+type TryOp2<C> = fn(C,C) -> Result<C, fallible::Error>;
+type TryApp<C> = Arc<dyn Fn(C) -> Result<C, fallible::Error> + Send + Sync>;
+
+/// Every function a query contains. Residuals only ever hold functions
+/// taken from their query, so a checkpoint can name them by index here and
+/// `restore` can look them up again in the same query. Functions are
+/// compared by address, which is only as good as it sounds because every
+/// pointer looked up here was copied out of the query the table was built
+/// from, so it's one of the table's own values: the same function can't
+/// turn up at an address the table doesn't have. Two functions the compiler
+/// merged share an index, which is harmless, since they're the same code.
+struct Ops<D,C> {
+    sats: Vec<SatFns<D,C>>,
+    ops: Vec<fn(C,C) -> C>,
+    apps: Vec<Arc<dyn Fn(C) -> C + Send + Sync>>,
+    names: Vec<&'static str>,
+    actions: Vec<Action<C>>,
+    try_sats: Vec<TrySatFns<D,C>>,
+    try_ops: Vec<TryOp2<C>>,
+    try_apps: Vec<TryApp<C>>,
+}
+
+impl <D,C> Ops<D,C> {
+    fn of(q: &QRE<D,C>) -> Self {
+        let mut t = Ops {
+            sats: Vec::new(), ops: Vec::new(), apps: Vec::new(), names: Vec::new(), actions: Vec::new(),
+            try_sats: Vec::new(), try_ops: Vec::new(), try_apps: Vec::new()
+        };
+        t.collect(q);
+        t
+    }
+
+    fn collect(&mut self, q: &QRE<D,C>) {
+        match q {
+            Bot | Eps{..} => (),
+            Sat{phi, op} => {
+                if self.sat(*phi, *op).is_none() {
+                    self.sats.push((*phi, *op))
+                }
+            },
+            Choice{v} => {
+                for q in v {
+                    self.collect(q)
+                }
+            },
+            Split{f, g, op} | Combine{f, g, op} => {
+                self.op2(*op);
+                self.collect(f);
+                self.collect(g)
+            },
+            Iter{init, body, op} => {
+                self.op2(*op);
+                self.collect(init);
+                self.collect(body)
+            },
+            App{f, op} => {
+                self.app_op(op);
+                self.collect(f)
+            },
+            Tag{name, f} => {
+                if self.name(name).is_none() {
+                    self.names.push(name)
+                };
+                self.collect(f)
+            },
+            Cap{f, ..} => self.collect(f),
+            Trigger{body, action} => {
+                if self.action(action).is_none() {
+                    self.actions.push(action.clone())
+                };
+                self.collect(body)
+            },
+            TrySat{phi, op} => {
+                if self.try_sat(*phi, *op).is_none() {
+                    self.try_sats.push((*phi, *op))
+                }
+            },
+            TryCombine{f, g, op} => {
+                if self.try_op(*op).is_none() {
+                    self.try_ops.push(*op)
+                };
+                self.collect(f);
+                self.collect(g)
+            },
+        }
+    }
+
+    fn app_op(&mut self, op: &AppOp<C>) {
+        match op {
+            AppOp::Fn(op) => {
+                if self.app(op).is_none() {
+                    self.apps.push(op.clone())
+                }
+            },
+            AppOp::Left(op, _) | AppOp::Right(op, _) => self.op2(*op),
+            AppOp::Then(first, then) => {
+                self.app_op(first);
+                self.app_op(then)
+            },
+            AppOp::Try(op) => {
+                if self.try_app(op).is_none() {
+                    self.try_apps.push(op.clone())
+                }
+            }
+        }
+    }
+
+    fn op2(&mut self, op: fn(C,C) -> C) {
+        if self.op(op).is_none() {
+            self.ops.push(op)
+        }
+    }
+
+    fn sat(&self, phi: fn(&D) -> bool, op: fn(&D) -> C) -> Option<u32> {
+        self.sats.iter()
+            .position(|(p, o)| ptr::fn_addr_eq(*p, phi) && ptr::fn_addr_eq(*o, op))
+            .map(|i| i as u32)
+    }
+
+    fn op(&self, op: fn(C,C) -> C) -> Option<u32> {
+        self.ops.iter().position(|o| ptr::fn_addr_eq(*o, op)).map(|i| i as u32)
+    }
+
+    fn action(&self, action: &Action<C>) -> Option<u32> {
+        self.actions.iter().position(|a| Arc::ptr_eq(a, action)).map(|i| i as u32)
+    }
+
+    fn name(&self, name: &str) -> Option<u32> {
+        self.names.iter().position(|n| *n == name).map(|i| i as u32)
+    }
+
+    fn app(&self, op: &Arc<dyn Fn(C) -> C + Send + Sync>) -> Option<u32> {
+        self.apps.iter().position(|o| Arc::ptr_eq(o, op)).map(|i| i as u32)
+    }
+
+    fn try_sat(&self, phi: fn(&D) -> bool, op: fn(&D) -> Result<C, fallible::Error>) -> Option<u32> {
+        self.try_sats.iter()
+            .position(|(p, o)| ptr::fn_addr_eq(*p, phi) && ptr::fn_addr_eq(*o, op))
+            .map(|i| i as u32)
+    }
+
+    fn try_op(&self, op: TryOp2<C>) -> Option<u32> {
+        self.try_ops.iter().position(|o| ptr::fn_addr_eq(*o, op)).map(|i| i as u32)
+    }
+
+    fn try_app(&self, op: &TryApp<C>) -> Option<u32> {
+        self.try_apps.iter().position(|o| Arc::ptr_eq(o, op)).map(|i| i as u32)
+    }
+}
+
+/// A hash of `q`'s shape and of where in `ops` each of its nodes finds
+/// its functions and names, so a checkpoint can tell the query it was
+/// written for from another one with as many functions: a different
+/// query can only match by having the same structure, with each function
+/// used in the same places. FNV-1a, since it has to come out the same in
+/// every build.
+fn fingerprint<D,C>(q: &QRE<D,C>, ops: &Ops<D,C>) -> u64 {
+    let mut h = Fnv(0xcbf29ce484222325);
+    for n in [ops.sats.len(), ops.ops.len(), ops.apps.len(), ops.names.len(), ops.actions.len(),
+              ops.try_sats.len(), ops.try_ops.len(), ops.try_apps.len()] {
+        h.u32(n as u32)
+    };
+    shape(q, ops, &mut h);
+    h.0
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn bytes(&mut self, bs: &[u8]) {
+        for b in bs {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3)
+        }
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes(&x.to_le_bytes())
+    }
+
+    fn index(&mut self, i: Option<u32>) {
+        self.u32(i.map_or(u32::MAX, |i| i))
+    }
+}
+
+fn shape<D,C>(q: &QRE<D,C>, ops: &Ops<D,C>, h: &mut Fnv) {
+    match q {
+        Bot => h.bytes(&[BOT]),
+        Eps{..} => h.bytes(&[EPS]),
+        Sat{phi, op} => {
+            h.bytes(&[SAT]);
+            h.index(ops.sat(*phi, *op))
+        },
+        Choice{v} => {
+            h.bytes(&[CHOICE]);
+            h.u32(v.len() as u32);
+            for q in v {
+                shape(q, ops, h)
+            }
+        },
+        Split{f, g, op} | Iter{init: f, body: g, op} | Combine{f, g, op} => {
+            h.bytes(&[match q { Split{..} => SPLIT, Iter{..} => ITER, _ => COMBINE }]);
+            h.index(ops.op(*op));
+            shape(f, ops, h);
+            shape(g, ops, h)
+        },
+        App{f, op} => {
+            h.bytes(&[APP]);
+            app_shape(op, ops, h);
+            shape(f, ops, h)
+        },
+        Tag{name, f} => {
+            h.bytes(&[TAG]);
+            h.index(ops.name(name));
+            h.bytes(name.as_bytes());
+            shape(f, ops, h)
+        },
+        Cap{f, ..} => {
+            h.bytes(&[CAP]);
+            shape(f, ops, h)
+        },
+        Trigger{body, action} => {
+            h.bytes(&[TRIGGER]);
+            h.index(ops.action(action));
+            shape(body, ops, h)
+        },
+        TrySat{phi, op} => {
+            h.bytes(&[TRY_SAT]);
+            h.index(ops.try_sat(*phi, *op))
+        },
+        TryCombine{f, g, op} => {
+            h.bytes(&[TRY_COMBINE]);
+            h.index(ops.try_op(*op));
+            shape(f, ops, h);
+            shape(g, ops, h)
+        },
+    }
+}
+
+fn app_shape<D,C>(op: &AppOp<C>, ops: &Ops<D,C>, h: &mut Fnv) {
+    match op {
+        AppOp::Fn(op) => {
+            h.bytes(&[APP_FN]);
+            h.index(ops.app(op))
+        },
+        AppOp::Left(op, _) => {
+            h.bytes(&[APP_LEFT]);
+            h.index(ops.op(*op))
+        },
+        AppOp::Right(op, _) => {
+            h.bytes(&[APP_RIGHT]);
+            h.index(ops.op(*op))
+        },
+        AppOp::Then(first, then) => {
+            h.bytes(&[APP_THEN]);
+            app_shape(first, ops, h);
+            app_shape(then, ops, h)
+        },
+        AppOp::Try(op) => {
+            h.bytes(&[APP_TRY]);
+            h.index(ops.try_app(op))
+        },
+    }
+}
+
+fn missing() -> String {
+    "residual refers to a function that isn't in the solver's query".to_string()
+}
+
+/// Node tags.
+const BOT: u8 = 0;
+const EPS: u8 = 1;
+const SAT: u8 = 2;
+const CHOICE: u8 = 3;
+const SPLIT: u8 = 4;
+const ITER: u8 = 5;
+const APP: u8 = 6;
+const COMBINE: u8 = 7;
+const TAG: u8 = 8;
+const CAP: u8 = 9;
+const TRIGGER: u8 = 10;
+const TRY_SAT: u8 = 11;
+const TRY_COMBINE: u8 = 12;
+
+const APP_FN: u8 = 0;
+const APP_LEFT: u8 = 1;
+const APP_RIGHT: u8 = 2;
+const APP_THEN: u8 = 3;
+const APP_TRY: u8 = 4;
+
+/// Writes residual DAGs as a table of nodes, children before parents, each
+/// node written once however many residuals share it.
+struct Writer<'a, D: 'a, C: 'a> {
+    ops: &'a Ops<D,C>,
+    ids: HashMap<*const QRE<D,C>, u32>,
+    nodes: u32,
+    out: Vec<u8>,
+}
+
+impl <'a, D, C: Persist> Writer<'a, D, C> {
+    fn node(&mut self, q: &QRE<D,C>) -> Result<u32, String> {
+        if let Some(id) = self.ids.get(&(q as *const QRE<D,C>)) {
+            return Ok(*id)
+        };
+        // Children first, so they already have ids.
+        let kids: Vec<u32> = match q {
+            Bot | Eps{..} | Sat{..} | TrySat{..} => vec![],
+            Choice{v} => v.iter().map(|q| self.node(q)).collect::<Result<_, _>>()?,
+            Split{f, g, ..} | Combine{f, g, ..} | TryCombine{f, g, ..} => vec![self.node(f)?, self.node(g)?],
+            Iter{init, body, ..} => vec![self.node(init)?, self.node(body)?],
+            App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => vec![self.node(f)?],
+        };
+        let w = &mut self.out;
+        match q {
+            Bot => w.push(BOT),
+            Eps{c} => { w.push(EPS); c.write(w) },
+            Sat{phi, op} => {
+                w.push(SAT);
+                write_u32(w, self.ops.sat(*phi, *op).ok_or_else(missing)?)
+            },
+            Choice{..} => {
+                w.push(CHOICE);
+                write_u32(w, kids.len() as u32);
+                for k in &kids {
+                    write_u32(w, *k)
+                }
+            },
+            Split{op, ..} | Iter{op, ..} | Combine{op, ..} => {
+                w.push(match q { Split{..} => SPLIT, Iter{..} => ITER, _ => COMBINE });
+                write_u32(w, kids[0]);
+                write_u32(w, kids[1]);
+                write_u32(w, self.ops.op(*op).ok_or_else(missing)?)
+            },
+            App{op, ..} => {
+                w.push(APP);
+                write_u32(w, kids[0]);
+                write_app(self.ops, op, w)?
+            },
+            Tag{name, ..} => {
+                w.push(TAG);
+                write_u32(w, kids[0]);
+                write_u32(w, self.ops.name(name).ok_or_else(missing)?)
+            },
+            Cap{caps, later, ..} => {
+                w.push(CAP);
+                write_u32(w, kids[0]);
+                w.push(*later as u8);
+                write_u32(w, caps.len() as u32);
+                for (name, c) in caps.iter() {
+                    write_u32(w, self.ops.name(name).ok_or_else(missing)?);
+                    c.write(w)
+                }
+            },
+            Trigger{action, ..} => {
+                w.push(TRIGGER);
+                write_u32(w, kids[0]);
+                write_u32(w, self.ops.action(action).ok_or_else(missing)?)
+            },
+            TrySat{phi, op} => {
+                w.push(TRY_SAT);
+                write_u32(w, self.ops.try_sat(*phi, *op).ok_or_else(missing)?)
+            },
+            TryCombine{op, ..} => {
+                w.push(TRY_COMBINE);
+                write_u32(w, kids[0]);
+                write_u32(w, kids[1]);
+                write_u32(w, self.ops.try_op(*op).ok_or_else(missing)?)
+            },
+        };
+        let id = self.nodes;
+        self.nodes += 1;
+        self.ids.insert(q as *const QRE<D,C>, id);
+        Ok(id)
+    }
+}
+
+fn write_app<D,C: Persist>(ops: &Ops<D,C>, op: &AppOp<C>, w: &mut Vec<u8>) -> Result<(), String> {
+    match op {
+        AppOp::Fn(op) => {
+            w.push(APP_FN);
+            write_u32(w, ops.app(op).ok_or_else(missing)?)
+        },
+        AppOp::Left(op, c) => {
+            w.push(APP_LEFT);
+            write_u32(w, ops.op(*op).ok_or_else(missing)?);
+            c.write(w)
+        },
+        AppOp::Right(op, c) => {
+            w.push(APP_RIGHT);
+            write_u32(w, ops.op(*op).ok_or_else(missing)?);
+            c.write(w)
+        },
+        AppOp::Then(first, then) => {
+            w.push(APP_THEN);
+            write_app(ops, first, w)?;
+            write_app(ops, then, w)?
+        },
+        AppOp::Try(op) => {
+            w.push(APP_TRY);
+            write_u32(w, ops.try_app(op).ok_or_else(missing)?)
+        }
+    };
+    Ok(())
+}
+
+impl <D,C> Solve<D,C> where C: Persist + Clone + std::fmt::Debug + Send + Sync {
+    /// Serializes the solver's state. Restoring it needs the same query
+    /// (see `restore`), since functions are written as references into it.
+    /// Fails for a solver an op failure aborted or a panic poisoned, whose
+    /// residuals no longer say what its output is.
+    pub fn checkpoint(&self) -> Result<Vec<u8>, String> {
+        if self.retiring.is_some() {
+            return Err("can't checkpoint in the middle of a parallel migration".to_string())
+        };
+        if self.errors.aborted || self.poisoned {
+            return Err("can't checkpoint a solver that has stopped".to_string())
+        };
+        let ops = Ops::of(&self.query);
+        let mut w = Writer { ops: &ops, ids: HashMap::new(), nodes: 0, out: Vec::new() };
+        let roots = self.state.iter().map(|q| w.node(q)).collect::<Result<Vec<_>, _>>()?;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        write_u32(&mut out, FORMAT_VERSION);
+        write_u32(&mut out, C::VERSION);
+        write_u64(&mut out, fingerprint(&self.query, &ops));
+        out.push(self.reverse as u8);
+        write_u64(&mut out, self.max_workingset);
+        write_u32(&mut out, w.nodes);
+        out.append(&mut w.out);
+        write_u32(&mut out, roots.len() as u32);
+        for r in roots {
+            write_u32(&mut out, r)
+        };
+        Ok(out)
+    }
+
+    /// Rebuilds a solver for `q` from a checkpoint written by a solver
+    /// running the same query, possibly by an older build.
+    pub fn restore(q: QRE<D,C>, bytes: &[u8]) -> Result<Self, String> {
+        let mut r = Reader::new(bytes);
+        if r.take(4)? != &MAGIC[..] {
+            return Err("not a checkpoint".to_string())
+        };
+        let format = r.u32()?;
+        match format {
+            1 | FORMAT_VERSION => read_state(q, format, &mut r),
+            _ => Err(format!("unknown checkpoint format version {}", format))
+        }
+    }
+}
+
+fn read_cost<C: Persist>(version: u32, r: &mut Reader) -> Result<C, String> {
+    if version == C::VERSION { C::read(r) } else { C::migrate(version, r) }
+}
+
+fn read_app<D,C: Persist>(ops: &Ops<D,C>, version: u32, r: &mut Reader) -> Result<AppOp<C>, String> {
+    let op = |i: u32| -> Result<fn(C,C) -> C, String> {
+        ops.ops.get(i as usize).cloned().ok_or_else(missing)
+    };
+    Ok(match r.u8()? {
+        APP_FN => AppOp::Fn(ops.apps.get(r.u32()? as usize).cloned().ok_or_else(missing)?),
+        APP_LEFT => { let op = op(r.u32()?)?; AppOp::Left(op, read_cost(version, r)?) },
+        APP_RIGHT => { let op = op(r.u32()?)?; AppOp::Right(op, read_cost(version, r)?) },
+        APP_THEN => {
+            let first = read_app(ops, version, r)?;
+            AppOp::Then(Box::new(first), Box::new(read_app(ops, version, r)?))
+        },
+        APP_TRY => AppOp::Try(ops.try_apps.get(r.u32()? as usize).cloned().ok_or_else(missing)?),
+        t => return Err(format!("bad App tag {}", t))
+    })
+}
+
+fn read_state<D,C>(q: QRE<D,C>, format: u32, r: &mut Reader) -> Result<Solve<D,C>, String>
+    where C: Persist + Clone + std::fmt::Debug + Send + Sync {
+    let version = r.u32()?;
+    if version > C::VERSION {
+        return Err(format!("cost type version {} is newer than this build's {}", version, C::VERSION))
+    };
+    // The solver that wrote it ran the query as `Solve::new` prunes it.
+    let q = canon::prune(q);
+    let ops = Ops::of(&q);
+    let same = match format {
+        1 => (r.u32()? as usize, r.u32()? as usize, r.u32()? as usize) == (ops.sats.len(), ops.ops.len(), ops.apps.len()),
+        _ => r.u64()? == fingerprint(&q, &ops)
+    };
+    if !same {
+        return Err("checkpoint was written for a different query".to_string())
+    };
+    let reverse = r.u8()? != 0;
+    let max_workingset = r.u64()?;
+
+    let n = r.u32()?;
+    // Counts come from the bytes, so nothing is allocated ahead of them.
+    let mut nodes: Vec<Arc<QRE<D,C>>> = Vec::new();
+    let node = |nodes: &Vec<Arc<QRE<D,C>>>, id: u32| -> Result<Arc<QRE<D,C>>, String> {
+        nodes.get(id as usize).cloned().ok_or_else(|| "bad node reference".to_string())
+    };
+    let op = |i: u32| -> Result<fn(C,C) -> C, String> {
+        ops.ops.get(i as usize).cloned().ok_or_else(missing)
+    };
+    let name = |i: u32| -> Result<&'static str, String> {
+        ops.names.get(i as usize).cloned().ok_or_else(missing)
+    };
+    for _ in 0..n {
+        let q = match r.u8()? {
+            BOT => Bot,
+            EPS => Eps{c: read_cost(version, r)?},
+            SAT => {
+                let (phi, op) = *ops.sats.get(r.u32()? as usize).ok_or_else(missing)?;
+                Sat{phi, op}
+            },
+            CHOICE => {
+                let k = r.u32()?;
+                let mut v = Vec::new();
+                for _ in 0..k {
+                    v.push((*node(&nodes, r.u32()?)?).clone())
+                };
+                Choice{v}
+            },
+            tag @ SPLIT | tag @ ITER | tag @ COMBINE => {
+                let f = node(&nodes, r.u32()?)?;
+                let g = node(&nodes, r.u32()?)?;
+                let op = op(r.u32()?)?;
+                match tag {
+                    SPLIT => Split{f, g, op},
+                    ITER => Iter{init: f, body: g, op},
+                    _ => Combine{f, g, op}
+                }
+            },
+            APP => {
+                let f = node(&nodes, r.u32()?)?;
+                App{f, op: read_app(&ops, version, r)?}
+            },
+            TAG => {
+                let f = node(&nodes, r.u32()?)?;
+                Tag{name: name(r.u32()?)?, f}
+            },
+            CAP => {
+                let f = node(&nodes, r.u32()?)?;
+                let later = r.u8()? != 0;
+                let mut caps = Caps::new();
+                for _ in 0..r.u32()? {
+                    let name = name(r.u32()?)?;
+                    caps.push(name, read_cost(version, r)?)
+                };
+                Cap{f, caps, later}
+            },
+            TRIGGER => {
+                let body = node(&nodes, r.u32()?)?;
+                Trigger{body, action: ops.actions.get(r.u32()? as usize).cloned().ok_or_else(missing)?}
+            },
+            TRY_SAT => {
+                let (phi, op) = *ops.try_sats.get(r.u32()? as usize).ok_or_else(missing)?;
+                TrySat{phi, op}
+            },
+            TRY_COMBINE => {
+                let f = node(&nodes, r.u32()?)?;
+                let g = node(&nodes, r.u32()?)?;
+                TryCombine{f, g, op: *ops.try_ops.get(r.u32()? as usize).ok_or_else(missing)?}
+            },
+            t => return Err(format!("bad node tag {}", t))
+        };
+        nodes.push(Arc::new(q))
+    };
+
+    let k = r.u32()?;
+    let mut state = Vec::new();
+    for _ in 0..k {
+        state.push((*node(&nodes, r.u32()?)?).clone())
+    };
+    let mut s = Solve::new(q);
+    s.state = Arc::new(state);
+    s.reverse = reverse;
+    s.max_workingset = max_workingset;
+    Ok(s)
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+
+use super::QRE;
+use super::QRE::*;
+#[cfg(feature = "symbolic")]
+use symbolic;
+
+/// Shortest and longest match of a query; `None` if it matches nothing,
+/// and no longest if matches can be arbitrarily long.
+pub type Lengths = Option<(u64, Option<u64>)>;
+
+pub fn lengths<D,C>(q: &QRE<D,C>) -> Lengths {
+    match q {
+        Bot => None,
+        Eps{..} => Some((0, Some(0))),
+        Sat{..} | TrySat{..} => Some((1, Some(1))),
+        Choice{v} => v.iter().filter_map(lengths).fold(None, |acc, (lo, hi)| match acc {
+            None => Some((lo, hi)),
+            Some((a, b)) => Some((a.min(lo), b.and_then(|b| hi.map(|h| b.max(h)))))
+        }),
+        Split{f, g, ..} => {
+            let ((a, b), (c, d)) = (lengths(f)?, lengths(g)?);
+            Some((a + c, b.and_then(|b| d.map(|d| b + d))))
+        },
+        Iter{init, body, ..} => {
+            let (a, b) = lengths(init)?;
+            match lengths(body) {
+                Some((_, Some(0))) | None => Some((a, b)),
+                Some(_) => Some((a, None))
+            }
+        },
+        App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => lengths(f),
+        Combine{f, g, ..} | TryCombine{f, g, ..} => {
+            let ((a, b), (c, d)) = (lengths(f)?, lengths(g)?);
+            let hi = match (b, d) { (Some(b), Some(d)) => Some(b.min(d)), (b, d) => b.or(d) };
+            if hi.is_some_and(|h| h < a.max(c)) { None } else { Some((a.max(c), hi)) }
+        },
+    }
+}
+
+/// How many different match lengths there can be, if that's bounded.
+fn width(l: Lengths) -> Option<u64> {
+    match l {
+        None => Some(0),
+        Some((lo, hi)) => hi.map(|hi| hi - lo + 1)
+    }
+}
+
+fn mul(x: Option<u64>, y: Option<u64>) -> Option<u64> {
+    x?.checked_mul(y?)
+}
+
+fn add(x: Option<u64>, y: Option<u64>) -> Option<u64> {
+    x?.checked_add(y?)
+}
+
+/// The most values `q` can produce on one stream (1 if it's unambiguous),
+/// if that's bounded.
+fn ambiguity<D,C>(q: &QRE<D,C>) -> Option<u64> {
+    match q {
+        Bot => Some(0),
+        Eps{..} | Sat{..} | TrySat{..} => Some(1),
+        // Branches no element can start together can't both match.
+        #[cfg(feature = "symbolic")]
+        Choice{v} if symbolic::disjoint_branches(v) => v.iter().map(ambiguity).try_fold(0u64, |acc, a| Some(acc.max(a?))),
+        Choice{v} => v.iter().map(ambiguity).try_fold(0u64, |acc, a| acc.checked_add(a?)),
+        Split{f, g, ..} => {
+            // One way to split per length f can take, up to the lengths g
+            // can take.
+            let points = match (width(lengths(f)), width(lengths(g))) {
+                (Some(x), Some(y)) => Some(x.min(y)),
+                (x, y) => x.or(y)
+            };
+            mul(points, mul(ambiguity(f), ambiguity(g)))
+        },
+        Iter{init, body, ..} => {
+            match lengths(body) {
+                None | Some((_, Some(0))) => ambiguity(init),
+                // Iterations of one fixed length, each unambiguous: the
+                // only freedom is where init stops.
+                Some((lo, Some(hi))) if lo == hi && ambiguity(body) == Some(1) => {
+                    mul(ambiguity(init), width(lengths(init)))
+                },
+                Some(_) => None
+            }
+        },
+        App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => ambiguity(f),
+        Combine{f, g, ..} | TryCombine{f, g, ..} => mul(ambiguity(f), ambiguity(g)),
+    }
+}
+
+/// The most residual nodes a residual of `q` can have alive at once,
+/// which is what an update derives, if that's bounded.
+fn residuals<D,C>(q: &QRE<D,C>) -> Option<u64> {
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => Some(1),
+        Choice{v} => v.iter().map(residuals).try_fold(0u64, |acc, r| acc.checked_add(r?)),
+        Split{f, g, ..} => {
+            // Every place f finished that g is still matching from holds
+            // a residual of g.
+            let places = match (width(lengths(f)), lengths(g).map(|(_, hi)| hi.map(|h| h + 1))) {
+                (Some(x), Some(Some(y))) => Some(x.min(y)),
+                (x, Some(y)) => x.or(y),
+                (_, None) => Some(0)
+            };
+            let done = mul(mul(places, ambiguity(f)), add(Some(1), residuals(g)));
+            add(add(Some(1), residuals(f)), done)
+        },
+        Iter{init, body, ..} => {
+            match lengths(body) {
+                None | Some((_, Some(0))) => add(Some(1), residuals(init)),
+                Some((lo, Some(hi))) if lo == hi && ambiguity(body) == Some(1) => {
+                    // One iteration in flight per way init could have
+                    // stopped.
+                    let flights = mul(ambiguity(init), width(lengths(init)));
+                    add(add(Some(1), residuals(init)), mul(flights, add(Some(1), residuals(body))))
+                },
+                Some(_) => None
+            }
+        },
+        App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => add(Some(1), residuals(f)),
+        Combine{f, g, ..} | TryCombine{f, g, ..} => add(Some(1), add(residuals(f), residuals(g))),
+    }
+}
+
+/// One combinator's entry in a `Complexity` report.
+#[derive(Clone,Debug)]
+pub struct Node {
+    /// Where it is in the query, e.g. `Iter.body/Choice[1]`.
+    pub path: String,
+    pub kind: &'static str,
+    /// The most residual nodes it can account for at once; `None` if that
+    /// grows with the stream.
+    pub residuals: Option<u64>,
+    /// The most values it can produce on one stream.
+    pub ambiguity: Option<u64>,
+}
+
+/// Worst-case cost of updating a query, read off its structure: how many
+/// residual nodes each combinator can keep alive, and so how many an
+/// update may have to derive. The bounds assume every predicate can be
+/// true on every element, so real streams usually do much better; what
+/// they're good for is telling a query whose state stays flat from one
+/// whose state can grow with the stream.
+#[derive(Clone,Debug)]
+pub struct Complexity {
+    /// Pre-order, the whole query first.
+    pub nodes: Vec<Node>,
+}
+
+impl Complexity {
+    /// Residual nodes derived per update, at most; `None` if unbounded.
+    pub fn per_update(&self) -> Option<u64> {
+        self.nodes[0].residuals
+    }
+}
+
+impl fmt::Display for Complexity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |x: Option<u64>| x.map_or("unbounded".to_string(), |x| x.to_string());
+        for n in &self.nodes {
+            writeln!(f, "{:<40} {:<8} residuals {:<10} ambiguity {}", n.path, n.kind, show(n.residuals), show(n.ambiguity))?
+        };
+        write!(f, "per update: {}", show(self.per_update()))
+    }
+}
+
+pub fn analyze<D,C>(q: &QRE<D,C>) -> Complexity {
+    let mut nodes = Vec::new();
+    each(q, &mut |path, q| {
+        nodes.push(Node { path: path.to_string(), kind: kind(q), residuals: residuals(q), ambiguity: ambiguity(q) })
+    });
+    Complexity { nodes }
+}
+
+/// Calls `f` on every node of `q` in pre-order, with its path as in
+/// `Node::path`.
+pub fn each<D,C,F>(q: &QRE<D,C>, f: &mut F) where F: FnMut(&str, &QRE<D,C>) {
+    walk(q, kind(q).to_string(), f)
+}
+
+/// The variant's name, as paths spell it.
+pub fn kind<D,C>(q: &QRE<D,C>) -> &'static str {
+    match q {
+        Bot => "Bot",
+        Eps{..} => "Eps",
+        Sat{..} => "Sat",
+        Choice{..} => "Choice",
+        Split{..} => "Split",
+        Iter{..} => "Iter",
+        App{..} => "App",
+        Combine{..} => "Combine",
+        Tag{..} => "Tag",
+        Cap{..} => "Cap",
+        Trigger{..} => "Trigger",
+        TrySat{..} => "TrySat",
+        TryCombine{..} => "TryCombine",
+    }
+}
+
+fn walk<D,C,F>(q: &QRE<D,C>, path: String, f: &mut F) where F: FnMut(&str, &QRE<D,C>) {
+    f(&path, q);
+    let child = |name: &str, q: &QRE<D,C>| format!("{}.{}/{}", path, name, kind(q));
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => (),
+        Choice{v} => {
+            for (i, q) in v.iter().enumerate() {
+                walk(q, format!("{}[{}]/{}", path, i, kind(q)), f)
+            }
+        },
+        Split{f: l, g: r, ..} | Combine{f: l, g: r, ..} | TryCombine{f: l, g: r, ..} => {
+            walk(l, child("f", l), f);
+            walk(r, child("g", r), f)
+        },
+        Iter{init, body, ..} => {
+            walk(init, child("init", init), f);
+            walk(body, child("body", body), f)
+        },
+        App{f: a, ..} | Tag{f: a, ..} | Cap{f: a, ..} => walk(a, child("f", a), f),
+        Trigger{body, ..} => walk(body, child("body", body), f),
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use super::{epsilon, single_element, AppOp, Pool, QRE};
+use super::QRE::*;
+use fallible;
+
+/// Whether every derivative of `q` is a single residual, as long as no two
+/// branches of a `Choice` match the same element. That holds when a
+/// `Split`'s first part and an `Iter`'s body (and init, unless it's just
+/// an `Eps`) always match exactly one element: a finished part then has
+/// nothing left to continue, so finishing it and carrying on never both
+/// survive. `Tag`s and `Trigger`s rule it out, since `deriv_one` may give
+/// up halfway through a derivative.
+pub fn deterministic<D,C>(q: &QRE<D,C>) -> bool {
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => true,
+        Choice{v} => v.iter().all(|q| single_element(q) && deterministic(q)),
+        Split{f, g, ..} => single_element(f) && deterministic(f) && deterministic(g),
+        Iter{init, body, ..} => {
+            (matches!(**init, Eps{..}) || single_element(init)) && deterministic(init)
+                && single_element(body) && deterministic(body)
+        },
+        App{f, ..} => deterministic(f),
+        Combine{f, g, ..} | TryCombine{f, g, ..} => deterministic(f) && deterministic(g),
+        Tag{..} | Cap{..} | Trigger{..} => false,
+    }
+}
+
+/// The derivative of `q` by `d` as a single residual (`Bot` if it's
+/// dead), for a `q` that's `deterministic`. Skips the continuations that
+/// `deriv` would build only for `canon` to throw away. `None` if a
+/// `Choice` turned out to be ambiguous, in which case `deriv` has to do.
+pub fn deriv_one<D,C>(q: &QRE<D,C>, d: &D, pool: &mut Pool<D,C>) -> Option<QRE<D,C>>
+    where C: Clone + Send + Sync + 'static {
+    Some(match q {
+        Bot | Eps{..} => Bot,
+        Sat{phi, op} => if phi(d) { Eps{c: op(d)} } else { Bot },
+        TrySat{phi, op} if phi(d) => op(d).map(|c| Eps{c}).unwrap_or_else(|e| { fallible::raise(e); Bot }),
+        TrySat{..} => Bot,
+        Choice{v} => {
+            let mut live = None;
+            for q in v {
+                match deriv_one(q, d, pool)? {
+                    Bot => (),
+                    r if live.is_none() => live = Some(r),
+                    _ => return None
+                }
+            };
+            live.unwrap_or(Bot)
+        },
+        Split{f, g, op} => {
+            let eps = epsilon(f);
+            match eps.len() {
+                0 => match deriv_one(f, d, pool)? {
+                    Bot => Bot,
+                    f => Split{f: pool.node(f), g: g.clone(), op: *op}
+                },
+                1 => match deriv_one(g, d, pool)? {
+                    Bot => Bot,
+                    dg => App{f: pool.node(dg), op: AppOp::Left(*op, eps[0].clone())}
+                },
+                _ => return None
+            }
+        },
+        Iter{init, body, op} => {
+            let eps = epsilon(init);
+            match eps.len() {
+                0 => match deriv_one(init, d, pool)? {
+                    Bot => Bot,
+                    init => Iter{init: pool.node(init), body: body.clone(), op: *op}
+                },
+                1 => match deriv_one(body, d, pool)? {
+                    Bot => Bot,
+                    db => {
+                        let init = App{f: pool.node(db), op: AppOp::Left(*op, eps[0].clone())};
+                        Iter{init: pool.node(init), body: body.clone(), op: *op}
+                    }
+                },
+                _ => return None
+            }
+        },
+        App{f, op} => match deriv_one(f, d, pool)? {
+            Bot => Bot,
+            f => App{f: pool.node(f), op: op.clone()}
+        },
+        Combine{f, g, op} => {
+            let f = deriv_one(f, d, pool)?;
+            let g = deriv_one(g, d, pool)?;
+            match (f, g) {
+                (Bot, _) | (_, Bot) => Bot,
+                (f, g) => Combine{f: pool.node(f), g: pool.node(g), op: *op}
+            }
+        },
+        TryCombine{f, g, op} => {
+            let f = deriv_one(f, d, pool)?;
+            let g = deriv_one(g, d, pool)?;
+            match (f, g) {
+                (Bot, _) | (_, Bot) => Bot,
+                (f, g) => TryCombine{f: pool.node(f), g: pool.node(g), op: *op}
+            }
+        },
+        Tag{..} | Cap{..} | Trigger{..} => return None,
+    })
+}
+
+/// Replaces a one-residual `state` with its derivative, if that can be
+/// done one residual at a time; see `deriv_one`.
+pub fn step<D,C>(state: &mut Arc<Vec<QRE<D,C>>>, d: &D, pool: &mut Pool<D,C>) -> bool
+    where C: Clone + Send + Sync + 'static {
+    let r = match deriv_one(&state[0], d, pool) {
+        Some(r) => r,
+        None => return false
+    };
+    match Arc::get_mut(state) {
+        Some(state) => {
+            state.clear();
+            if !matches!(r, Bot) {
+                state.push(r)
+            }
+        },
+        None => *state = Arc::new(if matches!(r, Bot) { Vec::new() } else { vec![r] })
+    };
+    true
+}
@@ -0,0 +1,129 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+use ops::Monoid;
+
+struct Bucket<S> {
+    // Timestamp of the newest element in the bucket.
+    end: u64,
+    size: u64,
+    summary: S,
+}
+
+/// A DGIM-style exponential histogram: a sliding window over the last
+/// `window` elements, kept as O(k log window) buckets of power-of-two
+/// sizes, each holding the summary of the elements it covers. Only the
+/// oldest bucket can straddle the window's edge, so `query` aggregates
+/// the window plus at most `1/k` of it in extra, already-expired elements.
+/// That's the price for aggregates like max that can't be retracted.
+pub struct ExpHistogram<S> {
+    window: u64,
+    k: usize,
+    now: u64,
+    // Oldest (and largest) first.
+    buckets: VecDeque<Bucket<S>>,
+}
+
+impl <S: Monoid + Clone> ExpHistogram<S> {
+    pub fn new(window: u64, k: usize) -> Self {
+        assert!(k > 0, "ExpHistogram needs at least one bucket per size");
+        Self { window, k, now: 0, buckets: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, x: S) {
+        self.now += 1;
+        self.buckets.push_back(Bucket{end: self.now, size: 1, summary: x});
+        // Buckets of equal size are adjacent; whenever a size has more
+        // than k, the two oldest become one of twice the size.
+        let mut hi = self.buckets.len();
+        let mut size = 1;
+        loop {
+            let mut lo = hi;
+            while lo > 0 && self.buckets[lo - 1].size == size {
+                lo -= 1
+            };
+            if hi - lo <= self.k {
+                break
+            };
+            let older = self.buckets.remove(lo).unwrap();
+            let newer = &mut self.buckets[lo];
+            newer.size += older.size;
+            newer.summary = S::combine(older.summary, newer.summary.clone());
+            hi = lo + 1;
+            size *= 2
+        };
+        while let Some(b) = self.buckets.front() {
+            if b.end + self.window <= self.now { self.buckets.pop_front(); }
+            else { break }
+        }
+    }
+
+    /// The aggregate over the (slightly over-approximated) window.
+    pub fn query(&self) -> S {
+        let mut acc = S::unit();
+        for b in &self.buckets {
+            acc = S::combine(acc, b.summary.clone())
+        };
+        acc
+    }
+
+    /// How many elements `query` covers. Once the window has filled, this
+    /// is `window` plus fewer than the oldest bucket's size.
+    pub fn coverage(&self) -> u64 {
+        self.buckets.iter().map(|b| b.size).sum()
+    }
+
+    #[allow(dead_code)]
+    pub fn buckets(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+const REGISTERS: usize = 64;
+
+/// A HyperLogLog-style distinct-count sketch with 64 registers (about 13%
+/// standard error). Combining keeps each register's max, so sketches of
+/// adjacent buckets merge exactly.
+#[derive(Clone,Debug)]
+pub struct Distinct {
+    regs: [u8; REGISTERS],
+}
+
+impl Distinct {
+    #[allow(dead_code)]
+    pub fn of<T: Hash>(x: &T) -> Self {
+        let mut h = DefaultHasher::new();
+        x.hash(&mut h);
+        let h = h.finish();
+        let mut regs = [0; REGISTERS];
+        let rest = h >> 6;
+        regs[(h & 63) as usize] = rest.trailing_zeros().min(58) as u8 + 1;
+        Distinct { regs }
+    }
+
+    #[allow(dead_code)]
+    pub fn estimate(&self) -> f64 {
+        let m = REGISTERS as f64;
+        let sum: f64 = self.regs.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let e = 0.709 * m * m / sum;
+        let zeros = self.regs.iter().filter(|r| **r == 0).count();
+        if e <= 2.5 * m && zeros > 0 {
+            // Small-range correction (linear counting).
+            m * (m / zeros as f64).ln()
+        }
+        else {
+            e
+        }
+    }
+}
+
+impl Monoid for Distinct {
+    fn unit() -> Self { Distinct { regs: [0; REGISTERS] } }
+    fn combine(mut x: Self, y: Self) -> Self {
+        for (a, b) in x.regs.iter_mut().zip(y.regs.iter()) {
+            *a = (*a).max(*b)
+        };
+        x
+    }
+}
@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+/// An error returned by a fallible op (`TrySat`, `TryCombine` or
+/// `AppOp::Try`), whatever its type; `downcast_ref` gets the original
+/// back. Any `std::error::Error` converts into one, so ops can use `?`.
+#[derive(Clone)]
+pub struct Error(Arc<dyn error::Error + Send + Sync>);
+
+impl Error {
+    /// An error that's just a message, e.g. for a check the op makes
+    /// itself.
+    #[allow(dead_code)]
+    pub fn msg<M: Into<String>>(m: M) -> Self {
+        Error(Arc::from(Box::<dyn error::Error + Send + Sync>::from(m.into())))
+    }
+
+    pub fn downcast_ref<E: error::Error + 'static>(&self) -> Option<&E> {
+        self.0.downcast_ref()
+    }
+}
+
+impl <E: error::Error + Send + Sync + 'static> From<E> for Error {
+    fn from(e: E) -> Self {
+        Error(Arc::new(e))
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// What a solver does when one of its ops fails; see `Solve::on_error`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Policy {
+    /// Stop: later updates are ignored, and `output` fails with the error.
+    Abort,
+    /// Drop the element that caused it, as if it had never arrived.
+    /// (`Trigger`s it set off have still fired.)
+    Skip,
+    /// Treat whatever the failing op was part of as not matching, and
+    /// carry on.
+    NoMatch,
+}
+
+/// The ops that failed under a solver, and what it does about them.
+#[derive(Clone,Debug)]
+pub struct Errors {
+    pub policy: Policy,
+    pub count: u64,
+    pub last: Option<Error>,
+    pub aborted: bool,
+}
+
+impl Errors {
+    pub fn new() -> Self {
+        Errors { policy: Policy::Abort, count: 0, last: None, aborted: false }
+    }
+
+    /// Notes the errors `take` returned, and says what to do about them.
+    pub fn record(&mut self, (e, n): (Error, u64)) -> Policy {
+        self.count += n;
+        self.last = Some(e);
+        if self.policy == Policy::Abort {
+            self.aborted = true
+        };
+        self.policy
+    }
+}
+
+thread_local! {
+    // The first error raised on this thread since the last `take`, and
+    // how many there have been. Ops run inside `epsilon` as well as
+    // `deriv`, and `epsilon` has no `Pool` to report them to.
+    static RAISED: RefCell<Option<(Error, u64)>> = const { RefCell::new(None) };
+}
+
+/// Reports that an op failed; what became of its value is up to the
+/// caller, which drops it.
+pub fn raise(e: Error) {
+    RAISED.with(|r| {
+        let mut r = r.borrow_mut();
+        match *r {
+            Some((_, ref mut n)) => *n += 1,
+            None => *r = Some((e, 1))
+        }
+    })
+}
+
+/// The errors raised on this thread since the last call.
+pub fn take() -> Option<(Error, u64)> {
+    RAISED.with(|r| r.borrow_mut().take())
+}
@@ -0,0 +1,146 @@
+use std::fmt::Debug;
+
+use super::{Solve, QRE};
+use checkpoint::{Persist, Reader};
+use scan::Scan;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Model {
+    /// A level that drifts by process noise `q`, measured with noise `r`.
+    Kalman { q: f64, r: f64 },
+    /// A level moving at a velocity, corrected by fixed gains.
+    AlphaBeta { alpha: f64, beta: f64 },
+}
+
+/// Smooths noisy measurements, as a `Scan`: read the smoothed value off
+/// `estimate`. To match patterns against the smoothed values instead, see
+/// `Smoothed`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Filter {
+    model: Model,
+    started: bool,
+    estimate: f64,
+    // The estimate's variance (Kalman) or its velocity (alpha-beta).
+    aux: f64,
+    latest: f64,
+}
+
+impl Filter {
+    /// A 1D Kalman filter for a slowly varying level: `q` is how much it
+    /// can drift per element, `r` how noisy the measurements are (both as
+    /// variances). Higher `r / q` smooths harder.
+    pub fn kalman(q: f64, r: f64) -> Self {
+        Self::with(Model::Kalman { q, r })
+    }
+
+    /// An alpha-beta filter, which also tracks a trend: gains in (0, 1],
+    /// lower smoothing harder.
+    #[allow(dead_code)]
+    pub fn alpha_beta(alpha: f64, beta: f64) -> Self {
+        Self::with(Model::AlphaBeta { alpha, beta })
+    }
+
+    fn with(model: Model) -> Self {
+        Filter { model, started: false, estimate: 0.0, aux: 0.0, latest: 0.0 }
+    }
+
+    #[allow(dead_code)]
+    pub fn estimate(&self) -> f64 {
+        self.estimate
+    }
+
+    /// The estimated change per element (0 for the Kalman filter).
+    #[allow(dead_code)]
+    pub fn velocity(&self) -> f64 {
+        match self.model {
+            Model::Kalman { .. } => 0.0,
+            Model::AlphaBeta { .. } => self.aux
+        }
+    }
+}
+
+impl Scan for Filter {
+    fn sample(x: f64) -> Self {
+        Filter { latest: x, ..Self::kalman(0.0, 0.0) }
+    }
+
+    fn latest(&self) -> f64 {
+        self.latest
+    }
+
+    /// The first measurement is taken as is.
+    fn push(&mut self, x: f64) {
+        self.latest = x;
+        if !self.started {
+            self.started = true;
+            self.estimate = x;
+            self.aux = match self.model { Model::Kalman { r, .. } => r, Model::AlphaBeta { .. } => 0.0 };
+            return
+        };
+        match self.model {
+            Model::Kalman { q, r } => {
+                let p = self.aux + q;
+                let gain = p / (p + r);
+                self.estimate += gain * (x - self.estimate);
+                self.aux = (1.0 - gain) * p
+            },
+            Model::AlphaBeta { alpha, beta } => {
+                let predicted = self.estimate + self.aux;
+                let err = x - predicted;
+                self.estimate = predicted + alpha * err;
+                self.aux += beta * err
+            }
+        }
+    }
+}
+
+/// Runs `query` over the filtered values of a stream of measurements
+/// rather than the raw ones, so noise doesn't trip its predicates.
+pub struct Smoothed<C: 'static> {
+    filter: Filter,
+    solver: Solve<f64,C>,
+}
+
+impl <C> Smoothed<C> where C: Clone + Debug + Send + Sync {
+    pub fn new(filter: Filter, query: QRE<f64,C>) -> Self {
+        Smoothed { filter, solver: Solve::new(query) }
+    }
+
+    pub fn update(&mut self, x: f64) {
+        self.filter.push(x);
+        self.solver.update(self.filter.estimate)
+    }
+
+    pub fn output(&self) -> Result<C, String> {
+        self.solver.output()
+    }
+
+    #[allow(dead_code)]
+    pub fn estimate(&self) -> f64 {
+        self.filter.estimate
+    }
+}
+
+impl Persist for Filter {
+    fn write(&self, w: &mut Vec<u8>) {
+        match self.model {
+            Model::Kalman { q, r } => { false.write(w); q.write(w); r.write(w) },
+            Model::AlphaBeta { alpha, beta } => { true.write(w); alpha.write(w); beta.write(w) }
+        };
+        self.started.write(w);
+        self.estimate.write(w);
+        self.aux.write(w);
+        self.latest.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let alpha_beta = bool::read(r)?;
+        let (a, b) = (f64::read(r)?, f64::read(r)?);
+        Ok(Filter {
+            model: if alpha_beta { Model::AlphaBeta { alpha: a, beta: b } } else { Model::Kalman { q: a, r: b } },
+            started: bool::read(r)?,
+            estimate: f64::read(r)?,
+            aux: f64::read(r)?,
+            latest: f64::read(r)?
+        })
+    }
+}
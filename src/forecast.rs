@@ -0,0 +1,143 @@
+use checkpoint::{Persist, Reader};
+use scan::Scan;
+
+/// Additive Holt-Winters smoothing, as a `Scan`. Each element updates the
+/// level, trend and seasonal components; `forecast` extrapolates them, and
+/// `residual` is how far the latest element was from what was forecast for
+/// it. A `period` of 0 drops the seasonal part (Holt's linear method), and
+/// a `beta` of 0 as well leaves simple exponential smoothing.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Forecast {
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+    period: usize,
+    // Elements seen, including the warm-up.
+    n: u64,
+    // The first season (or element), which the components start from.
+    warmup: Vec<f64>,
+    level: f64,
+    trend: f64,
+    season: Vec<f64>,
+    latest: f64,
+    residual: Option<f64>,
+}
+
+impl Forecast {
+    /// Smoothing factors in [0, 1] for the level, trend and season; higher
+    /// forgets faster.
+    pub fn new(alpha: f64, beta: f64, gamma: f64, period: usize) -> Self {
+        Forecast {
+            alpha, beta, gamma, period,
+            n: 0,
+            warmup: Vec::new(),
+            level: 0.0,
+            trend: 0.0,
+            season: vec![0.0; period],
+            latest: 0.0,
+            residual: None
+        }
+    }
+
+    fn warm(&self) -> bool {
+        self.warmup.len() >= self.period.max(1)
+    }
+
+    /// The value expected `h` (at least 1) elements from now; `None`
+    /// during the first season.
+    pub fn forecast(&self, h: u64) -> Option<f64> {
+        if !self.warm() {
+            return None
+        };
+        let s = if self.period > 0 { self.season[((self.n + h - 1) % self.period as u64) as usize] } else { 0.0 };
+        Some(self.level + h as f64 * self.trend + s)
+    }
+
+    /// The latest element minus its one-step forecast.
+    pub fn residual(&self) -> Option<f64> {
+        self.residual
+    }
+
+    #[allow(dead_code)]
+    pub fn level(&self) -> f64 {
+        self.level
+    }
+
+    #[allow(dead_code)]
+    pub fn trend(&self) -> f64 {
+        self.trend
+    }
+}
+
+impl Scan for Forecast {
+    fn sample(x: f64) -> Self {
+        Forecast { latest: x, ..Forecast::new(0.0, 0.0, 0.0, 0) }
+    }
+
+    fn latest(&self) -> f64 {
+        self.latest
+    }
+
+    fn push(&mut self, x: f64) {
+        self.residual = self.forecast(1).map(|f| x - f);
+        if !self.warm() {
+            self.warmup.push(x);
+            if self.warm() {
+                self.level = self.warmup.iter().sum::<f64>() / self.warmup.len() as f64;
+                for (s, w) in self.season.iter_mut().zip(self.warmup.iter()) {
+                    *s = w - self.level
+                }
+            }
+        }
+        else {
+            let i = if self.period > 0 { (self.n % self.period as u64) as usize } else { 0 };
+            let s = self.season.get(i).cloned().unwrap_or(0.0);
+            let level = self.alpha * (x - s) + (1.0 - self.alpha) * (self.level + self.trend);
+            self.trend = self.beta * (level - self.level) + (1.0 - self.beta) * self.trend;
+            if let Some(s) = self.season.get_mut(i) {
+                *s = self.gamma * (x - level) + (1.0 - self.gamma) * *s
+            };
+            self.level = level
+        };
+        self.n += 1;
+        self.latest = x
+    }
+}
+
+impl Persist for Forecast {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.alpha.write(w);
+        self.beta.write(w);
+        self.gamma.write(w);
+        self.period.write(w);
+        self.n.write(w);
+        self.warmup.write(w);
+        self.level.write(w);
+        self.trend.write(w);
+        self.season.write(w);
+        self.latest.write(w);
+        self.residual.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let f = Forecast {
+            alpha: f64::read(r)?,
+            beta: f64::read(r)?,
+            gamma: f64::read(r)?,
+            period: usize::read(r)?,
+            n: u64::read(r)?,
+            warmup: Vec::read(r)?,
+            level: f64::read(r)?,
+            trend: f64::read(r)?,
+            season: Vec::read(r)?,
+            latest: f64::read(r)?,
+            residual: Option::read(r)?
+        };
+        if f.season.len() != f.period {
+            return Err(format!("Forecast: {} seasonal components for a period of {}", f.season.len(), f.period))
+        };
+        if f.warmup.len() > f.period.max(1) {
+            return Err(format!("Forecast: {} warm-up elements for a period of {}", f.warmup.len(), f.period))
+        };
+        Ok(f)
+    }
+}
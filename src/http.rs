@@ -0,0 +1,226 @@
+use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::Solve;
+use runtime::Decode;
+
+/// How long a client gets to send a request's head, and how long its body
+/// can then go quiet (between records, say), before it's dropped.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The longest line, and the most header lines, a request's head can have;
+/// the records and chunk-size lines of a body are held to `MAX_LINE` too.
+const MAX_LINE: u64 = 8192;
+const MAX_HEADERS: usize = 100;
+
+/// Serves `solver` over HTTP on `addr`, one thread per connection, until
+/// the listener fails:
+///
+/// - `POST /ingest` feeds it the request body as it arrives, either as
+///   server-sent events (each `data:` line is a record; comments and other
+///   fields are skipped) or as one record per line, chunked or not. It
+///   answers with how many records it took, or 400 at the first one
+///   `decode` rejects or that's longer than `MAX_LINE` (the records
+///   before it have been fed).
+/// - `GET /output` answers with the current output, or 409 if it's
+///   undefined.
+///
+/// Either answers 500 once an update has panicked while holding `solver`.
+#[allow(dead_code)]
+pub fn serve<D,C,A>(addr: A, solver: Arc<Mutex<Solve<D,C>>>, decode: Decode<D>) -> io::Result<()>
+    where A: ToSocketAddrs,
+          D: Send + 'static,
+          C: Clone + Debug + Send + Sync + 'static {
+    let listener = TcpListener::bind(addr)?;
+    for conn in listener.incoming() {
+        let conn = conn?;
+        let solver = solver.clone();
+        let decode = decode.clone();
+        thread::spawn(move || {
+            // A client that hangs up mid-request has nobody to tell.
+            let _ = handle(conn, &solver, &decode);
+        });
+    };
+    Ok(())
+}
+
+const POISONED: (&str, &str) = ("500 Internal Server Error", "an update panicked\n");
+
+fn handle<D,C>(conn: TcpStream, solver: &Mutex<Solve<D,C>>, decode: &Decode<D>) -> io::Result<()>
+    where C: Clone + Debug + Send + Sync {
+    let (mut r, head) = accept(conn)?;
+    let (status, body) = match (head.method.as_str(), head.path.as_str()) {
+        ("POST", "/ingest") => match ingest(body(&mut r, &head), solver, decode)? {
+            Ok(n) => ("200 OK", format!("{}\n", n)),
+            Err(e) => e
+        },
+        ("GET", "/output") => match solver.lock() {
+            Ok(s) => match s.defined_output() {
+                Some(c) => ("200 OK", format!("{:?}\n", c)),
+                None => ("409 Conflict", "undefined\n".to_string())
+            },
+            Err(_) => (POISONED.0, POISONED.1.to_string())
+        },
+        _ => ("404 Not Found", "not found\n".to_string())
+    };
+    respond(r.get_mut(), status, "text/plain", body.as_bytes())
+}
+
+/// Reads the head of the request on `conn`, under `HEAD_TIMEOUT`, and
+/// leaves the rest to be read under `IDLE_TIMEOUT`.
+pub fn accept(conn: TcpStream) -> io::Result<(BufReader<TcpStream>, Head)> {
+    conn.set_read_timeout(Some(HEAD_TIMEOUT))?;
+    let mut r = BufReader::new(conn);
+    let head = read_head(&mut r)?;
+    r.get_ref().set_read_timeout(Some(IDLE_TIMEOUT))?;
+    Ok((r, head))
+}
+
+/// A request line and the headers needed to find the body.
+pub struct Head {
+    pub method: String,
+    pub path: String,
+    /// `Content-Encoding`, if it isn't `identity`.
+    pub encoding: Option<String>,
+    length: Option<u64>,
+    chunked: bool,
+}
+
+pub fn read_head<R: BufRead>(r: &mut R) -> io::Result<Head> {
+    let mut line = String::new();
+    read_line(r, &mut line)?;
+    let mut words = line.split_whitespace();
+    let (method, path) = (words.next().unwrap_or("").to_string(), words.next().unwrap_or("").to_string());
+    let mut head = Head { method, path, encoding: None, length: None, chunked: false };
+    for _ in 0..=MAX_HEADERS {
+        line.clear();
+        if read_line(r, &mut line)? == 0 || line.trim().is_empty() {
+            return Ok(head)
+        };
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => head.length = value.parse::<u64>().ok(),
+                "content-encoding" if !value.eq_ignore_ascii_case("identity") => {
+                    head.encoding = Some(value.to_ascii_lowercase())
+                },
+                "transfer-encoding" => head.chunked = value.eq_ignore_ascii_case("chunked"),
+                _ => ()
+            }
+        }
+    };
+    Err(io::Error::new(io::ErrorKind::InvalidData, "too many headers"))
+}
+
+/// `r.read_line(line)`, failing on a line longer than `MAX_LINE`.
+fn read_line<R: BufRead>(r: &mut R, line: &mut String) -> io::Result<usize> {
+    let n = r.take(MAX_LINE + 1).read_line(line)?;
+    if n as u64 > MAX_LINE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"))
+    };
+    Ok(n)
+}
+
+/// The body of the request `head` was read from `r` for: chunked, of a
+/// given length, or running to the end of the connection.
+pub fn body<'a, R: BufRead + 'a>(r: &'a mut R, head: &Head) -> Box<dyn BufRead + 'a> {
+    if head.chunked {
+        Box::new(BufReader::new(Chunked { inner: r, left: 0, done: false }))
+    }
+    else {
+        match head.length {
+            Some(n) => Box::new(r.take(n)),
+            None => Box::new(r)
+        }
+    }
+}
+
+pub fn respond<W: Write>(w: &mut W, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(w, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+           status, content_type, body.len())?;
+    w.write_all(body)
+}
+
+/// Feeds the records in `body` to `solver` as they arrive; how many, or
+/// the status and message to answer with instead.
+fn ingest<D,C,R: BufRead>(mut body: R, solver: &Mutex<Solve<D,C>>, decode: &Decode<D>)
+    -> io::Result<Result<u64, (&'static str, String)>>
+    where C: Clone + Debug + Send + Sync {
+    let mut n = 0;
+    let mut line = String::new();
+    // Records are SSE `data:` payloads or lines of newline-delimited JSON.
+    for i in 0.. {
+        line.clear();
+        match read_line(&mut body, &mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                return Ok(Err(("400 Bad Request", format!("line {}: {}\n", i + 1, e))))
+            },
+            Err(e) => return Err(e)
+        };
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let record = match line.strip_prefix("data:") {
+            Some(data) => data.strip_prefix(' ').unwrap_or(data),
+            None if line.is_empty() || line.starts_with(':') => continue,
+            None if ["event:", "id:", "retry:"].iter().any(|f| line.starts_with(f)) => continue,
+            None => line
+        };
+        match (decode(record), solver.lock()) {
+            (_, Err(_)) => return Ok(Err((POISONED.0, POISONED.1.to_string()))),
+            (Ok(d), Ok(mut s)) => s.update(d),
+            (Err(e), _) => return Ok(Err(("400 Bad Request", format!("line {}: {}\n", i + 1, e))))
+        };
+        n += 1
+    };
+    Ok(Ok(n))
+}
+
+/// A `Transfer-Encoding: chunked` body.
+struct Chunked<R> {
+    inner: R,
+    // Bytes left in the current chunk.
+    left: u64,
+    done: bool,
+}
+
+impl <R: BufRead> Read for Chunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0)
+        };
+        let mut line = String::new();
+        if self.left == 0 {
+            read_line(&mut self.inner, &mut line)?;
+            let size = line.trim().split(';').next().unwrap_or("");
+            self.left = u64::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad chunk size"))?;
+            if self.left == 0 {
+                // Skip any trailers.
+                self.done = true;
+                loop {
+                    line.clear();
+                    if read_line(&mut self.inner, &mut line)? == 0 || line.trim().is_empty() {
+                        return Ok(0)
+                    }
+                }
+            }
+        };
+        let n = (&mut self.inner).take(self.left).read(buf)?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated chunk"))
+        };
+        self.left -= n as u64;
+        if self.left == 0 {
+            // The CRLF after the chunk.
+            read_line(&mut self.inner, &mut line)?;
+        };
+        Ok(n)
+    }
+}
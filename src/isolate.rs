@@ -0,0 +1,20 @@
+use std::panic::{self, AssertUnwindSafe};
+
+/// How much a panicking predicate, op or callback takes down with it; see
+/// `Solve::isolate_panics`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Poison {
+    /// Just the residual being derived when it panicked: that way of
+    /// matching the stream is dropped, the others carry on.
+    #[allow(dead_code)]
+    Residual,
+    /// The whole solver: later updates are ignored, and `output` fails.
+    Solver,
+}
+
+/// Runs `f`, turning a panic into `None`. Whatever `f` was in the middle
+/// of rewriting may be left half done, so callers throw away everything
+/// it touched. The panic hook still runs, so the panic is still logged.
+pub fn catch<R, F: FnOnce() -> R>(f: F) -> Option<R> {
+    panic::catch_unwind(AssertUnwindSafe(f)).ok()
+}
@@ -0,0 +1,76 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::{Solve, QRE};
+use isolate;
+
+/// One solver per key (device, user, ...), each running `query` on just
+/// the elements for its key. A key's solver starts the first time the key
+/// shows up.
+pub struct Keyed<K,D,C: 'static> {
+    query: QRE<D,C>,
+    solvers: HashMap<K, Solve<D,C>>,
+    // See `isolate_panics`.
+    isolation: Option<isolate::Poison>,
+}
+
+impl <K,D,C> Keyed<K,D,C> where K: Eq + Hash, C: Clone + Debug + Send + Sync {
+    pub fn new(query: QRE<D,C>) -> Self {
+        Keyed { query, solvers: HashMap::new(), isolation: None }
+    }
+
+    pub fn update<B: Borrow<D>>(&mut self, k: K, d: B) {
+        let (query, isolation) = (&self.query, self.isolation);
+        self.solvers.entry(k).or_insert_with(|| {
+            let mut s = Solve::new(query.clone());
+            if let Some(poison) = isolation {
+                s.isolate_panics(poison)
+            };
+            s
+        }).update(d)
+    }
+
+    /// `Solve::isolate_panics` for every key's solver, so a panic on one
+    /// key's elements at most poisons that key (with `Poison::Solver`).
+    pub fn isolate_panics(&mut self, poison: isolate::Poison) {
+        self.isolation = Some(poison);
+        for s in self.solvers.values_mut() {
+            s.isolate_panics(poison)
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, k: &K) -> Option<&Solve<D,C>> {
+        self.solvers.get(k)
+    }
+
+    pub fn output(&self, k: &K) -> Result<C, String> {
+        match self.solvers.get(k) {
+            Some(s) => s.output(),
+            None => Err("no elements for that key".to_string())
+        }
+    }
+
+    /// Stops tracking `k`, e.g. once a device is decommissioned.
+    #[allow(dead_code)]
+    pub fn remove(&mut self, k: &K) -> Option<Solve<D,C>> {
+        self.solvers.remove(k)
+    }
+
+    #[allow(dead_code)]
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.solvers.keys()
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.solvers.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.solvers.is_empty()
+    }
+}
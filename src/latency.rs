@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+/// Sub-buckets per power of two, as a power of two: every value is kept
+/// to within 1/64 of itself, i.e. about two significant digits.
+const PRECISION: u32 = 6;
+const HALF: u64 = 1 << PRECISION;
+
+/// An HDR-style histogram of durations in nanoseconds: values below
+/// `2 * HALF` are counted exactly, larger ones in log-linear buckets, so
+/// it's a few KiB however many updates it sees and however long the tail.
+#[derive(Clone,Debug)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+/// Tail latency as of some point, for `Stats`.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Latency {
+    pub count: u64,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+fn index(v: u64) -> usize {
+    if v < 2 * HALF {
+        return v as usize
+    };
+    let p = 63 - v.leading_zeros();
+    let top = v >> (p - PRECISION);
+    (2 * HALF + (p - PRECISION - 1) as u64 * HALF + (top - HALF)) as usize
+}
+
+/// The largest value counted in bucket `i`.
+fn highest(i: usize) -> u64 {
+    let i = i as u64;
+    if i < 2 * HALF {
+        return i
+    };
+    let p = (i - 2 * HALF) / HALF + PRECISION as u64 + 1;
+    let top = (i - 2 * HALF) % HALF + HALF;
+    let shift = p - PRECISION as u64;
+    (top << shift) + ((1 << shift) - 1)
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram { counts: Vec::new(), count: 0, max: 0 }
+    }
+
+    pub fn record(&mut self, d: Duration) {
+        let v = d.as_nanos().min(u64::MAX as u128) as u64;
+        let i = index(v);
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0)
+        };
+        self.counts[i] += 1;
+        self.count += 1;
+        self.max = self.max.max(v)
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// The duration that a fraction `q` of the recorded ones are at most
+    /// (to within the histogram's precision); zero if it's empty.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_nanos(highest(i).min(self.max))
+            }
+        };
+        Duration::from_nanos(self.max)
+    }
+
+    pub fn latency(&self) -> Latency {
+        Latency { count: self.count, p50: self.quantile(0.5), p99: self.quantile(0.99), max: self.max() }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
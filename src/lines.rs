@@ -0,0 +1,648 @@
+use std::io::{self, BufRead};
+
+use validate;
+
+/// A captured value: numbers where the text parses as one, so numeric
+/// fields can be aggregated without a parser of their own.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Field {
+    Num(f64),
+    Text(String),
+}
+
+impl Field {
+    fn coerce(s: String) -> Self {
+        // Not `inf` or `nan`, which are more likely words.
+        match s.parse::<f64>() {
+            Ok(x) if x.is_finite() => Field::Num(x),
+            _ => Field::Text(s)
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self { Field::Num(x) => Some(*x), _ => None }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self { Field::Text(s) => Some(s), _ => None }
+    }
+}
+
+/// A line of text and what a `Pattern`'s named groups captured in it.
+/// Groups that took no part in the match are left out.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Line {
+    pub line: String,
+    pub fields: Vec<(String, Field)>,
+}
+
+impl Line {
+    #[allow(dead_code)]
+    pub fn get(&self, name: &str) -> Option<&Field> {
+        self.find(name).or_else(|| { validate::missed(name, "a"); None })
+    }
+
+    pub fn num(&self, name: &str) -> Option<f64> {
+        self.find(name).and_then(Field::as_f64).or_else(|| { validate::missed(name, "a numeric"); None })
+    }
+
+    #[allow(dead_code)]
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.find(name).and_then(Field::as_str).or_else(|| { validate::missed(name, "a text"); None })
+    }
+
+    fn find(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, f)| f)
+    }
+}
+
+/// A regular expression with named groups, `(?P<name>...)` or
+/// `(?<name>...)`. Supports literals and escapes, `.`, classes (`[a-z_]`,
+/// `[^ ]`, `\d \w \s` and their negations), groups (`(...)`, `(?:...)`),
+/// `|`, `^ $`, and the quantifiers `* + ? {n} {n,} {n,m}`, greedy or lazy
+/// (`*?` and so on). It matches by backtracking, so patterns that nest
+/// unbounded repetitions can be slow on lines that don't match; the
+/// backtracking is kept on the heap, so long lines can't overflow the
+/// stack.
+#[derive(Clone,Debug)]
+pub struct Pattern {
+    prog: Vec<Inst>,
+    // Slots hold where each group starts and ends: 2 per group.
+    slots: usize,
+    regs: usize,
+    names: Vec<(String, usize)>,
+}
+
+/// The most instructions a pattern may compile to; counted repetitions
+/// are written out in full, so `(a{1000}){1000}` would be a million.
+const MAX_PROGRAM: usize = 100_000;
+
+impl Pattern {
+    pub fn new(re: &str) -> Result<Self, String> {
+        let mut p = Parser { s: re.chars().collect(), i: 0, slots: 1, names: Vec::new() };
+        let alts = p.alts()?;
+        if p.i < p.s.len() {
+            return Err(format!("unmatched ) at {}", p.i))
+        };
+        let mut c = Compiler { prog: Vec::new(), regs: 0 };
+        c.node(&Node::Group { slot: Some(0), alts })?;
+        c.prog.push(Inst::Match);
+        Ok(Pattern { prog: c.prog, slots: 2 * p.slots, regs: c.regs, names: p.names })
+    }
+
+    /// The leftmost match in `line`, if there is one.
+    pub fn parse(&self, line: &str) -> Option<Line> {
+        let text: Vec<char> = line.chars().collect();
+        let mut m = Matcher::new(self, &text);
+        (0..=text.len()).find_map(|start| m.run(start)).map(|_| {
+            let fields = self.names.iter()
+                .filter_map(|(name, i)| m.group(*i).map(|(a, b)| {
+                    (name.clone(), Field::coerce(text[a..b].iter().collect()))
+                }))
+                .collect();
+            Line { line: line.to_string(), fields }
+        })
+    }
+
+    /// Where each match in `text` starts and ends, in bytes, leftmost
+    /// first and not overlapping. Empty matches are left out.
+    pub fn spans(&self, text: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = text.chars().collect();
+        let bytes: Vec<usize> = text.char_indices().map(|(i, _)| i).chain(Some(text.len())).collect();
+        let mut m = Matcher::new(self, &chars);
+        let mut spans = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            match m.run(start) {
+                Some(end) if end > start => {
+                    spans.push((bytes[start], bytes[end]));
+                    start = end
+                },
+                _ => start += 1
+            }
+        };
+        spans
+    }
+
+    /// The lines of `r` that match, in order. Lines that don't are skipped.
+    pub fn lines<R: BufRead>(&self, r: R) -> Matches<'_, R> {
+        Matches { pattern: self, lines: r.lines() }
+    }
+}
+
+pub struct Matches<'a, R> {
+    pattern: &'a Pattern,
+    lines: io::Lines<R>,
+}
+
+impl <'a, R: BufRead> Iterator for Matches<'a, R> {
+    type Item = io::Result<Line>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in &mut self.lines {
+            match line {
+                Ok(l) => if let Some(m) = self.pattern.parse(&l) { return Some(Ok(m)) },
+                Err(e) => return Some(Err(e))
+            }
+        };
+        None
+    }
+}
+
+#[derive(Clone,Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    Group { slot: Option<usize>, alts: Vec<Vec<Node>> },
+    Repeat { node: Box<Node>, min: usize, max: Option<usize>, greedy: bool },
+}
+
+impl Node {
+    fn accepts(&self, c: char) -> bool {
+        match self {
+            Node::Char(x) => *x == c,
+            Node::Any => c != '\n',
+            Node::Class { ranges, negated } => ranges.iter().any(|(a, b)| *a <= c && c <= *b) != *negated,
+            _ => false
+        }
+    }
+}
+
+/// What a `Pattern` compiles to: a program for a backtracking machine.
+#[derive(Clone,Debug)]
+enum Inst {
+    /// A `Char`, `Any` or `Class` node, consuming one char it accepts.
+    Atom(Node),
+    Start,
+    End,
+    /// Records the position in a slot.
+    Save(usize),
+    /// Goes on at the first, and failing that, at the second.
+    Split(usize, usize),
+    Jmp(usize),
+    /// Records the position in a register, at the start of an iteration
+    /// past a repetition's minimum...
+    Mark(usize),
+    /// ...which fails at its end unless it consumed something, or `(a*)*`
+    /// would go round forever.
+    Progress(usize),
+    Match,
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+    regs: usize,
+}
+
+impl Compiler {
+    fn emit(&mut self, i: Inst) -> Result<usize, String> {
+        if self.prog.len() >= MAX_PROGRAM {
+            return Err(format!("pattern is too big: over {} instructions", MAX_PROGRAM))
+        };
+        self.prog.push(i);
+        Ok(self.prog.len() - 1)
+    }
+
+    fn seq(&mut self, nodes: &[Node]) -> Result<(), String> {
+        nodes.iter().try_for_each(|n| self.node(n))
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Start => { self.emit(Inst::Start)?; },
+            Node::End => { self.emit(Inst::End)?; },
+            Node::Group { slot, alts } => {
+                if let Some(i) = slot {
+                    self.emit(Inst::Save(2 * i))?;
+                };
+                // Each alternative but the last tries itself first, then
+                // jumps past the rest.
+                let mut jumps = Vec::new();
+                for (n, alt) in alts.iter().enumerate() {
+                    if n + 1 < alts.len() {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.seq(alt)?;
+                        jumps.push(self.emit(Inst::Jmp(0))?);
+                        self.prog[split] = Inst::Split(split + 1, self.prog.len())
+                    }
+                    else {
+                        self.seq(alt)?
+                    }
+                };
+                for j in jumps {
+                    self.prog[j] = Inst::Jmp(self.prog.len())
+                };
+                if let Some(i) = slot {
+                    self.emit(Inst::Save(2 * i + 1))?;
+                }
+            },
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.node(node)?
+                };
+                let r = self.regs;
+                self.regs += 1;
+                let split = |c: &mut Compiler, at: usize, body: usize, exit: usize| {
+                    c.prog[at] = if *greedy { Inst::Split(body, exit) } else { Inst::Split(exit, body) }
+                };
+                match max {
+                    None => {
+                        let top = self.emit(Inst::Split(0, 0))?;
+                        self.emit(Inst::Mark(r))?;
+                        self.node(node)?;
+                        self.emit(Inst::Progress(r))?;
+                        self.emit(Inst::Jmp(top))?;
+                        let exit = self.prog.len();
+                        split(self, top, top + 1, exit)
+                    },
+                    Some(max) => {
+                        // Each optional iteration can stop the rest.
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0))?);
+                            self.emit(Inst::Mark(r))?;
+                            self.node(node)?;
+                            self.emit(Inst::Progress(r))?;
+                        };
+                        let exit = self.prog.len();
+                        for at in splits {
+                            split(self, at, at + 1, exit)
+                        }
+                    }
+                }
+            },
+            atom => { self.emit(Inst::Atom(atom.clone()))?; }
+        };
+        Ok(())
+    }
+}
+
+/// What the machine has to undo, or try next, when it backtracks.
+enum Frame {
+    Try(usize, usize),
+    Slot(usize, Option<usize>),
+    Reg(usize, usize),
+}
+
+/// Runs a pattern over `text`, reusing its buffers from one start to the
+/// next.
+struct Matcher<'a> {
+    pattern: &'a Pattern,
+    text: &'a [char],
+    slots: Vec<Option<usize>>,
+    regs: Vec<usize>,
+    stack: Vec<Frame>,
+}
+
+impl <'a> Matcher<'a> {
+    fn new(pattern: &'a Pattern, text: &'a [char]) -> Self {
+        Matcher { pattern, text, slots: vec![None; pattern.slots], regs: vec![0; pattern.regs], stack: Vec::new() }
+    }
+
+    /// Where the first match starting at `start` ends, trying alternatives
+    /// in the pattern's order of preference, as backtracking does.
+    fn run(&mut self, start: usize) -> Option<usize> {
+        self.slots.iter_mut().for_each(|s| *s = None);
+        self.stack.clear();
+        self.stack.push(Frame::Try(0, start));
+        while let Some(f) = self.stack.pop() {
+            let (mut pc, mut pos) = match f {
+                Frame::Try(pc, pos) => (pc, pos),
+                Frame::Slot(i, old) => { self.slots[i] = old; continue },
+                Frame::Reg(r, old) => { self.regs[r] = old; continue }
+            };
+            loop {
+                match &self.pattern.prog[pc] {
+                    Inst::Atom(node) => {
+                        if pos < self.text.len() && node.accepts(self.text[pos]) {
+                            pos += 1
+                        }
+                        else {
+                            break
+                        }
+                    },
+                    Inst::Start => if pos != 0 { break },
+                    Inst::End => if pos != self.text.len() { break },
+                    Inst::Save(i) => {
+                        self.stack.push(Frame::Slot(*i, self.slots[*i]));
+                        self.slots[*i] = Some(pos)
+                    },
+                    Inst::Split(a, b) => {
+                        self.stack.push(Frame::Try(*b, pos));
+                        pc = *a;
+                        continue
+                    },
+                    Inst::Jmp(a) => {
+                        pc = *a;
+                        continue
+                    },
+                    Inst::Mark(r) => {
+                        self.stack.push(Frame::Reg(*r, self.regs[*r]));
+                        self.regs[*r] = pos
+                    },
+                    Inst::Progress(r) => if pos <= self.regs[*r] { break },
+                    Inst::Match => return Some(pos),
+                };
+                pc += 1
+            }
+        };
+        None
+    }
+
+    /// What group `i` matched in the last successful `run`, in chars.
+    fn group(&self, i: usize) -> Option<(usize, usize)> {
+        Some((self.slots[2 * i]?, self.slots[2 * i + 1]?))
+    }
+}
+
+struct Parser {
+    s: Vec<char>,
+    i: usize,
+    slots: usize,
+    names: Vec<(String, usize)>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.s.get(self.i).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.i += 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    fn alts(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alts = vec![self.seq()?];
+        while self.eat('|') {
+            alts.push(self.seq()?)
+        };
+        Ok(alts)
+    }
+
+    fn seq(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break
+            };
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?)
+        };
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("unexpected end of pattern")?;
+        self.i += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => self.class()?,
+            '\\' => self.escape()?,
+            '(' => {
+                let slot = if self.eat('?') {
+                    if self.eat(':') {
+                        None
+                    }
+                    else {
+                        self.eat('P');
+                        if !self.eat('<') {
+                            return Err(format!("unsupported group syntax at {}", self.i))
+                        };
+                        let end = self.s[self.i..].iter().position(|c| *c == '>')
+                            .ok_or("unterminated group name")? + self.i;
+                        let name: String = self.s[self.i..end].iter().collect();
+                        self.i = end + 1;
+                        self.names.push((name, self.slots));
+                        Some(self.slots)
+                    }
+                }
+                else {
+                    Some(self.slots)
+                };
+                if slot.is_some() {
+                    self.slots += 1
+                };
+                let alts = self.alts()?;
+                if !self.eat(')') {
+                    return Err("unclosed (".to_string())
+                };
+                Node::Group { slot, alts }
+            },
+            '*' | '+' | '?' | '{' => return Err(format!("nothing to repeat at {}", self.i - 1)),
+            c => Node::Char(c)
+        })
+    }
+
+    fn quantified(&mut self, node: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let end = self.s[self.i..].iter().position(|c| *c == '}')
+                    .ok_or("unterminated {")? + self.i;
+                let spec: String = self.s[self.i + 1..end].iter().collect();
+                let num = |s: &str| s.trim().parse::<usize>().map_err(|_| format!("bad repetition {{{}}}", spec));
+                let bounds = match spec.split_once(',') {
+                    None => { let n = num(&spec)?; (n, Some(n)) },
+                    Some((a, "")) => (num(a)?, None),
+                    Some((a, b)) => (num(a)?, Some(num(b)?))
+                };
+                self.i = end;
+                bounds
+            },
+            _ => return Ok(node)
+        };
+        self.i += 1;
+        let greedy = !self.eat('?');
+        Ok(Node::Repeat { node: Box::new(node), min, max, greedy })
+    }
+
+    /// After a `\`.
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("trailing \\")?;
+        self.i += 1;
+        Ok(match shorthand(c) {
+            Some((ranges, negated)) => Node::Class { ranges, negated },
+            None => Node::Char(literal(c))
+        })
+    }
+
+    /// After a `[`.
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or("unterminated [")?;
+            self.i += 1;
+            if c == ']' && !first {
+                return Ok(Node::Class { ranges, negated })
+            };
+            first = false;
+            let lo = if c == '\\' {
+                let e = self.peek().ok_or("unterminated [")?;
+                self.i += 1;
+                match shorthand(e) {
+                    Some((rs, false)) => { ranges.extend(rs); continue },
+                    Some((_, true)) => return Err(format!("\\{} isn't supported inside []", e)),
+                    None => literal(e)
+                }
+            }
+            else {
+                c
+            };
+            let hi = if self.peek() == Some('-') && self.s.get(self.i + 1).is_some_and(|c| *c != ']') {
+                self.i += 2;
+                match self.s[self.i - 1] {
+                    '\\' => {
+                        let e = self.peek().ok_or("unterminated [")?;
+                        self.i += 1;
+                        literal(e)
+                    },
+                    c => c
+                }
+            }
+            else {
+                lo
+            };
+            ranges.push((lo, hi))
+        }
+    }
+}
+
+/// `\d \w \s` and their negations.
+fn shorthand(c: char) -> Option<(Vec<(char, char)>, bool)> {
+    let digits = vec![('0', '9')];
+    let word = vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+    let space = vec![('\t', '\r'), (' ', ' ')];
+    Some(match c {
+        'd' => (digits, false),
+        'D' => (digits, true),
+        'w' => (word, false),
+        'W' => (word, true),
+        's' => (space, false),
+        'S' => (space, true),
+        _ => return None
+    })
+}
+
+fn literal(c: char) -> char {
+    match c {
+        't' => '\t',
+        'n' => '\n',
+        'r' => '\r',
+        c => c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(re: &str, line: &str) -> Option<Vec<(String, Field)>> {
+        Pattern::new(re).unwrap().parse(line).map(|l| l.fields)
+    }
+
+    fn text(name: &str, s: &str) -> (String, Field) {
+        (name.to_string(), Field::Text(s.to_string()))
+    }
+
+    #[test]
+    fn captures_named_groups() {
+        let f = fields(r"(?P<method>[A-Z]+) (?<path>\S+) (?P<status>\d+)", "GET /a/b 404 12ms");
+        assert_eq!(f, Some(vec![text("method", "GET"), text("path", "/a/b"), ("status".to_string(), Field::Num(404.0))]));
+    }
+
+    #[test]
+    fn leaves_out_groups_that_took_no_part() {
+        assert_eq!(fields("(?P<a>x)|(?P<b>y)", "y"), Some(vec![text("b", "y")]));
+    }
+
+    #[test]
+    fn finds_the_leftmost_match() {
+        assert_eq!(fields("(?P<n>b+)", "abbbc"), Some(vec![text("n", "bbb")]));
+        assert_eq!(fields("x", "abc"), None);
+    }
+
+    #[test]
+    fn prefers_alternatives_in_order() {
+        assert_eq!(fields("(?P<m>a|ab)", "ab"), Some(vec![text("m", "a")]));
+        assert_eq!(fields("(?P<m>a|ab)$", "ab"), Some(vec![text("m", "ab")]));
+    }
+
+    #[test]
+    fn greedy_and_lazy() {
+        assert_eq!(fields("<(?P<t>.*)>", "<a><b>"), Some(vec![text("t", "a><b")]));
+        assert_eq!(fields("<(?P<t>.*?)>", "<a><b>"), Some(vec![text("t", "a")]));
+        assert_eq!(fields("(?P<t>a??)a", "aa"), Some(vec![text("t", "")]));
+    }
+
+    #[test]
+    fn counted_repetition() {
+        assert_eq!(fields("^(?P<t>a{2,3})", "aaaa"), Some(vec![text("t", "aaa")]));
+        assert_eq!(fields("^(?P<t>a{2,3}?)", "aaaa"), Some(vec![text("t", "aa")]));
+        assert_eq!(fields("^a{3}$", "aa"), None);
+        assert_eq!(fields("^(?P<t>a{2,})$", "aaaaa"), Some(vec![text("t", "aaaaa")]));
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert_eq!(fields(r"(?P<w>[^ \d]+)", "12 ab3"), Some(vec![text("w", "ab")]));
+        assert_eq!(fields(r"(?P<w>[a-c_]+)", "xx_cab!"), Some(vec![text("w", "_cab")]));
+        assert_eq!(fields(r"(?P<d>\.\d)", "a.5"), Some(vec![("d".to_string(), Field::Num(0.5))]));
+        assert_eq!(fields(r"^\s*(?P<w>\w+)\W", "\t hi!"), Some(vec![text("w", "hi")]));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(fields("^b", "ab").is_none());
+        assert!(fields("a$", "ab").is_none());
+        assert!(fields("^$", "").is_some());
+    }
+
+    #[test]
+    fn last_iteration_captures() {
+        assert_eq!(fields("^(?:(?P<x>[a-z])-)+$", "a-b-c-"), Some(vec![text("x", "c")]));
+    }
+
+    #[test]
+    fn empty_iterations_terminate() {
+        assert_eq!(fields("^(?P<t>(a*)*)b", "aab"), Some(vec![text("t", "aa")]));
+        assert!(fields("^(a*)*$", "aac").is_none());
+        assert!(fields("^(a?){5}$", "aa").is_some());
+    }
+
+    #[test]
+    fn long_lines_dont_overflow() {
+        let line = "x".repeat(100_000);
+        assert_eq!(fields("^(?P<x>.*)$", &line), Some(vec![text("x", &line)]));
+        assert!(fields("^(?:x|y)+z$", &line).is_none());
+    }
+
+    #[test]
+    fn spans() {
+        let p = Pattern::new("[0-9]+|é").unwrap();
+        assert_eq!(p.spans("a12é3"), vec![(1, 3), (3, 5), (5, 6)]);
+        assert_eq!(Pattern::new("x*").unwrap().spans("axxb"), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        for re in ["(a", "a)", "*a", "[a", "a{2", "a{x}", r"[\D]", "(?=a)", "a\\"] {
+            assert!(Pattern::new(re).is_err(), "{}", re)
+        };
+        assert!(Pattern::new("(a{1000}){1000}").is_err());
+    }
+}
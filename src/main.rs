@@ -1,150 +1,1194 @@
-use std::fmt::Debug;
+
+use std::borrow::Borrow;
+use std::fmt::{self, Debug};
 use std::clone::Clone;
-use std::sync::Arc;
-use std::time::{Instant};
+use std::collections::HashSet;
+use std::io::{Cursor, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod algebra;
+mod alert;
+mod anomaly;
+mod broadcast;
+mod canon;
+mod captures;
+mod cdc;
+mod checkpoint;
+mod complexity;
+mod det;
+mod ehist;
+mod fallible;
+mod filter;
+mod forecast;
+#[cfg(feature = "http")]
+mod http;
+mod isolate;
+mod keyed;
+mod latency;
+mod lines;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod observe;
+mod ops;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod par;
+mod plan;
+mod progress;
+#[cfg(feature = "redis")]
+mod redis;
+mod replay;
+mod rev;
+mod runtime;
+mod sample;
+mod scan;
+mod smallvec;
+mod spectrum;
+#[cfg(feature = "symbolic")]
+mod symbolic;
+mod syslog;
+mod tap;
+mod testing;
+mod tokens;
+mod trace;
+mod transcript;
+mod validate;
+mod window;
+#[cfg(feature = "websocket")]
+mod ws;
 
+use alert::Alert;
+use captures::{epsilon_caps, Caps};
+use observe::Observer;
+use ops::{fold, Count, Max, Sum};
+use rev::RevLines;
+use smallvec::SmallVec;
+use window::{ApproxWindow, Window};
+
+#[allow(dead_code)]
 trait SplitExp<D,C> {
     type A;
     type B;
 
     fn f() -> QRE<D,Self::A>;
     fn g() -> QRE<D,Self::B>;
-    fn op(Self::A, Self::B) -> C;
+    fn op(a: Self::A, b: Self::B) -> C;
 }
 
-#[derive(Clone)]
+#[allow(clippy::upper_case_acronyms)]
 enum QRE<D,C> {
     Bot,
     Eps{c: C},
     Sat{phi: fn(&D) -> bool, op: fn(&D) -> C}, 
     Choice{v: Vec<QRE<D,C>>},
-    Split{f: Box<QRE<D,C>>, g: Box<QRE<D,C>>, op: fn(C,C) -> C},
+    Split{f: Arc<QRE<D,C>>, g: Arc<QRE<D,C>>, op: fn(C,C) -> C},
     //Split(Box<SplitExp<D,C>>),
-    Iter{init: Box<QRE<D,C>>, body: Box<QRE<D,C>>, op: fn(C,C) -> C},
-    App{f: Box<QRE<D,C>>, op: Arc<Fn(C) -> C>},
-    Combine{f: Box<QRE<D,C>>, g: Box<QRE<D,C>>, op: fn(C,C) -> C},    
+    Iter{init: Arc<QRE<D,C>>, body: Arc<QRE<D,C>>, op: fn(C,C) -> C},
+    App{f: Arc<QRE<D,C>>, op: AppOp<C>},
+    Combine{f: Arc<QRE<D,C>>, g: Arc<QRE<D,C>>, op: fn(C,C) -> C},    
+    // `f`, with its value reported under `name` by `Solve::captures`.
+    Tag{name: &'static str, f: Arc<QRE<D,C>>},
+    // Only in residuals: `f`, where the part of the match already consumed
+    // captured `caps` (coming after `f`'s own match if `later`, i.e. when
+    // reading in reverse).
+    Cap{f: Arc<QRE<D,C>>, caps: Caps<C>, later: bool},
+    // `body`, calling `action` on every value it produces, i.e. each time
+    // it completes a match. The value itself passes through unchanged.
+    Trigger{body: Arc<QRE<D,C>>, action: Action<C>},
+    // `Sat` and `Combine` with ops that can fail; see `fallible`.
+    TrySat{phi: fn(&D) -> bool, op: fn(&D) -> Result<C, fallible::Error>},
+    TryCombine{f: Arc<QRE<D,C>>, g: Arc<QRE<D,C>>, op: fn(C,C) -> Result<C, fallible::Error>},
 }
 
 use self::QRE::*;
 
-fn epsilon<D,C>(q: &QRE<D,C>) -> Vec<C> where C: Clone {
+/// What an `App` node applies. Derivatives only ever build partial
+/// applications of a query's binary ops, so those are kept as data rather
+/// than closures; that way every function in a residual can be traced back
+/// to the query (see `checkpoint`).
+enum AppOp<C> {
+    Fn(Arc<dyn Fn(C) -> C + Send + Sync>),
+    // x => op(c, x)
+    Left(fn(C,C) -> C, C),
+    // x => op(x, c)
+    Right(fn(C,C) -> C, C),
+    // The first, then the second; see `canon`.
+    Then(Box<AppOp<C>>, Box<AppOp<C>>),
+    // A user-supplied op that can fail; see `fallible`.
+    Try(Arc<dyn Fn(C) -> Result<C, fallible::Error> + Send + Sync>),
+}
+
+/// What a `Trigger` does with its body's values.
+type Action<C> = Arc<dyn Fn(&C) + Send + Sync>;
+
+impl <C: Clone> AppOp<C> {
+    /// `None` if the op failed (having `raise`d the error).
+    fn apply(&self, x: C) -> Option<C> {
+        match self {
+            AppOp::Fn(op) => Some(op(x)),
+            AppOp::Left(op, c) => Some(op(c.clone(), x)),
+            AppOp::Right(op, c) => Some(op(x, c.clone())),
+            AppOp::Then(first, then) => then.apply(first.apply(x)?),
+            AppOp::Try(op) => op(x).map_err(fallible::raise).ok(),
+        }
+    }
+}
+
+impl <C: Clone> Clone for AppOp<C> {
+    fn clone(&self) -> Self {
+        match self {
+            AppOp::Fn(op) => AppOp::Fn(op.clone()),
+            AppOp::Left(op, c) => AppOp::Left(*op, c.clone()),
+            AppOp::Right(op, c) => AppOp::Right(*op, c.clone()),
+            AppOp::Then(first, then) => AppOp::Then(first.clone(), then.clone()),
+            AppOp::Try(op) => AppOp::Try(op.clone()),
+        }
+    }
+}
+
+// Not derived: the domain only appears behind fn pointers, so cloning a
+// query shouldn't require `D: Clone`.
+impl <D,C: Clone> Clone for QRE<D,C> {
+    fn clone(&self) -> Self {
+        match self {
+            Bot => Bot,
+            Eps{c} => Eps{c: c.clone()},
+            Sat{phi, op} => Sat{phi: *phi, op: *op},
+            Choice{v} => Choice{v: v.clone()},
+            Split{f, g, op} => Split{f: f.clone(), g: g.clone(), op: *op},
+            Iter{init, body, op} => Iter{init: init.clone(), body: body.clone(), op: *op},
+            App{f, op} => App{f: f.clone(), op: op.clone()},
+            Combine{f, g, op} => Combine{f: f.clone(), g: g.clone(), op: *op},
+            Tag{name, f} => Tag{name, f: f.clone()},
+            Cap{f, caps, later} => Cap{f: f.clone(), caps: caps.clone(), later: *later},
+            Trigger{body, action} => Trigger{body: body.clone(), action: action.clone()},
+            TrySat{phi, op} => TrySat{phi: *phi, op: *op},
+            TryCombine{f, g, op} => TryCombine{f: f.clone(), g: g.clone(), op: *op},
+        }
+    }
+}
+
+fn epsilon<D,C>(q: &QRE<D,C>) -> SmallVec<C> where C: Clone {
     match q {
-        Bot => vec![],
-        Eps{c} => vec![c.clone()],
-        Sat{..} => vec![],
+        Bot => SmallVec::new(),
+        Eps{c} => SmallVec::One([c.clone()]),
+        Sat{..} | TrySat{..} => SmallVec::new(),
         Choice{v} => {
-            let mut vnew = Vec::new();
+            let mut vnew = SmallVec::new();
             for q in v {
-                vnew.append(&mut epsilon(&*q))
+                vnew.extend(epsilon(q))
             };
             vnew
         },
         Split{f, g, op} => {
-            let mut acc = vec![];
-            for x in &epsilon(&*f)[..] {
-                for y in &epsilon(&*g)[..] {
+            let mut acc = SmallVec::new();
+            let ys = epsilon(g);
+            for x in &epsilon(f)[..] {
+                for y in &ys[..] {
                     acc.push(op(x.clone(), y.clone()))
                 }
             };
             acc
         },
-        Iter{init, ..} => epsilon(&*init),
+        Iter{init, ..} => epsilon(init),
         App{f, op} => {
-            let mut acc = vec![];
-            for x in &epsilon(&*f)[..] {
-                acc.push(op(x.clone()))
+            let mut acc = SmallVec::new();
+            for x in epsilon(f) {
+                if let Some(y) = op.apply(x) {
+                    acc.push(y)
+                }
             };
             acc
         },
         Combine{f, g, op} => {
-            let mut acc = vec![];
-            for x in &epsilon(&*f)[..] {
-                for y in &epsilon(&*g)[..] {
+            let mut acc = SmallVec::new();
+            let ys = epsilon(g);
+            for x in &epsilon(f)[..] {
+                for y in &ys[..] {
                     acc.push(op(x.clone(), y.clone()))
                 }
             };
             acc
+        },
+        TryCombine{f, g, op} => {
+            let mut acc = SmallVec::new();
+            let ys = epsilon(g);
+            for x in &epsilon(f)[..] {
+                for y in &ys[..] {
+                    match op(x.clone(), y.clone()) {
+                        Ok(c) => acc.push(c),
+                        Err(e) => fallible::raise(e)
+                    }
+                }
+            };
+            acc
+        },
+        Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => epsilon(f),
+    }
+}
+
+/// Bound on how many spare buffers/nodes a `Pool` hangs on to, so a
+/// burst in the working set doesn't pin its memory forever.
+const POOL_CAP: usize = 1 << 12;
+
+/// Free-lists of `Choice` buffers and `Arc` nodes recovered as the old
+/// generation of residuals is consumed, reused for the next one.
+struct Pool<D,C> {
+    bufs: Vec<Vec<QRE<D,C>>>,
+    nodes: Vec<Arc<QRE<D,C>>>,
+    // Whether derivatives need to remember what finished sub-matches
+    // captured; only worth it if the query has `Tag`s.
+    captures: bool,
+    // Whether `canon` may merge `Iter`s; see `canon::has_hooks`.
+    factor: bool,
+}
+
+impl <D,C> Pool<D,C> {
+    fn new() -> Self {
+        Self { bufs: Vec::new(), nodes: Vec::new(), captures: false, factor: false }
+    }
+
+    fn buf(&mut self) -> Vec<QRE<D,C>> {
+        self.bufs.pop().unwrap_or_default()
+    }
+
+    fn node(&mut self, q: QRE<D,C>) -> Arc<QRE<D,C>> {
+        match self.nodes.pop() {
+            Some(mut a) => {
+                // Only uniquely owned nodes are ever put on the free-list.
+                *Arc::get_mut(&mut a).unwrap() = q;
+                a
+            },
+            None => Arc::new(q)
+        }
+    }
+
+    /// Wraps derivatives as a single node. Most derivatives come out as a
+    /// single residual, which is stored as-is rather than as a one-element
+    /// `Choice`, so the buffer goes straight back on the free-list.
+    fn choice(&mut self, mut v: Vec<QRE<D,C>>) -> Arc<QRE<D,C>> {
+        if v.len() == 1 {
+            let q = v.pop().unwrap();
+            self.recycle_buf(v);
+            self.node(q)
+        }
+        else {
+            self.node(Choice{v})
+        }
+    }
+
+    fn recycle_buf(&mut self, v: Vec<QRE<D,C>>) {
+        if self.bufs.len() < POOL_CAP {
+            self.bufs.push(v)
+        }
+    }
+
+    /// `a` must be uniquely owned and already emptied.
+    fn recycle_node(&mut self, a: Arc<QRE<D,C>>) {
+        if self.nodes.len() < POOL_CAP {
+            self.nodes.push(a)
+        }
+    }
+}
+
+/// Pushes the residuals for `f` having just finished: for each way `f`
+/// can finish, the rest of the stream goes to `g`.
+fn deriv_split_done<D,C>(f: &QRE<D,C>, g: &QRE<D,C>, op: fn(C,C) -> C, d: &D,
+                         pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>)
+    where C: Clone + Send + Sync + 'static {
+    if pool.captures {
+        return deriv_split_done_caps(f, g, op, d, pool, out)
+    };
+    let eps = epsilon(f);
+    if !eps.is_empty() {
+        // One derivative of g, shared by every way f can finish.
+        let mut vg = pool.buf();
+        deriv(g, d, pool, &mut vg);
+        let dg = pool.choice(vg);
+        for a in eps {
+            out.push(App{f: dg.clone(), op: AppOp::Left(op, a)})
+        }
+    }
+}
+
+/// `deriv_split_done`, keeping what `f` captured alongside `g`'s residual.
+fn deriv_split_done_caps<D,C>(f: &QRE<D,C>, g: &QRE<D,C>, op: fn(C,C) -> C, d: &D,
+                              pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>)
+    where C: Clone + Send + Sync + 'static {
+    let eps = epsilon_caps(f);
+    if !eps.is_empty() {
+        let mut vg = pool.buf();
+        deriv(g, d, pool, &mut vg);
+        let dg = pool.choice(vg);
+        for (a, caps) in eps {
+            let r = App{f: dg.clone(), op: AppOp::Left(op, a)};
+            out.push(with_caps(r, caps, false, pool))
+        }
+    }
+}
+
+fn with_caps<D,C>(q: QRE<D,C>, caps: Caps<C>, later: bool, pool: &mut Pool<D,C>) -> QRE<D,C> {
+    if caps.is_empty() { q } else { Cap{f: pool.node(q), caps, later} }
+}
+
+/// Pushes the residuals for `init` having just finished and a new
+/// iteration of `body` starting with `d`.
+fn deriv_iter_done<D,C>(init: &QRE<D,C>, body: &Arc<QRE<D,C>>, op: fn(C,C) -> C, d: &D,
+                        pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>)
+    where C: Clone + Send + Sync + 'static {
+    if pool.captures {
+        return deriv_iter_done_caps(init, body, op, d, pool, out)
+    };
+    let eps = epsilon(init);
+    if !eps.is_empty() {
+        let mut vbody = pool.buf();
+        deriv(body, d, pool, &mut vbody);
+        let dbody = pool.choice(vbody);
+        for b in eps {
+            let init = pool.node(App{f: dbody.clone(), op: AppOp::Left(op, b)});
+            out.push(Iter{init, body: body.clone(), op})
         }
     }
 }
 
-fn deriv<D,C: 'static>(q: QRE<D,C>, d: &D) -> Vec<QRE<D,C>> where D: Clone, C: Clone {
+/// `deriv_iter_done`, keeping what `init` captured alongside the new
+/// iteration's residual.
+fn deriv_iter_done_caps<D,C>(init: &QRE<D,C>, body: &Arc<QRE<D,C>>, op: fn(C,C) -> C, d: &D,
+                             pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>)
+    where C: Clone + Send + Sync + 'static {
+    let eps = epsilon_caps(init);
+    if !eps.is_empty() {
+        let mut vbody = pool.buf();
+        deriv(body, d, pool, &mut vbody);
+        let dbody = pool.choice(vbody);
+        for (b, caps) in eps {
+            let r = App{f: dbody.clone(), op: AppOp::Left(op, b)};
+            let init = with_caps(r, caps, false, pool);
+            let init = pool.node(init);
+            out.push(Iter{init, body: body.clone(), op})
+        }
+    }
+}
+
+/// Pushes the derivatives of `q` by `d` onto `out`.
+fn deriv<D,C>(q: &QRE<D,C>, d: &D, pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>)
+    where C: Clone + Send + Sync + 'static {
     match q {
-        Bot => vec![Bot],
-        Eps{..} => vec![Bot],
-        Sat{phi, op} if phi(d) => vec![Eps{c: op(d)}],
-        Sat{..} => vec![Bot],        
+        Bot => out.push(Bot),
+        Eps{..} => out.push(Bot),
+        Sat{phi, op} if phi(d) => out.push(Eps{c: op(d)}),
+        Sat{..} => out.push(Bot),
+        TrySat{phi, op} if phi(d) => match op(d) {
+            Ok(c) => out.push(Eps{c}),
+            Err(e) => {
+                fallible::raise(e);
+                out.push(Bot)
+            }
+        },
+        TrySat{..} => out.push(Bot),
         Choice{v} => {
-            let mut vnew = Vec::new();
             for q in v {
-                vnew.append(&mut deriv(q, d))
-            };
-            vnew
+                deriv(q, d, pool, out)
+            }
         },
         Split{f, g, op} => {
-            let mut vnew = Vec::new();
-            for a in epsilon(&*f) {
-                vnew.push(App{f: Box::new(Choice{v: deriv((*g).clone(), d)}),
-                              op: Arc::new(move |x| op(a.clone(), x))})
+            deriv_split_done(f, g, *op, d, pool, out);
+            let mut vf = pool.buf();
+            deriv(f, d, pool, &mut vf);
+            out.push(
+                Split{f: pool.choice(vf),
+                      g: g.clone(),
+                      op: *op})
+        },
+        Iter{init, body, op} => {
+            deriv_iter_done(init, body, *op, d, pool, out);
+            let mut vinit = pool.buf();
+            deriv(init, d, pool, &mut vinit);
+            out.push(
+                Iter{init: pool.choice(vinit),
+                     body: body.clone(),
+                     op: *op})
+        },
+        App{f, op} => {
+            let mut vf = pool.buf();
+            deriv(f, d, pool, &mut vf);
+            out.push(App{f: pool.choice(vf), op: op.clone()})
+        },
+        Combine{f, g, op} => {
+            let mut vf = pool.buf();
+            deriv(f, d, pool, &mut vf);
+            let mut vg = pool.buf();
+            deriv(g, d, pool, &mut vg);
+            out.push(Combine{f: pool.choice(vf), g: pool.choice(vg), op: *op})
+        },
+        TryCombine{f, g, op} => {
+            let mut vf = pool.buf();
+            deriv(f, d, pool, &mut vf);
+            let mut vg = pool.buf();
+            deriv(g, d, pool, &mut vg);
+            out.push(TryCombine{f: pool.choice(vf), g: pool.choice(vg), op: *op})
+        },
+        Tag{name, f} => {
+            let mut vf = pool.buf();
+            deriv(f, d, pool, &mut vf);
+            out.push(Tag{name, f: pool.choice(vf)})
+        },
+        Cap{f, caps, later} => {
+            let mut vf = pool.buf();
+            deriv(f, d, pool, &mut vf);
+            out.push(Cap{f: pool.choice(vf), caps: caps.clone(), later: *later})
+        },
+        Trigger{body, action} => {
+            let mut vbody = pool.buf();
+            deriv(body, d, pool, &mut vbody);
+            let body = pool.choice(vbody);
+            fire(&body, action);
+            out.push(Trigger{body, action: action.clone()})
+        },
+    }
+}
+
+/// Runs a `Trigger`'s action on each match its freshly derived `body` has
+/// just completed. A match that finishes on several branches at once
+/// (say, of a `Choice`) fires once per branch, including branches the
+/// query as a whole later rules out.
+fn fire<D,C: Clone>(body: &QRE<D,C>, action: &Action<C>) {
+    for c in epsilon(body) {
+        action(&c)
+    }
+}
+
+/// Like `deriv`, but consumes `q`: children nobody else holds are derived
+/// in place (reusing their allocation), and the children a derivative
+/// leaves untouched -- `Split`'s `g`, `Iter`'s `body`, the ops -- are
+/// moved into the new node rather than copied.
+fn deriv_owned<D,C>(q: QRE<D,C>, d: &D, pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>)
+    where C: Clone + Send + Sync + 'static {
+    match q {
+        Choice{mut v} => {
+            for q in v.drain(..) {
+                deriv_owned(q, d, pool, out)
             };
-            vnew.push(
-                Split{f: Box::new(Choice{v: deriv(*f, d)}),
-                      g: g,
-                      op: op});
-            vnew
+            pool.recycle_buf(v)
+        },
+        Split{f, g, op} => {
+            deriv_split_done(&f, &g, op, d, pool, out);
+            let f = deriv_node(f, d, pool);
+            out.push(Split{f, g, op})
         },
         Iter{init, body, op} => {
-            let mut vnew = Vec::new();
-            for b in epsilon(&*init) {
-                vnew.push(Iter{
-                    init: Box::new(App{f: Box::new(Choice{v: deriv((*body).clone(), d)}),
-                                       op: Arc::new(move |x| op(b.clone(), x))}),
-                    body: body.clone(),
-                    op: op})
+            deriv_iter_done(&init, &body, op, d, pool, out);
+            let init = deriv_node(init, d, pool);
+            out.push(Iter{init, body, op})
+        },
+        App{f, op} => {
+            let f = deriv_node(f, d, pool);
+            out.push(App{f, op})
+        },
+        Combine{f, g, op} => {
+            let f = deriv_node(f, d, pool);
+            let g = deriv_node(g, d, pool);
+            out.push(Combine{f, g, op})
+        },
+        TryCombine{f, g, op} => {
+            let f = deriv_node(f, d, pool);
+            let g = deriv_node(g, d, pool);
+            out.push(TryCombine{f, g, op})
+        },
+        Tag{name, f} => {
+            let f = deriv_node(f, d, pool);
+            out.push(Tag{name, f})
+        },
+        Cap{f, caps, later} => {
+            let f = deriv_node(f, d, pool);
+            out.push(Cap{f, caps, later})
+        },
+        Trigger{body, action} => {
+            let body = deriv_node(body, d, pool);
+            fire(&body, &action);
+            out.push(Trigger{body, action})
+        },
+        q => deriv(&q, d, pool, out)
+    }
+}
+
+/// Derives a child, copying it only if it's shared.
+fn deriv_node<D,C>(mut a: Arc<QRE<D,C>>, d: &D, pool: &mut Pool<D,C>) -> Arc<QRE<D,C>>
+    where C: Clone + Send + Sync + 'static {
+    let mut v = pool.buf();
+    match Arc::get_mut(&mut a) {
+        Some(q) => {
+            let q = std::mem::replace(q, Bot);
+            deriv_owned(q, d, pool, &mut v);
+            pool.recycle_node(a)
+        },
+        None => deriv(&a, d, pool, &mut v)
+    };
+    pool.choice(v)
+}
+
+/// Pushes the right-derivatives ("co-derivatives") of `q` by `d` onto
+/// `out`: the residuals for `q` matching a stream whose *last* element is
+/// `d`. Feeding a stream to `rderiv` back to front gives the same outputs
+/// as feeding it to `deriv` front to back.
+fn rderiv<D,C>(q: &QRE<D,C>, d: &D, pool: &mut Pool<D,C>, out: &mut Vec<QRE<D,C>>)
+    where C: Clone + Send + Sync + 'static {
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => deriv(q, d, pool, out),
+        Choice{v} => {
+            for q in v {
+                rderiv(q, d, pool, out)
+            }
+        },
+        Split{f, g, op} if pool.captures => {
+            let eps = epsilon_caps(g);
+            if !eps.is_empty() {
+                let mut vf = pool.buf();
+                rderiv(f, d, pool, &mut vf);
+                let df = pool.choice(vf);
+                for (b, caps) in eps {
+                    let r = App{f: df.clone(), op: AppOp::Right(*op, b)};
+                    out.push(with_caps(r, caps, true, pool))
+                }
             };
-            vnew.push(
-                Iter{init: Box::new(Choice{v: deriv(*init, d)}),
-                     body: body,
-                     op: op});
-            vnew
+            let mut vg = pool.buf();
+            rderiv(g, d, pool, &mut vg);
+            out.push(
+                Split{f: f.clone(),
+                      g: pool.choice(vg),
+                      op: *op})
+        },
+        Split{f, g, op} => {
+            let eps = epsilon(g);
+            if !eps.is_empty() {
+                // g matched nothing, so d is f's last element.
+                let mut vf = pool.buf();
+                rderiv(f, d, pool, &mut vf);
+                let df = pool.choice(vf);
+                for b in eps {
+                    out.push(App{f: df.clone(), op: AppOp::Right(*op, b)})
+                }
+            };
+            let mut vg = pool.buf();
+            rderiv(g, d, pool, &mut vg);
+            out.push(
+                Split{f: f.clone(),
+                      g: pool.choice(vg),
+                      op: *op})
+        },
+        Iter{init, body, op} => {
+            // Either d ends the last iteration of body...
+            let mut vbody = pool.buf();
+            rderiv(body, d, pool, &mut vbody);
+            out.push(
+                Split{f: pool.node(q.clone()),
+                      g: pool.choice(vbody),
+                      op: *op});
+            // ...or there were no iterations and d ends init.
+            rderiv(init, d, pool, out)
+        },
+        App{f, op} => {
+            let mut vf = pool.buf();
+            rderiv(f, d, pool, &mut vf);
+            out.push(App{f: pool.choice(vf), op: op.clone()})
         },
-        App{f, op} => vec![App{f: Box::new(Choice{v: deriv(*f, d)}), op: op}], 
-        Combine{f, g, op} =>
-            vec![Combine{f: Box::new(Choice{v: deriv(*f, d)}),
-                         g: Box::new(Choice{v: deriv(*g, d)}),
-                         op: op}],
+        Combine{f, g, op} => {
+            let mut vf = pool.buf();
+            rderiv(f, d, pool, &mut vf);
+            let mut vg = pool.buf();
+            rderiv(g, d, pool, &mut vg);
+            out.push(Combine{f: pool.choice(vf), g: pool.choice(vg), op: *op})
+        },
+        TryCombine{f, g, op} => {
+            let mut vf = pool.buf();
+            rderiv(f, d, pool, &mut vf);
+            let mut vg = pool.buf();
+            rderiv(g, d, pool, &mut vg);
+            out.push(TryCombine{f: pool.choice(vf), g: pool.choice(vg), op: *op})
+        },
+        Tag{name, f} => {
+            let mut vf = pool.buf();
+            rderiv(f, d, pool, &mut vf);
+            out.push(Tag{name, f: pool.choice(vf)})
+        },
+        Cap{f, caps, later} => {
+            let mut vf = pool.buf();
+            rderiv(f, d, pool, &mut vf);
+            out.push(Cap{f: pool.choice(vf), caps: caps.clone(), later: *later})
+        },
+        Trigger{body, action} => {
+            let mut vbody = pool.buf();
+            rderiv(body, d, pool, &mut vbody);
+            let body = pool.choice(vbody);
+            fire(&body, action);
+            out.push(Trigger{body, action: action.clone()})
+        },
+    }
+}
+
+/// What a query that matches single elements makes of one element.
+enum One<C> {
+    NoMatch,
+    Value(C),
+    Ambiguous,
+}
+
+/// Whether every match of `q` is exactly one element long.
+fn single_element<D,C>(q: &QRE<D,C>) -> bool {
+    match q {
+        Bot | Sat{..} | TrySat{..} => true,
+        Eps{..} | Split{..} | Iter{..} => false,
+        Choice{v} => v.iter().all(single_element),
+        App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => single_element(f),
+        Combine{f, g, ..} | TryCombine{f, g, ..} => single_element(f) && single_element(g),
+    }
+}
+
+fn one<D,C>(body: &QRE<D,C>, d: &D, pool: &mut Pool<D,C>, buf: &mut Vec<QRE<D,C>>) -> One<C>
+    where C: Clone + Send + Sync + 'static {
+    deriv(body, d, pool, buf);
+    let mut cs = Vec::new();
+    for q in buf.drain(..) {
+        cs.extend(epsilon(&q))
+    };
+    match cs.len() {
+        0 => One::NoMatch,
+        1 => One::Value(cs.pop().unwrap()),
+        _ => One::Ambiguous
     }
 }
 
+/// How much structure the residual DAG actually shares: `total_refs`
+/// counts every edge into a node (plus one per root), `unique_nodes`
+/// counts each distinct node once.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+struct Sharing {
+    pub unique_nodes: u64,
+    pub total_refs: u64,
+}
+
+impl Sharing {
+    /// Average number of references per node; 1.0 means no sharing.
+    pub fn ratio(&self) -> f64 {
+        if self.unique_nodes == 0 { 1.0 }
+        else { self.total_refs as f64 / self.unique_nodes as f64 }
+    }
+}
+
+fn sharing_rec<D,C>(q: &QRE<D,C>, seen: &mut HashSet<*const QRE<D,C>>, acc: &mut Sharing) {
+    acc.total_refs += 1;
+    if !seen.insert(q as *const QRE<D,C>) {
+        return
+    }
+    acc.unique_nodes += 1;
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => (),
+        Choice{v} => {
+            for q in v {
+                sharing_rec(q, seen, acc)
+            }
+        },
+        Split{f, g, ..} | Combine{f, g, ..} | TryCombine{f, g, ..} => {
+            sharing_rec(f, seen, acc);
+            sharing_rec(g, seen, acc)
+        },
+        Iter{init, body, ..} => {
+            sharing_rec(init, seen, acc);
+            sharing_rec(body, seen, acc)
+        },
+        App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => sharing_rec(f, seen, acc),
+    }
+}
+
+/// Walks every node reachable from `roots`. This is O(unique nodes), so
+/// it's meant for reporting, not for calling on every update.
+fn sharing<D,C>(roots: &[QRE<D,C>]) -> Sharing {
+    let mut seen = HashSet::new();
+    let mut acc = Sharing::default();
+    for q in roots {
+        sharing_rec(q, &mut seen, &mut acc)
+    };
+    acc
+}
+
+#[derive(Clone,Copy,Debug)]
+struct Stats {
+    pub workingset: u64,
+    pub max_workingset: u64,
+    pub sharing: Sharing,
+    // See `Solve::record_latency`.
+    pub latency: Option<latency::Latency>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "workingset = {}, max_workingset = {}, nodes = {}, refs = {} ({:.2} refs/node)",
+               self.workingset, self.max_workingset,
+               self.sharing.unique_nodes, self.sharing.total_refs, self.sharing.ratio())?;
+        if let Some(l) = self.latency {
+            write!(f, ", p50 = {:?}, p99 = {:?}, max = {:?}", l.p50, l.p99, l.max)?
+        };
+        Ok(())
+    }
+}
+
+/// How `Solve::migrate` treats the state built up under the old query.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+enum MigrationPolicy {
+    /// Start the new query from scratch.
+    #[allow(dead_code)]
+    Restart,
+    /// Carry the old query's current output into the new one, which has to
+    /// be an `Iter`: its `init` is replaced by that value, so e.g. a running
+    /// aggregate keeps its history.
+    Carry,
+    /// Start the new query from scratch, but keep running the old one
+    /// alongside it and answer `output` from the old one until the new one
+    /// has seen `warmup` elements.
+    #[allow(dead_code)]
+    Parallel{warmup: u64},
+}
+
+/// Residual trees are immutable once shared, so cloning a `Solve` is O(1):
+/// the clone and the original share the current state, and each copies
+/// only the parts of it that its own updates rewrite.
 struct Solve<D,C: 'static> {
-    pub state: Vec<QRE<D,C>>,
+    // The query as given (or as rebuilt by `migrate`).
+    query: QRE<D,C>,
+    pub state: Arc<Vec<QRE<D,C>>>,
+    // The previous generation's buffer, kept around for its capacity.
+    next: Vec<QRE<D,C>>,
+    pool: Pool<D,C>,
     max_workingset: u64,
+    // Elements arrive last-to-first; see `new_reverse`.
+    reverse: bool,
+    // The query being replaced under `MigrationPolicy::Parallel`, and how
+    // many more elements it has to answer for.
+    retiring: Option<(Box<Solve<D,C>>, u64)>,
+    // See `on_output`.
+    alerts: Vec<Alert<C>>,
+    // See `observe`.
+    observers: Vec<Observer<C>>,
+    // Whether updates take `det::step`'s single-residual path.
+    planner: plan::Planner,
+    // See `on_error`.
+    errors: fallible::Errors,
+    // See `isolate_panics`: the policy, how many panics it has caught,
+    // and whether one took the whole solver down.
+    isolation: Option<isolate::Poison>,
+    panics: u64,
+    poisoned: bool,
+    // See `record_latency`.
+    latency: Option<Box<latency::Histogram>>,
+    // See `record`.
+    journal: Option<Box<replay::Journal<D,C>>>,
+    // See `new_traced`.
+    trace: Option<trace::Trace<C>>,
+    // See `trace_to`.
+    transcript: Option<transcript::Transcript<D>>,
+}
+
+impl <D,C: Clone> Clone for Solve<D,C> {
+    fn clone(&self) -> Self {
+        Self {
+            query: self.query.clone(),
+            state: self.state.clone(),
+            next: Vec::new(),
+            pool: Pool { captures: self.pool.captures, factor: self.pool.factor, ..Pool::new() },
+            max_workingset: self.max_workingset,
+            reverse: self.reverse,
+            retiring: self.retiring.clone(),
+            alerts: self.alerts.clone(),
+            observers: self.observers.clone(),
+            planner: self.planner.clone(),
+            errors: self.errors.clone(),
+            isolation: self.isolation,
+            panics: self.panics,
+            poisoned: self.poisoned,
+            latency: self.latency.clone(),
+            journal: self.journal.clone(),
+            trace: self.trace.clone(),
+            transcript: self.transcript.clone()
+        }
+    }
 }
 
-impl <D,C> Solve<D,C> where D: Clone, C: Clone + Debug {
+impl <D,C> Solve<D,C> where C: Clone + Debug + Send + Sync {
     pub fn new(q: QRE<D,C>) -> Self {
+        let q = canon::prune(q);
         Self {
-            state: vec![q],
-            max_workingset: 0
+            planner: plan::Planner::new(&q),
+            pool: Pool { captures: captures::has_tags(&q), factor: !canon::has_hooks(&q), ..Pool::new() },
+            query: q.clone(),
+            state: Arc::new(vec![q]),
+            next: Vec::new(),
+            max_workingset: 0,
+            reverse: false,
+            retiring: None,
+            alerts: Vec::new(),
+            observers: Vec::new(),
+            errors: fallible::Errors::new(),
+            isolation: None,
+            panics: 0,
+            poisoned: false,
+            latency: None,
+            journal: None,
+            trace: None,
+            transcript: None
+        }
+    }
+
+    /// A solver that is fed the stream back to front, for queries that are
+    /// naturally about a stream's suffix (e.g., "the last run of ...").
+    /// `output` is the value `q` would have on the elements seen so far
+    /// read in their original order.
+    pub fn new_reverse(q: QRE<D,C>) -> Self {
+        Self { reverse: true, ..Self::new(q) }
+    }
+
+    /// A solver that records every value its `Iter`s and `Combine`s
+    /// produce, for `trace`: when an output looks wrong, the accumulators
+    /// that led to it. That includes values on branches the query later
+    /// rules out, as with `tap`. Tracing keeps updates off the
+    /// deterministic backend, clones of the solver add to the same trace,
+    /// and `migrate` stops it.
+    pub fn new_traced(q: QRE<D,C>) -> Self {
+        let (q, trace) = trace::traced(&q);
+        Self { trace: Some(trace), ..Self::new(q) }
+    }
+
+    /// Replaces the query mid-stream, keeping as much of the old state as
+    /// `policy` allows. Fails, leaving the solver as it was, if the state
+    /// can't be carried over.
+    pub fn migrate(&mut self, q: QRE<D,C>, policy: MigrationPolicy) -> Result<(), String> {
+        let q = match policy {
+            MigrationPolicy::Carry => match q {
+                Iter{body, op, ..} => {
+                    let mut cs = self.outputs();
+                    if cs.len() != 1 {
+                        return Err("undefined".to_string())
+                    };
+                    Iter{init: Arc::new(Eps{c: cs.pop().unwrap()}), body, op}
+                },
+                _ => return Err("can only carry state into an Iter".to_string())
+            },
+            _ => q
+        };
+        let mut old = std::mem::replace(self, Self::new(q));
+        self.reverse = old.reverse;
+        self.max_workingset = old.max_workingset;
+        self.alerts = std::mem::take(&mut old.alerts);
+        self.observers = std::mem::take(&mut old.observers);
+        self.errors.policy = old.errors.policy;
+        self.isolation = old.isolation;
+        self.latency = old.latency.take();
+        self.transcript = old.transcript.take();
+        if let MigrationPolicy::Parallel{warmup} = policy {
+            if warmup > 0 {
+                // Drop anything the old solver was itself retiring.
+                old.retiring = None;
+                self.retiring = Some((Box::new(old), warmup))
+            }
+        };
+        Ok(())
+    }
+
+    /// Feeds the solver the next element. It's only borrowed (`&D` will
+    /// do as well as `D`), so big records needn't be copied: residuals
+    /// keep just the values the query's ops project out of them.
+    pub fn update<B: Borrow<D>>(&mut self, d: B) {
+        let d = d.borrow();
+        let before = self.transcript.as_ref().map(|_| (self.planner.backend(), transcript::residuals(&self.state)));
+        match self.latency {
+            None => self.advance(d),
+            Some(_) => {
+                let start = Instant::now();
+                self.advance(d);
+                if let Some(h) = self.latency.as_mut() {
+                    h.record(start.elapsed())
+                }
+            }
+        };
+        if let Some((backend, before)) = before {
+            self.transcribe(d, backend, before)
+        };
+        replay::append(self, d);
+        if let Some(t) = &self.trace {
+            t.advance()
+        }
+    }
+
+    /// Writes an account of every update from now on to `w`: the element,
+    /// the residuals before and after deriving them, and the values they
+    /// would give if the stream ended there. Meant for small examples --
+    /// write-ups, teaching, bug reports about what the evaluator does --
+    /// since it writes out the whole working set twice per update. Clones
+    /// of the solver write to the same `w`, and `migrate` keeps writing to
+    /// it. Stops at the first write that fails.
+    pub fn trace_to<W: Write + Send + 'static>(&mut self, w: W, format: transcript::Format) where D: Debug {
+        self.transcript = Some(transcript::Transcript::new(w, format))
+    }
+
+    fn transcribe(&mut self, d: &D, backend: plan::Backend, before: Vec<String>) {
+        let after = transcript::residuals(&self.state);
+        // Any op that fails here will fail again for `output`.
+        let epsilons = match self.isolation {
+            None => Some(self.outputs()),
+            Some(_) => isolate::catch(|| self.outputs())
+        };
+        fallible::take();
+        let epsilons = epsilons.unwrap_or_default().iter().map(|c| format!("{:?}", c)).collect();
+        if let Some(t) = self.transcript.as_mut() {
+            if t.write(d, backend, before, after, epsilons).is_err() {
+                self.transcript = None
+            }
         }
     }
 
-    pub fn update(&mut self, d: D) -> () {
-        let mut vnew = Vec::new();
+    fn advance(&mut self, d: &D) {
+        if self.errors.aborted || self.poisoned {
+            return
+        };
+        if let Some((mut old, n)) = self.retiring.take() {
+            old.update(d);
+            if n > 1 {
+                self.retiring = Some((old, n - 1))
+            }
+        };
+        // Anything raised outside a solver isn't ours to report.
+        fallible::take();
+        let before = match self.errors.policy {
+            fallible::Policy::Skip => Some(self.state.clone()),
+            _ => None
+        };
+        match self.isolation {
+            None => self.derive(d),
+            Some(poison) => {
+                let panicked = self.derive_isolated(d);
+                if panicked > 0 && self.poison(panicked, poison) {
+                    return
+                }
+            }
+        };
+        if let Some(raised) = fallible::take() {
+            match (self.errors.record(raised), before) {
+                (fallible::Policy::NoMatch, _) => (),
+                (fallible::Policy::Skip, Some(state)) => {
+                    self.state = state;
+                    return
+                },
+                _ => return
+            }
+        };
+        match self.isolation {
+            None => self.finish_update(),
+            Some(poison) => if isolate::catch(|| self.finish_update()).is_none() {
+                self.poison(1, poison);
+            }
+        };
+        // Alerts and observers read the output, which can fail too.
+        if let Some(raised) = fallible::take() {
+            self.errors.record(raised);
+        }
+    }
+
+    fn derive(&mut self, d: &D) {
+        if self.planner.backend() == plan::Backend::Deterministic && !self.reverse && self.state.len() <= 1
+            && (self.state.is_empty() || det::step(&mut self.state, d, &mut self.pool)) {
+            self.planner.observe(false, self.state.len());
+            return
+        };
+        if self.reverse {
+            for q in &self.state[..] {
+                rderiv(q, d, &mut self.pool, &mut self.next)
+            };
+            match Arc::get_mut(&mut self.state) {
+                Some(state) => {
+                    state.clear();
+                    std::mem::swap(state, &mut self.next)
+                },
+                None => self.state = Arc::new(std::mem::take(&mut self.next))
+            }
+        }
+        else {
+            match Arc::get_mut(&mut self.state) {
+                Some(state) => {
+                    for q in state.drain(..) {
+                        deriv_owned(q, d, &mut self.pool, &mut self.next)
+                    };
+                    std::mem::swap(state, &mut self.next)
+                },
+                None => {
+                    // Shared with a clone: leave its residuals alone.
+                    for q in &self.state[..] {
+                        deriv(q, d, &mut self.pool, &mut self.next)
+                    };
+                    self.state = Arc::new(std::mem::take(&mut self.next))
+                }
+            }
+        };
+        // Whichever way it was built, the new generation is ours alone.
+        if let Some(state) = Arc::get_mut(&mut self.state) {
+            canon::canon_all(state, &mut self.pool)
+        };
+        self.planner.observe(true, self.state.len())
+    }
+
+    /// `derive`, deriving each residual separately and dropping the ones
+    /// whose derivatives panicked; returns how many did. Residuals are
+    /// derived by reference, so a panic can't leave one half rewritten.
+    fn derive_isolated(&mut self, d: &D) -> u64 {
+        let mut panicked = 0;
+        let (pool, next, reverse) = (&mut self.pool, &mut self.next, self.reverse);
         for q in &self.state[..] {
-            vnew.append(&mut deriv(q.clone(), &d))
+            let len = next.len();
+            let derived = isolate::catch(|| {
+                if reverse { rderiv(q, d, pool, next) } else { deriv(q, d, pool, next) }
+            });
+            if derived.is_none() {
+                next.truncate(len);
+                panicked += 1
+            }
         };
-        let len = vnew.len() as u64;
-        self.state = vnew;
+        let mut state = std::mem::take(&mut self.next);
+        canon::canon_all(&mut state, &mut self.pool);
+        self.state = Arc::new(state);
+        self.planner.observe(true, self.state.len());
+        panicked
+    }
+
+    /// Counts caught panics; returns whether they poisoned the solver.
+    fn poison(&mut self, panics: u64, poison: isolate::Poison) -> bool {
+        self.panics += panics;
+        self.poisoned = poison == isolate::Poison::Solver;
+        self.poisoned
+    }
+
+    fn finish_update(&mut self) {
+        let len = self.state.len() as u64;
         if len > self.max_workingset {
             self.max_workingset = len
+        };
+        self.fire_alerts();
+        self.notify_observers()
+    }
+
+    /// Whether the query's residuals never branch, so updates can derive a
+    /// single residual in place of a generation of them (see `det`).
+    #[allow(dead_code)]
+    pub fn is_deterministic(&self) -> bool {
+        self.planner.eligible()
+    }
+
+    /// The backend updates are currently using. The planner picks it from
+    /// the query, then switches as the working set grows or shrinks (see
+    /// `plan::Planner`), unless `force_backend` pins it.
+    pub fn backend(&self) -> plan::Backend {
+        self.planner.backend()
+    }
+
+    /// How many times the planner has switched backends.
+    pub fn backend_switches(&self) -> u64 {
+        self.planner.switches()
+    }
+
+    /// Pins updates to `backend`, or with `None` hands the choice back to
+    /// the planner. Fails for `Backend::Deterministic` on a query that
+    /// isn't.
+    pub fn force_backend(&mut self, backend: Option<plan::Backend>) -> Result<(), String> {
+        self.planner.force(backend)
+    }
+
+    /// Sets what happens when a fallible op (`TrySat`, `TryCombine`,
+    /// `AppOp::Try`) fails during an update; the default is
+    /// `Policy::Abort`. Ops only run once their value is needed, so one
+    /// whose value is only needed by `output` (say, a `TryCombine` at the
+    /// top of the query) makes `output` fail instead, whatever the policy.
+    pub fn on_error(&mut self, policy: fallible::Policy) {
+        self.errors.policy = policy
+    }
+
+    /// How many times an op has failed while updating.
+    pub fn error_count(&self) -> u64 {
+        self.errors.count
+    }
+
+    /// The latest op failure; under `Policy::Abort`, the one that stopped
+    /// the solver.
+    pub fn last_error(&self) -> Option<&fallible::Error> {
+        self.errors.last.as_ref()
+    }
+
+    /// Catches panics in the query's predicates, ops and actions (and in
+    /// alerts and observers), rather than letting them unwind out of
+    /// `update`; `poison` says what's lost when one does. This costs a
+    /// little on every update, since residuals can no longer be derived in
+    /// place, and updates all go through the interpreter (which `backend`
+    /// comes round to saying).
+    pub fn isolate_panics(&mut self, poison: isolate::Poison) {
+        self.isolation = Some(poison)
+    }
+
+    /// How many panics `isolate_panics` has caught.
+    #[allow(dead_code)]
+    pub fn panic_count(&self) -> u64 {
+        self.panics
+    }
+
+    #[allow(dead_code)]
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Times every update from now on, so `stats` can report tail
+    /// latency. Off by default: it reads the clock twice per update.
+    pub fn record_latency(&mut self) {
+        if self.latency.is_none() {
+            self.latency = Some(Box::default())
         }
     }
 
-    pub fn output(&self) -> Result<C, String> {
+    pub fn latency(&self) -> Option<&latency::Histogram> {
+        self.latency.as_deref()
+    }
+
+    fn outputs(&self) -> Vec<C> {
         let mut cnew = Vec::new();
         for q in &self.state[..] {
-            cnew.append(&mut epsilon(&q.clone()))
+            cnew.extend(epsilon(q))
+        };
+        cnew
+    }
+
+    /// `output`, without the diagnostics.
+    fn defined_output(&self) -> Option<C> {
+        if let Some((ref old, _)) = self.retiring {
+            return old.defined_output()
+        };
+        let mut cs = self.outputs();
+        if cs.len() == 1 { cs.pop() } else { None }
+    }
+
+    pub fn output(&self) -> Result<C, String> {
+        if let Some((ref old, _)) = self.retiring {
+            return old.output()
+        };
+        if self.errors.aborted {
+            return Err(format!("aborted: {}", self.errors.last.as_ref().unwrap()))
+        };
+        if self.poisoned {
+            return Err("poisoned by a panic".to_string())
+        };
+        fallible::take();
+        let cnew = match self.isolation {
+            None => self.outputs(),
+            Some(_) => isolate::catch(|| self.outputs()).ok_or_else(|| "panicked computing the output".to_string())?
+        };
+        if let Some((e, _)) = fallible::take() {
+            return Err(e.to_string())
         };
         if cnew.len() == 1 {
             println!("max_workingset = {}", self.max_workingset);
@@ -155,8 +1199,31 @@ impl <D,C> Solve<D,C> where D: Clone, C: Clone + Debug {
             Err("undefined".to_string())
         }
     }
+
+    /// What `new_traced` has recorded, in order; empty for a solver that
+    /// isn't tracing.
+    pub fn trace(&self) -> Vec<trace::Step<C>> {
+        self.trace.as_ref().map(|t| t.steps()).unwrap_or_default()
+    }
+
+    /// A rough count of the bytes the residuals take up: the distinct
+    /// nodes times a node's size. Like `stats`, this walks the state.
+    pub fn approx_memory(&self) -> u64 {
+        sharing(&self.state).unique_nodes * std::mem::size_of::<QRE<D,C>>() as u64
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            workingset: self.state.len() as u64,
+            max_workingset: self.max_workingset,
+            sharing: sharing(&self.state),
+            latency: self.latency.as_ref().map(|h| h.latency()),
+        }
+    }
 }
 
+// Operands are only ever printed.
+#[allow(dead_code)]
 #[derive(Clone,Debug)]
 enum PInstr {
     Var(u32),
@@ -168,17 +1235,15 @@ enum PInstr {
 }
 
 fn is_push(i: &PInstr) -> bool {
-    match i {
-        PInstr::Push(_) => true,
-        _ => false
-    }
+    matches!(i, PInstr::Push(_))
 }
 
 fn is_pop(i: &PInstr) -> bool {
-    match i {
-        PInstr::Pop => true,
-        _ => false
-    }
+    matches!(i, PInstr::Pop)
+}
+
+fn is_other(i: &PInstr) -> bool {
+    !is_push(i) && !is_pop(i)
 }
 
 fn nop(_i: PInstr, _j: PInstr) -> PInstr { PInstr::PVec(vec![]) }
@@ -212,14 +1277,14 @@ fn example1() {
     let f = Sat{phi: is_push, op: id};
     let g = Sat{phi: is_pop, op: id};    
     let h1 = Split{
-        f: Box::new(f.clone()),
-        g: Box::new(g.clone()),
+        f: Arc::new(f.clone()),
+        g: Arc::new(g.clone()),
         op: nop};
     let h2 = Sat{phi: true_pred, op: id};
     let h = Choice{v: vec![h1, h2]};
     let peephole = Iter{
-        init: Box::new(Eps{c: PInstr::PVec(vec![])}),
-        body: Box::new(h),
+        init: Arc::new(Eps{c: PInstr::PVec(vec![])}),
+        body: Arc::new(h),
         op: concat
     };
     
@@ -253,42 +1318,50 @@ fn avg(x: f64, y: f64) -> f64 { (x + y) / 2.0 }
 fn example14() {
     let f = Sat{phi: true_f64, op: id_f64};
     let h1 = Split{
-        f: Box::new(f.clone()),
-        g: Box::new(f.clone()),
+        f: Arc::new(f.clone()),
+        g: Arc::new(f.clone()),
         op: max_f64
     };
     let h2 = Split{
-        f: Box::new(f.clone()),
-        g: Box::new(f.clone()),
+        f: Arc::new(f.clone()),
+        g: Arc::new(f.clone()),
         op: min_f64
     };
     let gbody = Sat{phi: true_f64, op: zero};
     let g = Iter{
-        init: Box::new(Eps{c: 0.0}),
-        body: Box::new(gbody),
+        init: Arc::new(Eps{c: 0.0}),
+        body: Arc::new(gbody),
         op: pi2
     };
     let k1 = Split{
-        f: Box::new(g.clone()),
-        g: Box::new(h1.clone()),
+        f: Arc::new(g.clone()),
+        g: Arc::new(h1.clone()),
         op: pi2
     };
     let k2 = Split{
-        f: Box::new(g),
-        g: Box::new(h2),
+        f: Arc::new(g),
+        g: Arc::new(h2),
         op: pi2
     };
     let r = Combine{
-        f: Box::new(k1),
-        g: Box::new(k2),
+        f: Arc::new(k1),
+        g: Arc::new(k2),
         op: avg
     };
-    let mut s = Solve::new(r);
+    let mut s = Solve::new(r.clone());
     s.update(5.0);
     s.update(4.0);    
     s.update(3.0);
     s.update(2.0);    
     s.update(1.0);    
+    println!("{:?}", s.output());
+
+    //Only the last two elements matter, so read the recorded stream
+    //backwards
+    let mut s = Solve::new_reverse(r);
+    for l in RevLines::new(Cursor::new("5\n4\n3\n2\n1\n")).unwrap() {
+        s.update(l.unwrap().parse::<f64>().unwrap())
+    }
     println!("{:?}", s.output())
 }
 
@@ -297,18 +1370,18 @@ fn running_avg() {
     let f = Sat{phi: true_f64, op: id_f64};
     let g = Sat{phi: true_f64, op: one_f64};
     let sum = Iter{
-        init: Box::new(zero.clone()),
-        body: Box::new(f),
+        init: Arc::new(zero.clone()),
+        body: Arc::new(f),
         op: sum_f64
     };
     let len = Iter{
-        init: Box::new(zero.clone()),
-        body: Box::new(g),
+        init: Arc::new(zero.clone()),
+        body: Arc::new(g),
         op: sum_f64
     };
     let avg = Combine{
-        f: Box::new(sum),
-        g: Box::new(len),
+        f: Arc::new(sum),
+        g: Arc::new(len),
         op: div_f64
     };
     let mut s = Solve::new(avg);
@@ -325,8 +1398,8 @@ struct Record {
     amount: f64
 }
 
-fn match_pred(r: &Record) -> bool { r.name == "Gordon".to_string() }
-fn notmatch_pred(r: &Record) -> bool { r.name != "Gordon".to_string() }
+fn match_pred(r: &Record) -> bool { r.name == "Gordon" }
+fn notmatch_pred(r: &Record) -> bool { r.name != "Gordon" }
 fn proj_amount(r: &Record) -> f64 { r.amount }
 
 fn aggregate() {
@@ -336,8 +1409,8 @@ fn aggregate() {
                     Sat{phi: notmatch_pred, op: zero}]
         };
     let agg_gordon = Iter{
-        init: Box::new(f.clone()),
-        body: Box::new(f),
+        init: Arc::new(f.clone()),
+        body: Arc::new(f),
         op: sum_f64
     };
     let mut s = Solve::new(agg_gordon);
@@ -358,6 +1431,685 @@ fn aggregate() {
     println!("{:?}", s.output())
 }
 
+//The same total, written with the operators from `algebra`
+fn aggregate_ops() {
+    use algebra::{agg, sat};
+    let gordon = sat(match_pred, proj_amount);
+    let other = sat(notmatch_pred, zero);
+    let mut s = Solve::new(((gordon | other) >> agg(sum_f64)).query());
+    for x in 0..10 {
+        let (name, amount) = if x % 2 == 0 { ("NotGordon", 3.0) } else { ("Gordon", 10.0) };
+        s.update(Record{name: name.to_string(), amount})
+    };
+    println!("{:?}", s.output())
+}
+
+//Fork a running sum midway and feed the two copies different suffixes
+fn snapshot() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let r = Iter{init: Arc::new(f.clone()),
+                 body: Arc::new(f),
+                 op: sum_f64};
+    let mut s = Solve::new(r);
+    for x in 0..10 { s.update(x as f64) }
+    let mut t = s.clone();
+    for x in 10..20 { s.update(x as f64) }
+    for x in 100..110 { t.update(x as f64) }
+    println!("{:?} {:?}", s.output(), t.output())
+}
+
+fn sum_f64_id(x: &f64) -> Sum { Sum(*x) }
+
+//Sum of the last 3 elements, retracting each one as it leaves the window
+fn sliding_sum() {
+    let mut w = Window::new(Sat{phi: true_f64, op: sum_f64_id}, 3);
+    for x in 0..10 { w.update(x as f64) }
+    println!("{:?}", w.output())
+}
+
+fn max_f64_id(x: &f64) -> Max { Max(*x) }
+
+//Max of (about) the last 100 elements, in logarithmic space
+fn sliding_max() {
+    let mut w = ApproxWindow::new(Sat{phi: true_f64, op: max_f64_id}, 100, 4);
+    for x in 0..1000 { w.update(((x * 37) % 1000) as f64) }
+    println!("{:?} over the last {} elements", w.output(), w.coverage())
+}
+
+//Swap a running sum for a running max, seeded with the sum so far
+fn migrate() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let sum = Iter{init: Arc::new(f.clone()),
+                   body: Arc::new(f.clone()),
+                   op: sum_f64};
+    let max = Iter{init: Arc::new(f.clone()),
+                   body: Arc::new(f),
+                   op: max_f64};
+    let mut s = Solve::new(sum);
+    for x in 0..10 { s.update(x as f64) }
+    s.migrate(max, MigrationPolicy::Carry).unwrap();
+    for x in 0..50 { s.update(x as f64) }
+    println!("{:?}", s.output())
+}
+
+//Checkpoint a running sum midway and pick it up again from the bytes
+fn checkpoint() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let r = Iter{init: Arc::new(f.clone()),
+                 body: Arc::new(f),
+                 op: sum_f64};
+    let mut s = Solve::new(r.clone());
+    for x in 0..50 { s.update(x as f64) }
+    let bytes = s.checkpoint().unwrap();
+    let mut t = Solve::restore(r, &bytes).unwrap();
+    for x in 50..101 { t.update(x as f64) }
+    println!("{:?} from a {} byte checkpoint", t.output(), bytes.len())
+}
+
+fn sum_count(x: &f64) -> (Sum, Count) { (Sum(*x), Count(1)) }
+
+//Average as a single (sum, count) fold, divided at the end
+fn tuple_avg() {
+    let mut s = Solve::new(fold(Sat{phi: true_f64, op: sum_count}));
+    for x in 0..101 { s.update(x as f64) }
+    println!("{:?}", s.output().map(|(sum, n)| sum.0 / n.0 as f64))
+}
+
+//The running average again, reporting the sum and count it divided
+fn captures() {
+    let zero = Sat{phi: true_f64, op: zero};
+    let sum = Iter{
+        init: Arc::new(zero.clone()),
+        body: Arc::new(Sat{phi: true_f64, op: id_f64}),
+        op: sum_f64
+    };
+    let len = Iter{
+        init: Arc::new(zero),
+        body: Arc::new(Sat{phi: true_f64, op: one_f64}),
+        op: sum_f64
+    };
+    let avg = Combine{
+        f: Arc::new(Tag{name: "sum", f: Arc::new(sum)}),
+        g: Arc::new(Tag{name: "len", f: Arc::new(len)}),
+        op: div_f64
+    };
+    let mut s = Solve::new(avg);
+    for x in 0..101 { s.update(x as f64) }
+    println!("{:?} {:?}", s.output(), s.captures())
+}
+
+//A running max, with each element's value also sent to a side channel
+fn taps() {
+    let (tx, rx) = mpsc::channel();
+    let f = Sat{phi: true_f64, op: id_f64};
+    let r = Iter{init: Arc::new(f.clone()),
+                 body: Arc::new(tap::tap_to("elem", f, tx)),
+                 op: max_f64};
+    let mut s = Solve::new(r);
+    for x in [3.0, 1.0, 4.0, 1.0, 5.0] { s.update(x) }
+    let tapped: Vec<_> = rx.try_iter().collect();
+    println!("{:?} {:?}", s.output(), tapped)
+}
+
+//Alert while the running average is over 100
+fn alerts() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let g = Sat{phi: true_f64, op: one_f64};
+    let sum = Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: sum_f64};
+    let len = Iter{init: Arc::new(g.clone()), body: Arc::new(g), op: sum_f64};
+    let avg = Combine{f: Arc::new(sum), g: Arc::new(len), op: div_f64};
+    let mut s = Solve::new(avg);
+    s.on_output(|avg| *avg > 100.0, |avg| println!("alert: average is {}", avg));
+    for x in [90.0, 95.0, 130.0, 110.0, 20.0, 150.0] { s.update(x) }
+}
+
+//Count the push/pop pairs the peephole optimizer removes as it goes
+fn triggers() {
+    let removed = Arc::new(AtomicU64::new(0));
+    let counter = removed.clone();
+    let pair = Split{
+        f: Arc::new(Sat{phi: is_push, op: id}),
+        g: Arc::new(Sat{phi: is_pop, op: id}),
+        op: nop};
+    let pair = Trigger{
+        body: Arc::new(pair),
+        action: Arc::new(move |_| { counter.fetch_add(1, Ordering::Relaxed); })
+    };
+    let peephole = Iter{
+        init: Arc::new(Eps{c: PInstr::PVec(vec![])}),
+        body: Arc::new(Choice{v: vec![pair, Sat{phi: is_other, op: id}]}),
+        op: concat
+    };
+    let mut s = Solve::new(peephole);
+    for i in [PInstr::Push(3), PInstr::Pop, PInstr::Var(1), PInstr::Push(4), PInstr::Pop] {
+        s.update(i)
+    }
+    println!("{:?}, {} pairs matched", s.output(), removed.load(Ordering::Relaxed))
+}
+
+//Watch the sum and len branches of the running average as it goes
+fn observers() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let g = Sat{phi: true_f64, op: one_f64};
+    let sum = Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: sum_f64};
+    let len = Iter{init: Arc::new(g.clone()), body: Arc::new(g), op: sum_f64};
+    let avg = Combine{f: Arc::new(sum), g: Arc::new(len), op: div_f64};
+    let avg = observe::tag_at(avg, &[0], "sum").unwrap();
+    let avg = observe::tag_at(avg, &[1], "len").unwrap();
+    let mut s = Solve::new(avg);
+    s.observe("sum", |cs| println!("sum = {:?}", cs));
+    s.observe("len", |cs| println!("len = {:?}", cs));
+    for x in [4.0, 8.0, 3.0] { s.update(x) }
+}
+
+//Attach and detach queries on one stream as it runs
+fn broadcast() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let sum = Arc::new(Mutex::new(Solve::new(
+        Iter{init: Arc::new(f.clone()), body: Arc::new(f.clone()), op: sum_f64})));
+    let max = Arc::new(Mutex::new(Solve::new(
+        Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: max_f64})));
+    let mut b = broadcast::Broadcast::new();
+    b.add(sum.clone());
+    for x in 0..10 { b.push(&(x as f64)) }
+    let id = b.add(max.clone());
+    for x in 10..20 { b.push(&(x as f64)) }
+    b.remove(id);
+    for x in 20..30 { b.push(&(x as f64)) }
+    println!("{:?} {:?}", sum.lock().unwrap().output(), max.lock().unwrap().output())
+}
+
+fn fails_at_15(x: &f64) -> f64 { assert!(*x < 15.0, "bad element"); *x }
+
+//Kill a tenant's query that blows up, or panics, without the others noticing
+fn quotas() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let sum = Arc::new(Mutex::new(Solve::new(
+        Iter{init: Arc::new(f.clone()), body: Arc::new(f.clone()), op: sum_f64})));
+    let run = Iter{init: Arc::new(f.clone()), body: Arc::new(f.clone()), op: sum_f64};
+    let splits = Split{f: Arc::new(Split{f: Arc::new(run.clone()), g: Arc::new(run.clone()), op: sum_f64}),
+                       g: Arc::new(run), op: max_f64};
+    let g = Sat{phi: true_f64, op: fails_at_15};
+    let panicky = Iter{init: Arc::new(g.clone()), body: Arc::new(g), op: sum_f64};
+    let mut b = broadcast::Broadcast::new();
+    let quota = broadcast::Quota { residuals: Some(50), ..Default::default() };
+    let ids = [b.add_with_quota(sum.clone(), quota),
+               b.add_with_quota(Solve::new(splits), quota),
+               b.add_with_quota(Solve::new(panicky), quota)];
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| ()));
+    for x in 0..30 { b.push(&(x as f64)) }
+    std::panic::set_hook(hook);
+    println!("{:?} {:?}", sum.lock().unwrap().output(), ids.iter().map(|id| b.status(*id).unwrap()).collect::<Vec<_>>())
+}
+
+//Sample a running sum at a fixed rate while elements trickle in
+fn periodic() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let s = Arc::new(Mutex::new(Solve::new(
+        Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: sum_f64})));
+    let ticker = runtime::every(s.clone(), Duration::from_millis(20), 0.0,
+                                |c| println!("tick: {}", c));
+    for x in 0..3 {
+        thread::sleep(Duration::from_millis(30));
+        s.lock().unwrap().update(x as f64 + 1.0)
+    };
+    ticker.stop()
+}
+
+//Run a running sum on its own threads, checkpointing as it goes
+fn spawned() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let r = Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: sum_f64};
+    let saved = Arc::new(Mutex::new(Vec::new()));
+    let last = saved.clone();
+    let opts = runtime::Options {
+        capacity: 16,
+        checkpoint: Some((Duration::from_millis(10), Box::new(move |s: &Solve<f64,f64>| {
+            *last.lock().unwrap() = s.checkpoint().unwrap()
+        }) as runtime::Hook<f64,f64>))
+    };
+    let task = runtime::spawn(r, (0..101).map(|x| x as f64), |_| (), opts);
+    println!("{:?}, last checkpoint {} bytes", task.join(), saved.lock().unwrap().len())
+}
+
+//Total amount per name, one solver per name
+fn per_name() {
+    let mut k = keyed::Keyed::new(Iter{
+        init: Arc::new(Eps{c: 0.0}),
+        body: Arc::new(Sat{phi: |_: &Record| true, op: proj_amount}),
+        op: sum_f64
+    });
+    for (name, amount) in [("Gordon", 10.0), ("Alice", 3.0), ("Gordon", 5.0)] {
+        k.update(name.to_string(), Record{name: name.to_string(), amount})
+    }
+    println!("{:?} {:?}", k.output(&"Gordon".to_string()), k.output(&"Alice".to_string()))
+}
+
+fn is_order_insert(c: &cdc::Change) -> bool {
+    c.table == "public.orders" && c.op == cdc::Op::Insert
+}
+fn not_order_insert(c: &cdc::Change) -> bool { !is_order_insert(c) }
+fn order_amount(c: &cdc::Change) -> f64 {
+    c.get("amount").and_then(|v| v.as_f64()).unwrap_or(0.0)
+}
+
+//Total value of new orders, off a database's change stream
+fn change_capture() {
+    let log = "BEGIN 529
+table public.orders: INSERT: id[integer]:1 customer[text]:'O''Brien' amount[numeric]:12.50
+table public.orders: INSERT: id[integer]:2 customer[text]:'Lee' amount[numeric]:7.25
+COMMIT 529
+BEGIN 530
+table public.orders: UPDATE: old-key: id[integer]:2 new-tuple: id[integer]:3 customer[text]:'Lee' amount[numeric]:7.25
+table public.orders: DELETE: id[integer]:1
+table public.users: INSERT: id[integer]:9 active[boolean]:true
+COMMIT 530";
+    let f = Choice{v: vec![Sat{phi: is_order_insert, op: order_amount},
+                           Sat{phi: not_order_insert, op: zero}]};
+    let mut s = Solve::new(Iter{init: Arc::new(Eps{c: 0.0}), body: Arc::new(f), op: sum_f64});
+    for l in log.lines() {
+        if let Some(c) = cdc::parse_test_decoding(l).unwrap() { s.update(c) }
+    }
+    println!("{:?}", s.output())
+}
+
+fn is_error(r: &syslog::LogRecord) -> bool { r.is_error() }
+fn not_error(r: &syslog::LogRecord) -> bool { !r.is_error() }
+fn one_log(_r: &syslog::LogRecord) -> f64 { 1.0 }
+
+//Count the errors in a syslog feed
+fn syslog_errors() {
+    let log = [
+        "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut=\"3\" eventSource=\"Application\"] An application event",
+        "<11>1 2003-10-11T22:14:16Z mymachine.example.com su - ID47 - 'su root' failed for lonvick on /dev/pts/8",
+        "<13>1 2003-10-11T22:14:17+02:00 mymachine.example.com app 77 - - all good",
+        "<10>1 2003-10-11T22:14:18Z mymachine.example.com app 77 - - disk full"];
+    let f = Choice{v: vec![Sat{phi: is_error, op: one_log}, Sat{phi: not_error, op: zero}]};
+    let mut s = Solve::new(Iter{init: Arc::new(Eps{c: 0.0}), body: Arc::new(f), op: sum_f64});
+    for l in &log {
+        s.update(syslog::parse_rfc5424(l).unwrap())
+    }
+    println!("{:?} errors", s.output())
+}
+
+//Flag a sustained level shift in a noisy series: robust score > 4 for 3 points
+fn anomalies() {
+    let mut s = Solve::new(scan::scan(anomaly::Anomaly::mad(20, 4.0)));
+    let mut flagged = None;
+    for i in 0..60 {
+        let noise = ((i * 7919) % 13) as f64 / 13.0 - 0.5;
+        s.update(if i >= 40 { 25.0 + noise } else { 10.0 + noise });
+        match s.output() {
+            Ok(ref a) if a.run() >= 3 && flagged.is_none() => flagged = Some(i),
+            _ => ()
+        }
+    }
+    println!("level shift flagged at element {:?}", flagged)
+}
+
+//Forecast the next season of a trending, seasonal series
+fn forecasts() {
+    let mut s = Solve::new(scan::scan(forecast::Forecast::new(0.5, 0.3, 0.3, 4)));
+    let season = [3.0, -1.0, -3.0, 1.0];
+    for i in 0..40 { s.update(100.0 + 0.5 * i as f64 + season[i % 4]) }
+    let f = s.output().unwrap();
+    let next: Vec<f64> = (1..5).map(|h| f.forecast(h).unwrap().round()).collect();
+    println!("next season {:?}, last residual {:.2}", next, f.residual().unwrap())
+}
+
+fn above_25(x: &f64) -> bool { *x > 25.0 }
+fn not_above_25(x: &f64) -> bool { *x <= 25.0 }
+
+//Count readings over 25 in a noisy sensor stream, raw and Kalman-filtered
+fn smoothing() {
+    let over = Iter{init: Arc::new(Eps{c: 0.0}),
+                    body: Arc::new(Choice{v: vec![Sat{phi: above_25, op: one_f64},
+                                                  Sat{phi: not_above_25, op: zero}]}),
+                    op: sum_f64};
+    let mut raw = Solve::new(over.clone());
+    let mut smoothed = filter::Smoothed::new(filter::Filter::kalman(0.01, 4.0), over);
+    for i in 0..200 {
+        let noise = ((i * 7919) % 17) as f64 - 8.0;
+        let x = if i >= 150 { 30.0 + noise } else { 20.0 + noise };
+        raw.update(x);
+        smoothed.update(x)
+    }
+    println!("{:?} raw, {:?} smoothed readings over 25", raw.output(), smoothed.output())
+}
+
+//Watch the dominant frequency of a vibration signal as a fault develops
+fn vibration() {
+    let mut s = Solve::new(scan::scan(spectrum::Spectrum::new(256, 64, 1000.0)));
+    let mut fault = None;
+    for i in 0..2000 {
+        let t = i as f64 / 1000.0;
+        let hz = if i < 1000 { 50.0 } else { 120.0 };
+        s.update((2.0 * std::f64::consts::PI * hz * t).sin() + 0.1 * (((i * 7919) % 11) as f64 - 5.0) / 5.0);
+        match s.output().ok().and_then(|sp| sp.features()) {
+            Some(f) if f.dominant > 100.0 && fault.is_none() => fault = Some((i, f.dominant)),
+            _ => ()
+        }
+    }
+    println!("dominant frequency moved to {:?}", fault)
+}
+
+fn is_server_error(l: &lines::Line) -> bool { l.num("status").is_some_and(|s| s >= 500.0) }
+fn not_server_error(l: &lines::Line) -> bool { !is_server_error(l) }
+fn latency(l: &lines::Line) -> f64 { l.num("ms").unwrap_or(0.0) }
+
+//Total the latency of failed requests in a plain access log
+fn log_latency() {
+    let log = "10.0.0.1 GET /index.html 200 12ms\n\
+               10.0.0.2 POST /api/orders 503 870ms\n\
+               starting worker 3\n\
+               10.0.0.1 GET /api/orders 500 415ms\n\
+               10.0.0.3 GET /favicon.ico 404 2ms\n";
+    let p = lines::Pattern::new(r"^(?P<client>[\d.]+) (?P<method>[A-Z]+) (?P<path>\S+) (?P<status>\d{3}) (?P<ms>\d+)ms$").unwrap();
+    let f = Choice{v: vec![Sat{phi: is_server_error, op: latency}, Sat{phi: not_server_error, op: zero}]};
+    let mut s = Solve::new(Iter{init: Arc::new(Eps{c: 0.0}), body: Arc::new(f), op: sum_f64});
+    for l in p.lines(Cursor::new(log)) {
+        s.update(l.unwrap())
+    }
+    println!("{:?}ms in failed requests", s.output())
+}
+
+//Check a query's worst-case update cost before running it
+fn complexity_report() {
+    let pairs = Iter{init: Arc::new(Eps{c: 0.0}),
+                     body: Arc::new(Split{f: Arc::new(Sat{phi: true_f64, op: id_f64}), g: Arc::new(Sat{phi: true_f64, op: id_f64}), op: max_f64}),
+                     op: sum_f64};
+    println!("{}", complexity::analyze(&pairs));
+    let runs = Iter{init: Arc::new(Eps{c: 0.0}),
+                    body: Arc::new(Choice{v: vec![Sat{phi: true_f64, op: id_f64},
+                                                  Split{f: Arc::new(Sat{phi: true_f64, op: id_f64}), g: Arc::new(Sat{phi: true_f64, op: id_f64}), op: max_f64}]}),
+                    op: sum_f64};
+    println!("{}", complexity::analyze(&runs).per_update().map_or("unbounded".to_string(), |n| n.to_string()))
+}
+
+fn parse_reading(s: &&'static str) -> Result<f64, fallible::Error> { Ok(s.trim().parse::<f64>()?) }
+
+//Sum readings that sometimes fail to parse, under each error policy
+fn fallible_ops() {
+    let readings = ["12.5", "7", "n/a", "0.5"];
+    for policy in [fallible::Policy::Skip, fallible::Policy::NoMatch, fallible::Policy::Abort] {
+        let body = TrySat{phi: true_pred, op: parse_reading};
+        let mut s = Solve::new(Iter{init: Arc::new(Eps{c: 0.0}), body: Arc::new(body), op: sum_f64});
+        s.on_error(policy);
+        for r in readings {
+            s.update(r)
+        };
+        let cause = s.last_error().and_then(|e| e.downcast_ref::<std::num::ParseFloatError>());
+        println!("{:?}: {:?} after {} error(s) ({:?})", policy, s.output(), s.error_count(), cause)
+    }
+}
+
+fn checked_reading(x: &f64) -> f64 {
+    assert!(x.is_finite(), "non-finite reading");
+    *x
+}
+
+//Keep aggregating the other keys when one key's record makes an op panic
+fn panic_isolation() {
+    let body = Sat{phi: true_f64, op: checked_reading};
+    let mut k = keyed::Keyed::new(Iter{init: Arc::new(Eps{c: 0.0}), body: Arc::new(body), op: sum_f64});
+    k.isolate_panics(isolate::Poison::Solver);
+    // Caught panics still go through the hook, which would print them.
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| ()));
+    for (key, x) in [("pump-1", 2.0), ("pump-2", 3.0), ("pump-2", f64::NAN), ("pump-1", 4.0), ("pump-2", 1.0)] {
+        k.update(key, x)
+    };
+    std::panic::set_hook(hook);
+    println!("pump-1: {:?}, pump-2: {:?}", k.output(&"pump-1"), k.output(&"pump-2"))
+}
+
+//Backfill an average from a recorded stream in parallel, then go live
+fn backfill() {
+    let recorded: Vec<f64> = (0..100000).map(|x| (x % 100) as f64).collect();
+    let mut s = par::par_backfill(par::Folds::fold(Sat{phi: true_f64, op: sum_count}), &recorded, 4).unwrap();
+    for x in [100.0, 200.0] {
+        s.update(x)
+    };
+    println!("{:?}", s.output().map(|(sum, n)| sum.0 / n.0 as f64))
+}
+
+fn latency_typo(l: &lines::Line) -> f64 { l.num("latency").unwrap_or(0.0) }
+
+//Check queries against what their input can look like, before any arrives
+fn validation() {
+    use validate::{Schema, Values};
+    let schema = Schema::fields(vec![("client", Values::text(&["10.0.0.1"])),
+                                     ("method", Values::text(&["GET", "POST"])),
+                                     ("path", Values::text(&["/index.html"])),
+                                     ("status", Values::num()),
+                                     ("ms", Values::num())]);
+    let good = Choice{v: vec![Sat{phi: is_server_error, op: latency}, Sat{phi: not_server_error, op: zero}]};
+    println!("{:?}", good.validate(&schema));
+    let typo = Choice{v: vec![Sat{phi: is_server_error, op: latency_typo}, Sat{phi: not_server_error, op: zero}]};
+    println!("{:?}", typo.validate(&schema));
+    // Readings that never go above 25 can't start a hot spell.
+    let hot = Split{f: Arc::new(Sat{phi: above_25, op: id_f64}), g: Arc::new(Sat{phi: true_f64, op: id_f64}), op: max_f64};
+    println!("{:?}", hot.validate(&Schema::alphabet(vec![10.0, 20.0])))
+}
+
+fn max_point<T: ops::Num>(x: &T) -> Max<T> { Max(*x) }
+fn one_i64<D>(_x: &D) -> i64 { 1 }
+
+//One query template for f32 sensor readings and i64 counters alike
+fn generic_ops() {
+    fn peak<T: ops::Num>() -> QRE<T, Max<T>> { fold(Sat{phi: true_pred, op: max_point}) }
+    let mut readings = Solve::new(peak::<f32>());
+    for x in [0.5f32, 2.25, 1.0] {
+        readings.update(x)
+    };
+    let mut counters = Solve::new(peak::<i64>());
+    let mut count = Solve::new(Iter{init: Arc::new(Eps{c: 0}), body: Arc::new(Sat{phi: true_pred, op: one_i64}), op: ops::add});
+    for x in [3i64, 9, 4] {
+        counters.update(x);
+        count.update(x)
+    };
+    println!("{:?} {:?} {:?}", readings.output(), counters.output(), count.output())
+}
+
+//Per-update latency percentiles, reported with the rest of the stats
+fn update_latency() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let mut s = Solve::new(Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: sum_f64});
+    s.record_latency();
+    for x in 0..1000 {
+        s.update(x as f64)
+    };
+    let h = s.latency().unwrap();
+    println!("{} updates, p50 <= p99 <= max: {}", h.count(), h.quantile(0.5) <= h.quantile(0.99) && h.quantile(0.99) <= h.max())
+}
+
+//Report how far a backfill over a log has got, by bytes read
+fn backfill_progress() {
+    let log: String = (0..2000).map(|i| format!("GET /item/{} {} {}ms\n", i, if i % 50 == 0 { 503 } else { 200 }, i % 97)).collect();
+    let p = lines::Pattern::new(r"^GET \S+ (?P<status>\d+) (?P<ms>\d+)ms$").unwrap();
+    let (r, bytes) = progress::counted(Cursor::new(log.as_bytes()));
+    let opts = progress::Options { every: Duration::ZERO, bytes: Some((bytes, Some(log.len() as u64))), ..Default::default() };
+    let mut s = Solve::new(fold(Choice{v: vec![Sat{phi: is_server_error, op: |_| Count(1)},
+                                               Sat{phi: not_server_error, op: |_| Count(0)}]}));
+    let mut reports = 0;
+    let done = progress::run(&mut s, p.lines(r).map_while(Result::ok), opts, |_| reports += 1);
+    println!("{} elements, {:?} of {:?} bytes, {:?} done, {} report(s): {:?}",
+             done.elements, done.bytes, done.total_bytes, done.fraction(), reports, s.output())
+}
+
+//What was the running sum after 500 of 1000 elements? Replays at most 99
+fn seek() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let mut s = Solve::new(Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: sum_f64});
+    s.record(100).unwrap();
+    for x in 0..1000 {
+        s.update(x as f64)
+    };
+    println!("{:?} {:?} {:?}", s.seek(500).and_then(|s| s.output()), s.seek(1000).and_then(|s| s.output()),
+             s.seek(1001).map(|_| ()))
+}
+
+//Let the planner pick each query's backend, and take over again after pinning one
+fn planner() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let mut sum = Solve::new(Iter{init: Arc::new(f.clone()), body: Arc::new(f.clone()), op: sum_f64});
+    let mut tagged = Solve::new(Tag{name: "x", f: Arc::new(f)});
+    print!("{:?} {:?} {:?}", sum.backend(), tagged.backend(), tagged.force_backend(Some(plan::Backend::Deterministic)));
+    sum.force_backend(Some(plan::Backend::Interpreter)).unwrap();
+    for x in 0..plan::WINDOW {
+        sum.update(x as f64)
+    };
+    sum.force_backend(None).unwrap();
+    for x in 0..plan::WINDOW {
+        sum.update(x as f64)
+    };
+    println!(" -> {:?} after {} switch(es)", sum.backend(), sum.backend_switches())
+}
+
+//Show the accumulators behind a number that looks wrong
+fn tracing() {
+    let f = Sat{phi: true_f64, op: id_f64};
+    let sum = Iter{init: Arc::new(f.clone()), body: Arc::new(f.clone()), op: sum_f64};
+    let max = Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: max_f64};
+    let mut s = Solve::new_traced(Combine{f: Arc::new(sum), g: Arc::new(max), op: sum_f64});
+    for x in [4.0, 8.0, 3.0] { s.update(x) }
+    println!("{:?}", s.output());
+    for step in s.trace() {
+        println!("{}", step)
+    }
+}
+
+fn request_bytes(r: &(u8, f64)) -> Sum<f64> { Sum(r.1) }
+
+//Estimate total bytes from a 2% sample, with a 95% confidence interval
+fn sampling() {
+    let requests: Vec<(u8, f64)> = (0..100000u64).map(|i| ((i % 1000 == 0) as u8, (i * 7919 % 1000) as f64)).collect();
+    let exact: f64 = requests.iter().map(|r| r.1).sum();
+    let bytes = || fold(Sat{phi: |_: &(u8, f64)| true, op: request_bytes});
+    let mut uniform = sample::Sampled::linear(bytes(), sample::uniform(0.02), 7);
+    let mut by_kind = sample::Sampled::linear(bytes(), sample::stratified(|r: &(u8, f64)| r.0, 50, 0.02), 7);
+    for r in requests {
+        uniform.update(r);
+        by_kind.update(r)
+    };
+    for e in [uniform.estimate().unwrap(), by_kind.estimate().unwrap()] {
+        println!("{} of {} sampled: {:.0} in [{:.0}, {:.0}], exact {} inside: {}",
+                 e.sampled, e.seen, e.value, e.low, e.high, exact, e.low <= exact && exact <= e.high)
+    }
+}
+
+// Not Clone: only the size is ever copied out.
+struct Upload { body: Vec<u8>, name: String }
+
+fn upload_size(u: &Upload) -> f64 { u.body.len() as f64 }
+
+//Total up large records in place, without copying them
+fn borrowed() {
+    let uploads: Vec<Upload> = (1..=3).map(|i| Upload { body: vec![0; i * 1024], name: format!("u{}", i) }).collect();
+    let f = Sat{phi: |_: &Upload| true, op: upload_size};
+    let mut s = Solve::new(Iter{init: Arc::new(f.clone()), body: Arc::new(f), op: sum_f64});
+    for u in &uploads {
+        s.update(u)
+    };
+    println!("{:?} over {}", s.output(), uploads.iter().map(|u| u.name.as_str()).collect::<Vec<_>>().join(", "))
+}
+
+fn token_len(t: &tokens::Token) -> f64 { t.len() as f64 }
+fn one_token(_t: &tokens::Token) -> f64 { 1.0 }
+
+//Word-length statistics over a text stream, as queries over tokens
+fn tokenized() {
+    let text = "The quick brown fox doesn't jump.\nIt naps: 12 hours a day!\n";
+    let (len, one) = (Sat{phi: true_pred, op: token_len}, Sat{phi: true_pred, op: one_token});
+    let total = |f: QRE<tokens::Token, f64>, op| Iter{init: Arc::new(Eps{c: 0.0}), body: Arc::new(f), op};
+    let mut mean = Solve::new(Combine{f: Arc::new(total(len.clone(), sum_f64)), g: Arc::new(total(one, sum_f64)), op: div_f64});
+    let mut longest = Solve::new(total(len, max_f64));
+    for t in tokens::Tokenizer::Words.read(Cursor::new(text)) {
+        let t = t.unwrap();
+        mean.update(&t);
+        longest.update(t)
+    };
+    let graphemes = tokens::Tokenizer::Graphemes.split("ne\u{301}e \u{1F1EB}\u{1F1F7}\r\n");
+    println!("{:?} {:?}, {} graphemes", mean.output(), longest.output(), graphemes.len())
+}
+
+//Alert when the temperature rises faster than 2 degrees a minute
+fn rising() {
+    let mut s = Solve::new(fold(Sat{phi: true_pred, op: ops::Rate::of}));
+    s.on_output(|r| r.per_minute().is_some_and(|r| r > 2.0),
+                |r| println!("alert: rising {:.1} degrees/min at {}s", r.per_minute().unwrap(), r.last.unwrap().time));
+    for (i, temp) in [20.0, 20.5, 21.0, 22.5, 23.0, 23.2].iter().enumerate() {
+        s.update(ops::Timed { time: 30.0 * i as f64, value: *temp })
+    };
+    let mut d = Solve::new(fold(Sat{phi: true_pred, op: ops::Delta::of}));
+    for x in [3i64, 7, 4] {
+        d.update(x)
+    };
+    println!("{:?} {:?}", s.output().map(|r| r.per_second), d.output().map(|d| d.change))
+}
+
+//Trend of the last 5 readings, and how well a line fits them
+fn trend() {
+    let mut w = Window::new(Sat{phi: true_pred, op: ops::Regression::timed}, 5);
+    for (i, temp) in [20.0, 20.4, 21.1, 21.4, 22.2, 22.4, 23.1, 23.5].iter().enumerate() {
+        w.update(ops::Timed { time: 60.0 * i as f64, value: *temp })
+    };
+    let fit = w.output().unwrap();
+    println!("{:.4}/s from {:.2}, r = {:.3}", fit.slope().unwrap(), fit.intercept().unwrap(), fit.correlation().unwrap())
+}
+
+fn count_min_max(x: &i64) -> (Count, ops::Min<i64>, Max<i64>) { (Count(1), ops::Min(*x), Max(*x)) }
+
+//Several statistics in a single fold rather than one Iter apiece
+fn fused() {
+    let mut s = Solve::new(fold(Sat{phi: true_f64, op: ops::Summary::of}));
+    for x in 0..101 { s.update(x as f64) }
+    let summary = s.output().unwrap();
+    println!("{:?} mean {:?}", summary, summary.mean());
+    let mut t = Solve::new(fold(Sat{phi: true_pred, op: count_min_max}));
+    for x in [5i64, -2, 9, 4] {
+        t.update(x)
+    };
+    println!("{:?}", t.output())
+}
+
+//A step-by-step account of a running sum of the readings above 25
+fn transcript() {
+    let hot = Sat{phi: above_25, op: id_f64};
+    let mild = Sat{phi: not_above_25, op: zero};
+    let mut s = Solve::new(Iter{init: Arc::new(Eps{c: 0.0}), body: Arc::new(Choice{v: vec![hot, mild]}), op: sum_f64});
+    s.trace_to(std::io::stdout(), transcript::Format::Text);
+    for x in [30.0, 20.0] {
+        s.update(x)
+    };
+    let mut t = s.clone();
+    t.trace_to(std::io::stdout(), transcript::Format::Json);
+    t.update(40.0)
+}
+
+fn is_two(x: &i64) -> bool { *x == 2 }
+fn not_two(x: &i64) -> bool { *x != 2 }
+fn id_i64(x: &i64) -> i64 { *x }
+
+//Check a query against a reference on every stream of up to 6 of 0, 1, 2
+fn exhaustive() {
+    // The sum of a stream ending in its only 2.
+    let q = Split{
+        f: Arc::new(Iter{init: Arc::new(Eps{c: 0}), body: Arc::new(Sat{phi: not_two, op: id_i64}), op: ops::add}),
+        g: Arc::new(Sat{phi: is_two, op: id_i64}),
+        op: ops::add
+    };
+    let sum = |s: &[i64]| Some(s.iter().sum());
+    let once = |s: &[i64]| if s.iter().filter(|x| **x == 2).count() == 1 && s.last() == Some(&2) { sum(s) } else { None };
+    println!("{:?}", testing::exhaustive(&q, &[0, 1, 2], 6, once));
+    // Forgetting that it has to end in a 2.
+    match testing::exhaustive(&q, &[0, 1, 2], 6, sum) {
+        Ok(n) => println!("{} streams agree", n),
+        Err(c) => println!("{}", c)
+    }
+}
+
 fn main() {
     example1();
     
@@ -368,10 +2120,75 @@ fn main() {
     running_avg();
 
     aggregate();
+    aggregate_ops();
+
+    snapshot();
+
+    sliding_sum();
+
+    sliding_max();
+
+    migrate();
+
+    checkpoint();
+
+    tuple_avg();
+
+    captures();
+
+    taps();
+
+    alerts();
+
+    triggers();
+
+    observers();
+
+    broadcast();
+    quotas();
+
+    periodic();
+
+    spawned();
+
+    per_name();
+
+    change_capture();
+
+    syslog_errors();
+
+    log_latency();
+
+    anomalies();
+
+    forecasts();
+
+    smoothing();
+
+    vibration();
+    complexity_report();
+    fallible_ops();
+    panic_isolation();
+    backfill();
+    validation();
+    generic_ops();
+    update_latency();
+    backfill_progress();
+    seek();
+    planner();
+    tracing();
+    sampling();
+    borrowed();
+    tokenized();
+    rising();
+    trend();
+    fused();
+    transcript();
+    exhaustive();
     
     let f = Sat{phi: true_f64, op: id_f64};
-    let r = Iter{init: Box::new(f.clone()),
-                 body: Box::new(f),
+    let r = Iter{init: Arc::new(f.clone()),
+                 body: Arc::new(f),
                  op: sum_f64};
     let mut s = Solve::new(r);
 
@@ -381,11 +2198,19 @@ fn main() {
     println!("{:?}", s.output());
     let elapsed = now.elapsed();
     println!("QRE time = {}s, {}ms", elapsed.as_secs(), elapsed.subsec_millis());
+    println!("{}", s.stats());
+
+    //Compute T(1000) as a parallel reduction
+    let xs: Vec<f64> = (0..1001).map(|x| x as f64).collect();
+    let now3 = Instant::now();
+    println!("{:?}", par::par_fold(&Sat{phi: true_f64, op: sum_f64_id}, &xs, 4));
+    let elapsed3 = now3.elapsed();
+    println!("parallel QRE time = {}s, {}ms", elapsed3.as_secs(), elapsed3.subsec_millis());
 
     //Compute T(1000) by iteration
     let mut t = 0.0;
     let now2 = Instant::now();    
-    for x in 0..1001 { t = t + (x as f64) }
+    for x in 0..1001 { t += x as f64 }
     println!("{:?}", t);
     let elapsed2 = now2.elapsed();
     println!("time = {}s, {}ms", elapsed2.as_secs(), elapsed2.subsec_millis());
@@ -0,0 +1,162 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use keyed::Keyed;
+use runtime::{reconnecting, Backoff, Decode};
+
+/// How often to tell the broker we're still here.
+const KEEP_ALIVE: u16 = 60;
+
+/// The longest packet we'll read; a longer one drops the connection
+/// rather than being buffered.
+const MAX_PACKET: u64 = 1 << 20;
+
+/// Sessions started by this process, so each gets its own client id: a
+/// broker disconnects a client when another connects under its id.
+static SESSIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Subscribes to `filter` (e.g. `"devices/+/temperature"`) on the MQTT
+/// broker at `addr` and feeds each message to `solvers`, under the key
+/// `key` extracts from its topic and decoded by `decode`; messages it
+/// rejects are reported on stderr and skipped. Reconnects according to
+/// `backoff`, and only returns once that gives up. This speaks MQTT 3.1.1
+/// at QoS 0 over plain TCP: no TLS, no authentication.
+#[allow(dead_code)]
+pub fn subscribe<A,K,D,C,F>(addr: A, filter: &str, key: F, decode: Decode<D>,
+                            solvers: Arc<Mutex<Keyed<K,D,C>>>, backoff: Backoff) -> io::Result<()>
+    where A: ToSocketAddrs + Clone,
+          K: Eq + Hash,
+          C: Clone + Debug + Send + Sync,
+          F: Fn(&str) -> K {
+    reconnecting(backoff, |connected| session(addr.clone(), filter, &key, &decode, &solvers, connected))
+}
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+
+fn session<A,K,D,C,F>(addr: A, filter: &str, key: &F, decode: &Decode<D>,
+                      solvers: &Mutex<Keyed<K,D,C>>, connected: &mut bool) -> io::Result<()>
+    where A: ToSocketAddrs, K: Eq + Hash, C: Clone + Debug + Send + Sync, F: Fn(&str) -> K {
+    let conn = TcpStream::connect(addr)?;
+    // The broker answers each ping, so silence for longer than this means
+    // the connection's gone, whether or not TCP has noticed.
+    conn.set_read_timeout(Some(Duration::from_secs(KEEP_ALIVE as u64 * 3 / 2)))?;
+    let mut w = conn.try_clone()?;
+    let mut r = BufReader::new(conn);
+
+    let mut connect = Vec::new();
+    write_str(&mut connect, "MQTT");
+    // Protocol level 4 (3.1.1), clean session, keep-alive.
+    connect.extend_from_slice(&[4, 0x02]);
+    connect.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+    write_str(&mut connect, &format!("qre-{}-{}", std::process::id(), SESSIONS.fetch_add(1, Ordering::Relaxed)));
+    write_packet(&mut w, CONNECT, &connect)?;
+    match read_packet(&mut r)? {
+        (CONNACK, body) if body.get(1) == Some(&0) => *connected = true,
+        (CONNACK, body) => return Err(io::Error::other(format!("connection refused with code {:?}", body.get(1)))),
+        (t, _) => return Err(io::Error::other(format!("expected CONNACK, got packet type {:#x}", t)))
+    };
+
+    let mut subscribe = vec![0, 1];
+    write_str(&mut subscribe, filter);
+    subscribe.push(0);
+    write_packet(&mut w, SUBSCRIBE, &subscribe)?;
+
+    // The broker drops us if it hears nothing for 1.5 keep-alives. Packets
+    // are written whole under the lock, and the pinger stops once the
+    // socket's shut down below.
+    let w = Arc::new(Mutex::new(w));
+    let pinger = w.clone();
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(KEEP_ALIVE as u64 / 2));
+            if write_packet(&mut *pinger.lock().unwrap(), PINGREQ, &[]).is_err() {
+                return
+            }
+        }
+    });
+
+    let result = (|| loop {
+        let (t, body) = read_packet(&mut r)?;
+        match t & 0xf0 {
+            PUBLISH => {
+                let n = u16::from_be_bytes([*body.first().unwrap_or(&0), *body.get(1).unwrap_or(&0)]) as usize;
+                if body.len() < 2 + n {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "bad PUBLISH"))
+                };
+                // A broker never delivers above the subscription's QoS,
+                // and ours is 0, so there's no packet id or ack.
+                if (t >> 1) & 3 > 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "PUBLISH above QoS 0"))
+                };
+                let topic = String::from_utf8_lossy(&body[2..2 + n]);
+                let payload = &body[2 + n..];
+                match decode(&String::from_utf8_lossy(payload)) {
+                    Ok(d) => solvers.lock().unwrap().update(key(&topic), d),
+                    Err(e) => eprintln!("skipping message on {}: {}", topic, e)
+                }
+            },
+            SUBACK if body.get(2) == Some(&0x80) => {
+                return Err(io::Error::other(format!("subscription to {} refused", filter)))
+            },
+            _ => ()
+        }
+    })();
+    let _ = w.lock().unwrap().shutdown(Shutdown::Both);
+    result
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes())
+}
+
+fn write_packet<W: Write>(w: &mut W, t: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![t];
+    // The remaining length, 7 bits at a time.
+    let mut n = body.len();
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        packet.push(if n > 0 { b | 0x80 } else { b });
+        if n == 0 {
+            break
+        }
+    };
+    packet.extend_from_slice(body);
+    w.write_all(&packet)
+}
+
+fn read_packet<R: Read>(r: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    let t = b[0];
+    let mut n = 0;
+    for i in 0..4 {
+        r.read_exact(&mut b)?;
+        n |= ((b[0] & 0x7f) as usize) << (7 * i);
+        if b[0] & 0x80 == 0 {
+            break
+        }
+    };
+    if n as u64 > MAX_PACKET {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("packet of {} bytes", n)))
+    };
+    // Read as it comes rather than allocated up front, in case the length
+    // is a lie.
+    let mut body = Vec::new();
+    if r.take(n as u64).read_to_end(&mut body)? < n {
+        return Err(io::ErrorKind::UnexpectedEof.into())
+    };
+    Ok((t, body))
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use super::{epsilon, Solve, QRE};
+use super::QRE::*;
+
+type Callback<C> = Arc<dyn Fn(&[C]) + Send + Sync>;
+
+/// A callback registered with `Solve::observe`.
+pub struct Observer<C> {
+    name: &'static str,
+    callback: Callback<C>,
+}
+
+impl <C> Clone for Observer<C> {
+    fn clone(&self) -> Self {
+        Observer { name: self.name, callback: self.callback.clone() }
+    }
+}
+
+/// Wraps the sub-expression of `q` at `path` in a `Tag` called `name`, so
+/// it can be observed (or captured) without rebuilding the query by hand.
+/// Each step of the path picks a child: `Choice`'s `i`th alternative,
+/// 0/1 for `Split` and `Combine`'s `f`/`g` and `Iter`'s `init`/`body`,
+/// and 0 for the one child of the other nodes.
+pub fn tag_at<D,C: Clone>(q: QRE<D,C>, path: &[usize], name: &'static str) -> Result<QRE<D,C>, String> {
+    let (i, rest) = match path.split_first() {
+        None => return Ok(Tag{name, f: Arc::new(q)}),
+        Some((i, rest)) => (*i, rest)
+    };
+    let child = |a: Arc<QRE<D,C>>| -> Result<Arc<QRE<D,C>>, String> {
+        let q = Arc::try_unwrap(a).unwrap_or_else(|a| (*a).clone());
+        Ok(Arc::new(tag_at(q, rest, name)?))
+    };
+    let bad = || Err(format!("no child {} on the path to {}", i, name));
+    match q {
+        Choice{mut v} if i < v.len() => {
+            let q = std::mem::replace(&mut v[i], Bot);
+            v[i] = tag_at(q, rest, name)?;
+            Ok(Choice{v})
+        },
+        Split{f, g, op} if i == 0 => Ok(Split{f: child(f)?, g, op}),
+        Split{f, g, op} if i == 1 => Ok(Split{f, g: child(g)?, op}),
+        Combine{f, g, op} if i == 0 => Ok(Combine{f: child(f)?, g, op}),
+        Combine{f, g, op} if i == 1 => Ok(Combine{f, g: child(g)?, op}),
+        TryCombine{f, g, op} if i == 0 => Ok(TryCombine{f: child(f)?, g, op}),
+        TryCombine{f, g, op} if i == 1 => Ok(TryCombine{f, g: child(g)?, op}),
+        Iter{init, body, op} if i == 0 => Ok(Iter{init: child(init)?, body, op}),
+        Iter{init, body, op} if i == 1 => Ok(Iter{init, body: child(body)?, op}),
+        App{f, op} if i == 0 => Ok(App{f: child(f)?, op}),
+        Tag{name: n, f} if i == 0 => Ok(Tag{name: n, f: child(f)?}),
+        Trigger{body, action} if i == 0 => Ok(Trigger{body: child(body)?, action}),
+        _ => bad()
+    }
+}
+
+fn collect<D,C: Clone>(q: &QRE<D,C>, name: &str, seen: &mut HashSet<*const QRE<D,C>>, out: &mut Vec<C>) {
+    if !seen.insert(q as *const QRE<D,C>) {
+        return
+    };
+    match q {
+        Bot | Eps{..} | Sat{..} | TrySat{..} => (),
+        Choice{v} => {
+            for q in v {
+                collect(q, name, seen, out)
+            }
+        },
+        Split{f, g, ..} | Combine{f, g, ..} | TryCombine{f, g, ..} | Iter{init: f, body: g, ..} => {
+            collect(f, name, seen, out);
+            collect(g, name, seen, out)
+        },
+        Tag{name: n, f} if *n == name => {
+            out.extend(epsilon(f));
+            collect(f, name, seen, out)
+        },
+        App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => collect(f, name, seen, out),
+    }
+}
+
+impl <D,C> Solve<D,C> where C: Clone + Debug + Send + Sync {
+    /// Calls `callback` after every update with the current values of the
+    /// sub-expressions tagged `name` (see `Tag` and `tag_at`): one per
+    /// residual still in the middle of matching one, so none once it's
+    /// finished or before it's started. This walks the whole residual DAG,
+    /// so each update costs O(nodes) while any observer is registered.
+    pub fn observe<F>(&mut self, name: &'static str, callback: F)
+        where F: Fn(&[C]) + Send + Sync + 'static {
+        self.observers.push(Observer { name, callback: Arc::new(callback) })
+    }
+
+    pub(super) fn notify_observers(&self) {
+        for o in &self.observers {
+            let mut seen = HashSet::new();
+            let mut cs = Vec::new();
+            for q in &self.state[..] {
+                collect(q, o.name, &mut seen, &mut cs)
+            };
+            (o.callback)(&cs)
+        }
+    }
+}
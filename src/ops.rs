@@ -0,0 +1,338 @@
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
+
+use super::{AppOp, QRE};
+
+/// An associative op with a unit. It's a property of the cost type, so
+/// e.g. `Sum` and `Count` are distinct types even though both add.
+pub trait Monoid: Sized {
+    fn unit() -> Self;
+    fn combine(x: Self, y: Self) -> Self;
+}
+
+/// `body*`, aggregated with `M`'s op starting from its unit. Because the
+/// op is known to be associative, this can also be run in parallel; see
+/// `par::par_fold` and `par::Folds`.
+pub fn fold<D,M: Monoid>(body: QRE<D,M>) -> QRE<D,M> {
+    QRE::Iter{
+        init: Arc::new(QRE::Eps{c: M::unit()}),
+        body: Arc::new(body),
+        op: M::combine
+    }
+}
+
+/// A monoid whose contributions can be taken back out of an aggregate.
+pub trait Group: Monoid {
+    /// Removes `x`, which was combined in first, from `acc`.
+    fn retract(acc: Self, x: Self) -> Self;
+}
+
+/// The numbers `Sum`, `Max`, `Min` and the generic ops work over: the
+/// primitive integer and float types, so the same query works for `f32`
+/// readings or `i64` counters. A stand-in for `num_traits`' `Num` and
+/// `Bounded`, with just what aggregating needs.
+pub trait Num: Copy + PartialOrd + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> {
+    fn zero() -> Self;
+    #[allow(dead_code)]
+    fn one() -> Self;
+    /// The least value (`-inf` for floats): `Max`'s unit.
+    fn lowest() -> Self;
+    /// The greatest value (`inf` for floats): `Min`'s unit.
+    fn highest() -> Self;
+}
+
+macro_rules! int_num {
+    ($($t:ty)*) => {$(
+        impl Num for $t {
+            fn zero() -> Self { 0 }
+            fn one() -> Self { 1 }
+            fn lowest() -> Self { <$t>::MIN }
+            fn highest() -> Self { <$t>::MAX }
+        }
+    )*}
+}
+
+macro_rules! float_num {
+    ($($t:ty)*) => {$(
+        impl Num for $t {
+            fn zero() -> Self { 0.0 }
+            fn one() -> Self { 1.0 }
+            fn lowest() -> Self { <$t>::NEG_INFINITY }
+            fn highest() -> Self { <$t>::INFINITY }
+        }
+    )*}
+}
+
+int_num!(i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize);
+float_num!(f32 f64);
+
+/// `Split`/`Combine`/`Iter` ops for any `Num`, in place of one helper per
+/// type.
+pub fn add<T: Num>(x: T, y: T) -> T { x + y }
+
+#[allow(dead_code)]
+pub fn mul<T: Num>(x: T, y: T) -> T { x * y }
+
+/// The larger, or `x` if they're unordered (a float NaN).
+pub fn max<T: Num>(x: T, y: T) -> T { if y > x { y } else { x } }
+
+/// The smaller, or `x` if they're unordered (a float NaN).
+pub fn min<T: Num>(x: T, y: T) -> T { if y < x { y } else { x } }
+
+#[derive(Clone,Copy,Debug,Default,PartialEq,PartialOrd)]
+pub struct Sum<T = f64>(pub T);
+
+impl <T: Num> Monoid for Sum<T> {
+    fn unit() -> Self { Sum(T::zero()) }
+    fn combine(x: Self, y: Self) -> Self { Sum(x.0 + y.0) }
+}
+
+impl <T: Num> Group for Sum<T> {
+    fn retract(acc: Self, x: Self) -> Self { Sum(acc.0 - x.0) }
+}
+
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Count(pub u64);
+
+impl Monoid for Count {
+    fn unit() -> Self { Count(0) }
+    fn combine(x: Self, y: Self) -> Self { Count(x.0 + y.0) }
+}
+
+impl Group for Count {
+    fn retract(acc: Self, x: Self) -> Self { Count(acc.0 - x.0) }
+}
+
+
+#[derive(Clone,Copy,Debug,PartialEq,PartialOrd)]
+pub struct Max<T = f64>(pub T);
+
+impl <T: Num> Monoid for Max<T> {
+    fn unit() -> Self { Max(T::lowest()) }
+    fn combine(x: Self, y: Self) -> Self { Max(max(x.0, y.0)) }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,PartialOrd)]
+pub struct Min<T = f64>(pub T);
+
+impl <T: Num> Monoid for Min<T> {
+    fn unit() -> Self { Min(T::highest()) }
+    fn combine(x: Self, y: Self) -> Self { Min(min(x.0, y.0)) }
+}
+
+/// Aggregating pairs componentwise, so one `fold` can compute two
+/// statistics, e.g. `(Sum, Count)` for an average.
+impl <A: Monoid, B: Monoid> Monoid for (A, B) {
+    fn unit() -> Self { (A::unit(), B::unit()) }
+    fn combine(x: Self, y: Self) -> Self { (A::combine(x.0, y.0), B::combine(x.1, y.1)) }
+}
+
+macro_rules! tuple_monoid {
+    ($(($($t:ident $i:tt),*))*) => {$(
+        impl <$($t: Monoid),*> Monoid for ($($t,)*) {
+            fn unit() -> Self { ($($t::unit(),)*) }
+            fn combine(x: Self, y: Self) -> Self { ($($t::combine(x.$i, y.$i),)*) }
+        }
+    )*}
+}
+
+// And for up to six, so one `fold` can compute as many statistics in one
+// pass: a `Combine` of one `Iter` per statistic keeps a residual of each
+// alive, and pays for every one on every element.
+tuple_monoid!((A 0, B 1, C 2) (A 0, B 1, C 2, D 3) (A 0, B 1, C 2, D 3, E 4) (A 0, B 1, C 2, D 3, E 4, F 5));
+
+/// A `Split`/`Combine` op taking the first component from the left and the
+/// second from the right, e.g. for combining a query that computes a sum
+/// in `.0` with one that computes a count in `.1`.
+#[allow(dead_code)]
+pub fn pair<A, B>(x: (A, B), y: (A, B)) -> (A, B) { (x.0, y.1) }
+
+/// A `Split`/`Combine` op keeping the left result.
+#[allow(dead_code)]
+pub fn fst<T>(x: T, _y: T) -> T { x }
+
+/// A `Split`/`Combine` op keeping the right result.
+#[allow(dead_code)]
+pub fn snd<T>(_x: T, y: T) -> T { y }
+
+/// An `App` op applying `f` to the first component.
+#[allow(dead_code)]
+pub fn map_fst<A: 'static, B: 'static>(f: fn(A) -> A) -> AppOp<(A, B)> {
+    AppOp::Fn(Arc::new(move |(a, b)| (f(a), b)))
+}
+
+/// An `App` op applying `f` to the second component.
+#[allow(dead_code)]
+pub fn map_snd<A: 'static, B: 'static>(f: fn(B) -> B) -> AppOp<(A, B)> {
+    AppOp::Fn(Arc::new(move |(a, b)| (a, f(b))))
+}
+
+/// A value and when it was taken, in seconds from any fixed epoch.
+#[derive(Clone,Copy,Debug,PartialEq,PartialOrd)]
+pub struct Timed<T = f64> {
+    pub time: f64,
+    pub value: T,
+}
+
+/// The change between consecutive values, as a fold: `Delta::of` each
+/// element, and the aggregate's `change` is the last value less the one
+/// before it (`None` until there are two). It keeps the last value
+/// around, which is what makes the op associative.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Delta<T = f64> {
+    pub last: Option<T>,
+    pub change: Option<T>,
+}
+
+impl <T: Num> Delta<T> {
+    pub fn of(x: &T) -> Self {
+        Delta { last: Some(*x), change: None }
+    }
+}
+
+impl <T: Num> Monoid for Delta<T> {
+    fn unit() -> Self { Delta { last: None, change: None } }
+    fn combine(x: Self, y: Self) -> Self {
+        let change = match (x.last, y.last, y.change) {
+            (_, _, Some(c)) => Some(c),
+            (Some(a), Some(b), None) => Some(b - a),
+            _ => x.change
+        };
+        Delta { last: y.last.or(x.last), change }
+    }
+}
+
+/// `Delta` for `Timed` values, divided by the time between them: the
+/// rate of change, per second, between the last two elements. Two
+/// elements at the same time give an infinite (or NaN) rate.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Rate {
+    pub last: Option<Timed>,
+    pub per_second: Option<f64>,
+}
+
+impl Rate {
+    pub fn of(x: &Timed) -> Self {
+        Rate { last: Some(*x), per_second: None }
+    }
+
+    pub fn per_minute(&self) -> Option<f64> {
+        self.per_second.map(|r| r * 60.0)
+    }
+}
+
+impl Monoid for Rate {
+    fn unit() -> Self { Rate { last: None, per_second: None } }
+    fn combine(x: Self, y: Self) -> Self {
+        let per_second = match (x.last, y.last, y.per_second) {
+            (_, _, Some(r)) => Some(r),
+            (Some(a), Some(b), None) => Some((b.value - a.value) / (b.time - a.time)),
+            _ => x.per_second
+        };
+        Rate { last: y.last.or(x.last), per_second }
+    }
+}
+
+/// Least-squares fit of `y` against `x` over the matched points, from
+/// their sums: `Regression::of` each `(x, y)` (or `Regression::timed` for
+/// a trend over time). A group, so it also fits over a sliding `Window`.
+/// Sums of squares lose precision when the `x`s are large and close
+/// together (Unix times, say): measure them from a nearby origin.
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct Regression {
+    pub n: f64,
+    pub sx: f64,
+    pub sy: f64,
+    pub sxy: f64,
+    pub sxx: f64,
+    pub syy: f64,
+}
+
+impl Regression {
+    pub fn of(p: &(f64, f64)) -> Self {
+        let (x, y) = *p;
+        Regression { n: 1.0, sx: x, sy: y, sxy: x * y, sxx: x * x, syy: y * y }
+    }
+
+    pub fn timed(t: &Timed) -> Self {
+        Regression::of(&(t.time, t.value))
+    }
+
+    /// `None` with fewer than two distinct `x`s.
+    pub fn slope(&self) -> Option<f64> {
+        let d = self.n * self.sxx - self.sx * self.sx;
+        if d > 0.0 { Some((self.n * self.sxy - self.sx * self.sy) / d) } else { None }
+    }
+
+    pub fn intercept(&self) -> Option<f64> {
+        self.slope().map(|m| (self.sy - m * self.sx) / self.n)
+    }
+
+    /// Pearson's r; `None` unless both the `x`s and the `y`s vary.
+    pub fn correlation(&self) -> Option<f64> {
+        let dx = self.n * self.sxx - self.sx * self.sx;
+        let dy = self.n * self.syy - self.sy * self.sy;
+        if dx > 0.0 && dy > 0.0 {
+            Some(((self.n * self.sxy - self.sx * self.sy) / (dx * dy).sqrt()).clamp(-1.0, 1.0))
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl Monoid for Regression {
+    fn unit() -> Self { Regression::default() }
+    fn combine(x: Self, y: Self) -> Self {
+        Regression {
+            n: x.n + y.n,
+            sx: x.sx + y.sx,
+            sy: x.sy + y.sy,
+            sxy: x.sxy + y.sxy,
+            sxx: x.sxx + y.sxx,
+            syy: x.syy + y.syy,
+        }
+    }
+}
+
+impl Group for Regression {
+    fn retract(acc: Self, x: Self) -> Self {
+        Regression {
+            n: acc.n - x.n,
+            sx: acc.sx - x.sx,
+            sy: acc.sy - x.sy,
+            sxy: acc.sxy - x.sxy,
+            sxx: acc.sxx - x.sxx,
+            syy: acc.syy - x.syy,
+        }
+    }
+}
+
+/// Count, sum, least and greatest of the matched numbers, in one fold:
+/// `fold(Sat{phi, op: Summary::of})`. A `tuple` of `Count`, `Sum`, `Min`
+/// and `Max` does the same for other combinations.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Summary {
+    pub fn of(x: &f64) -> Self {
+        Summary { count: 1, sum: *x, min: *x, max: *x }
+    }
+
+    /// `None` if nothing matched.
+    pub fn mean(&self) -> Option<f64> {
+        if self.count > 0 { Some(self.sum / self.count as f64) } else { None }
+    }
+}
+
+impl Monoid for Summary {
+    fn unit() -> Self { Summary { count: 0, sum: 0.0, min: f64::INFINITY, max: f64::NEG_INFINITY } }
+    fn combine(x: Self, y: Self) -> Self {
+        Summary { count: x.count + y.count, sum: x.sum + y.sum, min: min(x.min, y.min), max: max(x.max, y.max) }
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt::Debug;
+use std::io::{self, Read};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::Solve;
+use http::{accept, body, respond};
+
+/// The largest export taken, in bytes.
+const MAX_EXPORT: u64 = 16 << 20;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Kind {
+    Gauge,
+    /// A sum, cumulative or per-interval depending on the exporter.
+    Sum,
+}
+
+/// One data point of a gauge or sum metric.
+#[derive(Clone,Debug,PartialEq)]
+pub struct MetricPoint {
+    pub name: String,
+    pub unit: String,
+    pub kind: Kind,
+    /// Seconds since the Unix epoch.
+    pub time: f64,
+    pub value: f64,
+    pub attributes: Vec<(String, String)>,
+    /// The attributes of the resource (service, host, ...) that reported
+    /// it.
+    pub resource: Vec<(String, String)>,
+}
+
+impl MetricPoint {
+    #[allow(dead_code)]
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.iter().chain(self.resource.iter())
+            .find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Accepts OTLP/HTTP metric exports (`POST /v1/metrics`, protobuf-encoded)
+/// on `addr` and feeds `solver` their gauge and sum points, in the order
+/// they appear in each export. Histograms and summaries are skipped. Only
+/// uncompressed exports are taken; a compressed one (exporters gzip by
+/// default) is answered 415, so turn compression off. Runs until the
+/// listener fails.
+#[allow(dead_code)]
+pub fn serve<A,C>(addr: A, solver: Arc<Mutex<Solve<MetricPoint,C>>>) -> io::Result<()>
+    where A: ToSocketAddrs, C: Clone + Debug + Send + Sync + 'static {
+    let listener = TcpListener::bind(addr)?;
+    for conn in listener.incoming() {
+        let conn = conn?;
+        let solver = solver.clone();
+        thread::spawn(move || -> io::Result<()> {
+            let (mut r, head) = accept(conn)?;
+            if (head.method.as_str(), head.path.as_str()) != ("POST", "/v1/metrics") {
+                return respond(r.get_mut(), "404 Not Found", "text/plain", b"not found\n")
+            };
+            if let Some(e) = head.encoding {
+                let msg = format!("{} exports aren't supported; turn off compression\n", e);
+                return respond(r.get_mut(), "415 Unsupported Media Type", "text/plain", msg.as_bytes())
+            };
+            let mut bytes = Vec::new();
+            body(&mut r, &head).take(MAX_EXPORT + 1).read_to_end(&mut bytes)?;
+            if bytes.len() as u64 > MAX_EXPORT {
+                return respond(r.get_mut(), "413 Payload Too Large", "text/plain", b"export too large\n")
+            };
+            match (decode_export(&bytes), solver.lock()) {
+                (_, Err(_)) => respond(r.get_mut(), "500 Internal Server Error", "text/plain", b"an update panicked\n"),
+                (Ok(points), Ok(mut s)) => {
+                    for p in points {
+                        s.update(p)
+                    };
+                    drop(s);
+                    // An empty ExportMetricsServiceResponse.
+                    respond(r.get_mut(), "200 OK", "application/x-protobuf", b"")
+                },
+                (Err(e), _) => respond(r.get_mut(), "400 Bad Request", "text/plain", e.as_bytes())
+            }
+        });
+    };
+    Ok(())
+}
+
+/// The gauge and sum points in a protobuf `ExportMetricsServiceRequest`.
+pub fn decode_export(bytes: &[u8]) -> Result<Vec<MetricPoint>, String> {
+    let mut out = Vec::new();
+    // ExportMetricsServiceRequest.resource_metrics
+    for rm in Pb::new(bytes).messages(1)? {
+        let mut resource = Vec::new();
+        for res in Pb::new(rm).messages(1)? {
+            resource = attributes(res, 1)?
+        };
+        // ResourceMetrics.scope_metrics, ScopeMetrics.metrics
+        for sm in Pb::new(rm).messages(2)? {
+            for m in Pb::new(sm).messages(2)? {
+                metric(m, &resource, &mut out)?
+            }
+        }
+    };
+    Ok(out)
+}
+
+fn metric(m: &[u8], resource: &[(String, String)], out: &mut Vec<MetricPoint>) -> Result<(), String> {
+    let (mut name, mut unit) = (String::new(), String::new());
+    let mut data = None;
+    let mut pb = Pb::new(m);
+    while let Some((field, v)) = pb.field()? {
+        match (field, v) {
+            (1, Wire::Len(b)) => name = String::from_utf8_lossy(b).into_owned(),
+            (3, Wire::Len(b)) => unit = String::from_utf8_lossy(b).into_owned(),
+            (5, Wire::Len(b)) => data = Some((Kind::Gauge, b)),
+            (7, Wire::Len(b)) => data = Some((Kind::Sum, b)),
+            _ => ()
+        }
+    };
+    let (kind, data) = match data {
+        Some(d) => d,
+        None => return Ok(())
+    };
+    // Gauge.data_points and Sum.data_points
+    for p in Pb::new(data).messages(1)? {
+        let mut point = MetricPoint {
+            name: name.clone(), unit: unit.clone(), kind,
+            time: 0.0, value: 0.0,
+            attributes: attributes(p, 7)?,
+            resource: resource.to_vec()
+        };
+        let mut pb = Pb::new(p);
+        while let Some((field, v)) = pb.field()? {
+            match (field, v) {
+                (3, Wire::I64(t)) => point.time = t as f64 / 1e9,
+                (4, Wire::I64(x)) => point.value = f64::from_bits(x),
+                (6, Wire::I64(x)) => point.value = x as i64 as f64,
+                _ => ()
+            }
+        };
+        out.push(point)
+    };
+    Ok(())
+}
+
+/// The `KeyValue`s in field `field` of `msg`, with values printed as
+/// strings.
+fn attributes(msg: &[u8], field: u32) -> Result<Vec<(String, String)>, String> {
+    let mut out = Vec::new();
+    for kv in Pb::new(msg).messages(field)? {
+        let (mut key, mut value) = (String::new(), String::new());
+        let mut pb = Pb::new(kv);
+        while let Some(f) = pb.field()? {
+            match f {
+                (1, Wire::Len(b)) => key = String::from_utf8_lossy(b).into_owned(),
+                (2, Wire::Len(b)) => value = any_value(b)?,
+                _ => ()
+            }
+        };
+        out.push((key, value))
+    };
+    Ok(out)
+}
+
+fn any_value(b: &[u8]) -> Result<String, String> {
+    let mut pb = Pb::new(b);
+    Ok(match pb.field()? {
+        Some((1, Wire::Len(s))) => String::from_utf8_lossy(s).into_owned(),
+        Some((2, Wire::Varint(x))) => (x != 0).to_string(),
+        Some((3, Wire::Varint(x))) => (x as i64).to_string(),
+        Some((4, Wire::I64(x))) => f64::from_bits(x).to_string(),
+        // Arrays, maps and bytes aren't worth flattening.
+        _ => String::new()
+    })
+}
+
+enum Wire<'a> {
+    Varint(u64),
+    I64(u64),
+    Len(&'a [u8]),
+    /// Skipped: no field read here is a fixed32.
+    I32,
+}
+
+/// A protobuf message, read field by field.
+struct Pb<'a> {
+    b: &'a [u8],
+}
+
+impl <'a> Pb<'a> {
+    fn new(b: &'a [u8]) -> Self {
+        Pb { b }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.b.len() < n {
+            return Err("truncated protobuf".to_string())
+        };
+        let (x, rest) = self.b.split_at(n);
+        self.b = rest;
+        Ok(x)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut x = 0;
+        for i in 0..10 {
+            let b = self.take(1)?[0];
+            x |= ((b & 0x7f) as u64) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok(x)
+            }
+        };
+        Err("bad varint".to_string())
+    }
+
+    fn field(&mut self) -> Result<Option<(u32, Wire<'a>)>, String> {
+        if self.b.is_empty() {
+            return Ok(None)
+        };
+        let key = self.varint()?;
+        let v = match key & 7 {
+            0 => Wire::Varint(self.varint()?),
+            1 => {
+                let mut b = [0; 8];
+                b.copy_from_slice(self.take(8)?);
+                Wire::I64(u64::from_le_bytes(b))
+            },
+            2 => {
+                let n = self.varint()? as usize;
+                Wire::Len(self.take(n)?)
+            },
+            5 => {
+                self.take(4)?;
+                Wire::I32
+            },
+            t => return Err(format!("unsupported wire type {}", t))
+        };
+        Ok(Some(((key >> 3) as u32, v)))
+    }
+
+    /// Every occurrence of the embedded-message field `field`.
+    fn messages(mut self, field: u32) -> Result<Vec<&'a [u8]>, String> {
+        let mut out = Vec::new();
+        while let Some((f, v)) = self.field()? {
+            if let (true, Wire::Len(b)) = (f == field, v) {
+                out.push(b)
+            }
+        };
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An export as an SDK would send it, in protobuf text format:
+    //   resource_metrics {
+    //     resource { attributes { key: "service.name" value { string_value: "api" } } }
+    //     scope_metrics {
+    //       scope { name: "io.example" version: "1.0" }
+    //       metrics { name: "cpu" description: "CPU use" unit: "1"
+    //         gauge { data_points { attributes { key: "host" value { string_value: "a" } }
+    //           start_time_unix_nano: 1699999999000000000 time_unix_nano: 1700000000000000000 as_double: 0.5 } } }
+    //       metrics { name: "requests" unit: "{request}"
+    //         sum { data_points { attributes { key: "code" value { int_value: 200 } }
+    //           time_unix_nano: 1700000000500000000 as_int: 42 flags: 0 }
+    //           aggregation_temporality: AGGREGATION_TEMPORALITY_CUMULATIVE is_monotonic: true } }
+    //       metrics { name: "latency" unit: "ms"
+    //         histogram { data_points { time_unix_nano: 1700000000000000000 count: 3 } } }
+    //       schema_url: "https://opentelemetry.io/schemas/1.21.0"
+    //     }
+    //   }
+    const EXPORT: &[u8] = b"\x0a\xf5\x01\x0a\x17\x0a\x15\x0a\x0c\x73\x65\x72\x76\x69\x63\x65\x2e\x6e\x61\x6d\x65\x12\x05\x0a\x03\x61\x70\x69\x12\xd9\x01\x0a\
+          \x11\x0a\x0a\x69\x6f\x2e\x65\x78\x61\x6d\x70\x6c\x65\x12\x03\x31\x2e\x30\x12\x3d\x0a\x03\x63\x70\x75\x12\x07\x43\x50\x55\x20\x75\
+          \x73\x65\x1a\x01\x31\x2a\x2a\x0a\x28\x3a\x0b\x0a\x04\x68\x6f\x73\x74\x12\x03\x0a\x01\x61\x11\x00\x36\x8f\xfa\xfd\x9c\x97\x17\x19\
+          \x00\x00\x2a\x36\xfe\x9c\x97\x17\x21\x00\x00\x00\x00\x00\x00\xe0\x3f\x12\x3e\x0a\x08\x72\x65\x71\x75\x65\x73\x74\x73\x1a\x09\x7b\
+          \x72\x65\x71\x75\x65\x73\x74\x7d\x3a\x27\x0a\x21\x3a\x0b\x0a\x04\x63\x6f\x64\x65\x12\x03\x18\xc8\x01\x19\x00\x65\xf7\x53\xfe\x9c\
+          \x97\x17\x31\x2a\x00\x00\x00\x00\x00\x00\x00\x40\x00\x10\x02\x18\x01\x12\x1c\x0a\x07\x6c\x61\x74\x65\x6e\x63\x79\x1a\x02\x6d\x73\
+          \x4a\x0d\x0a\x0b\x19\x00\x00\x2a\x36\xfe\x9c\x97\x17\x20\x03\x1a\x27\x68\x74\x74\x70\x73\x3a\x2f\x2f\x6f\x70\x65\x6e\x74\x65\x6c\
+          \x65\x6d\x65\x74\x72\x79\x2e\x69\x6f\x2f\x73\x63\x68\x65\x6d\x61\x73\x2f\x31\x2e\x32\x31\x2e\x30";
+
+    #[test]
+    fn decodes_an_export() {
+        let resource = vec![("service.name".to_string(), "api".to_string())];
+        assert_eq!(decode_export(EXPORT), Ok(vec![
+            MetricPoint {
+                name: "cpu".into(), unit: "1".into(), kind: Kind::Gauge, time: 1.7e9, value: 0.5,
+                attributes: vec![("host".into(), "a".into())], resource: resource.clone()
+            },
+            MetricPoint {
+                name: "requests".into(), unit: "{request}".into(), kind: Kind::Sum, time: 1.7e9 + 0.5, value: 42.0,
+                attributes: vec![("code".into(), "200".into())], resource
+            },
+        ]));
+    }
+
+    #[test]
+    fn rejects_truncated_exports() {
+        for n in [1, 2, 40, EXPORT.len() - 1] {
+            assert!(decode_export(&EXPORT[..n]).is_err(), "{}", n)
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::thread;
+
+use super::{one, single_element, AppOp, One, Pool, Solve, QRE};
+use super::QRE::*;
+use fallible;
+use ops::{fold, Monoid};
+
+/// Evaluates `fold(body)` on `batch`, splitting it into one chunk per
+/// thread, folding the chunks in parallel and combining the results in
+/// order. That's only valid if every iteration of `body` is a single
+/// element (so no match straddles a chunk boundary); other bodies are
+/// evaluated sequentially.
+pub fn par_fold<D,M>(body: &QRE<D,M>, batch: &[D], threads: usize) -> Result<M, String>
+    where D: Sync, M: Monoid + Clone + Debug + Send + Sync + 'static {
+    if !single_element(body) || threads <= 1 || batch.len() < 2 {
+        let mut s = Solve::new(fold(body.clone()));
+        for d in batch {
+            s.update(d)
+        };
+        return s.output()
+    };
+    let chunk = batch.len().div_ceil(threads);
+    let partials: Vec<Result<M, String>> = thread::scope(|scope| {
+        let handles: Vec<_> = batch.chunks(chunk).map(|ds| {
+            scope.spawn(move || {
+                let mut pool = Pool::new();
+                let mut buf = Vec::new();
+                let mut acc = M::unit();
+                for d in ds {
+                    let v = one(body, d, &mut pool, &mut buf);
+                    if let Some((e, _)) = fallible::take() {
+                        return Err(e.to_string())
+                    };
+                    match v {
+                        One::Value(c) => acc = M::combine(acc, c),
+                        _ => return Err("undefined".to_string())
+                    }
+                };
+                Ok(acc)
+            })
+        }).collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let mut acc = M::unit();
+    for p in partials {
+        acc = M::combine(acc, p?)
+    };
+    Ok(acc)
+}
+
+/// A query for `par_backfill`: `fold`s under any number of `combine`s and
+/// `app`s. It's only built through these, so every `Iter` in it is known
+/// to aggregate with `M`'s op from a constant; function pointers can't be
+/// compared to find that out from a `QRE`. If the bodies all match single
+/// elements, a stream can then be cut anywhere, since no iteration
+/// straddles the cut, and the parts' aggregates combined in order, since
+/// the op is associative.
+#[derive(Clone)]
+pub struct Folds<D,M>(QRE<D,M>);
+
+impl <D,M: Monoid> Folds<D,M> {
+    /// `ops::fold(body)`.
+    pub fn fold(body: QRE<D,M>) -> Self {
+        Folds(fold(body))
+    }
+
+    /// `Combine`s the two with `op`, which needn't be `M`'s.
+    #[allow(dead_code)]
+    pub fn combine(self, g: Folds<D,M>, op: fn(M,M) -> M) -> Self {
+        Folds(Combine{f: Arc::new(self.0), g: Arc::new(g.0), op})
+    }
+
+    #[allow(dead_code)]
+    pub fn app(self, op: AppOp<M>) -> Self {
+        Folds(App{f: Arc::new(self.0), op})
+    }
+
+    #[allow(dead_code)]
+    pub fn query(&self) -> &QRE<D,M> {
+        &self.0
+    }
+}
+
+/// The bodies of a `Folds` query's `Iter`s, in pre-order.
+fn bodies<'a, D, M>(q: &'a QRE<D,M>, out: &mut Vec<&'a QRE<D,M>>) {
+    match q {
+        Iter{body, ..} => out.push(body),
+        Combine{f, g, ..} => {
+            bodies(f, out);
+            bodies(g, out)
+        },
+        App{f, ..} => bodies(f, out),
+        _ => ()
+    }
+}
+
+/// The residual a solver for `q` would have after the whole stream, given
+/// each `Iter`'s aggregate over it (`None` if its body failed to match
+/// some element); `None` if there's no residual left.
+fn rebuild<D, M, I>(q: &QRE<D,M>, totals: &mut I) -> Option<QRE<D,M>>
+    where M: Monoid + Clone, I: Iterator<Item = Option<M>> {
+    match q {
+        Iter{init, body, op} => {
+            let total = totals.next().unwrap()?;
+            let c = match **init { Eps{ref c} => M::combine(c.clone(), total), _ => unreachable!() };
+            Some(Iter{init: Arc::new(Eps{c}), body: body.clone(), op: *op})
+        },
+        Combine{f, g, op} => {
+            // Both sides use up their totals, even if f's is dead.
+            let (f, g) = (rebuild(f, totals), rebuild(g, totals));
+            Some(Combine{f: Arc::new(f?), g: Arc::new(g?), op: *op})
+        },
+        App{f, op} => Some(App{f: Arc::new(rebuild(f, totals)?), op: op.clone()}),
+        _ => unreachable!()
+    }
+}
+
+/// A solver for `q` that has seen `batch`, e.g. to backfill from a
+/// recorded stream before going live. If every fold's body matches single
+/// elements, the batch is cut into one chunk per thread, each thread
+/// aggregates its chunk for every `Iter` at once, and the aggregates are
+/// merged in order into the solver's state. Otherwise it's run through the
+/// batch sequentially, as it also is if a body matches some element two
+/// ways: a sequential solver is ambiguous from then on, which the
+/// aggregates can't express.
+pub fn par_backfill<D,M>(q: Folds<D,M>, batch: &[D], threads: usize) -> Result<Solve<D,M>, String>
+    where D: Sync, M: Monoid + Clone + Debug + Send + Sync + 'static {
+    let mut s = Solve::new(q.0);
+    let mut leaves = Vec::new();
+    bodies(&s.query, &mut leaves);
+    if !leaves.iter().all(|b| single_element(b)) || threads <= 1 || batch.len() < 2 {
+        for d in batch {
+            s.update(d)
+        };
+        return Ok(s)
+    };
+    let n = leaves.len();
+    let leaves = &leaves;
+    let chunk = batch.len().div_ceil(threads);
+    // Each chunk's aggregates, or `None` if it was ambiguous.
+    let partials: Vec<Result<Option<Vec<Option<M>>>, String>> = thread::scope(|scope| {
+        let handles: Vec<_> = batch.chunks(chunk).map(|ds| {
+            scope.spawn(move || {
+                let mut pool = Pool::new();
+                let mut buf = Vec::new();
+                let mut accs: Vec<Option<M>> = leaves.iter().map(|_| Some(M::unit())).collect();
+                for d in ds {
+                    for (body, acc) in leaves.iter().zip(accs.iter_mut()) {
+                        let a = match acc.take() {
+                            Some(a) => a,
+                            None => continue
+                        };
+                        let v = one(body, d, &mut pool, &mut buf);
+                        if let Some((e, _)) = fallible::take() {
+                            return Err(e.to_string())
+                        };
+                        match v {
+                            One::Value(c) => *acc = Some(M::combine(a, c)),
+                            One::NoMatch => (),
+                            One::Ambiguous => return Ok(None)
+                        }
+                    }
+                };
+                Ok(Some(accs))
+            })
+        }).collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let partials = match partials.into_iter().collect::<Result<Option<Vec<_>>, String>>()? {
+        Some(partials) => partials,
+        None => {
+            for d in batch {
+                s.update(d)
+            };
+            return Ok(s)
+        }
+    };
+    let mut totals: Vec<Option<M>> = (0..n).map(|_| Some(M::unit())).collect();
+    for p in partials {
+        for (total, part) in totals.iter_mut().zip(p) {
+            *total = match (total.take(), part) {
+                (Some(t), Some(x)) => Some(M::combine(t, x)),
+                _ => None
+            }
+        }
+    };
+    let state = rebuild(&s.query, &mut totals.into_iter());
+    s.state = Arc::new(state.into_iter().collect());
+    s.max_workingset = 1;
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ops::Sum;
+
+    fn any(_: &u64) -> bool { true }
+    fn one(x: &u64) -> Sum<u64> { Sum(*x) }
+    fn two(x: &u64) -> Sum<u64> { Sum(2 * *x) }
+
+    #[test]
+    fn backfills_ambiguity_like_a_sequential_run() {
+        let batch: Vec<u64> = (1..=8).collect();
+        let sequential = |body: QRE<u64, Sum<u64>>| {
+            let mut s = Solve::new(fold(body));
+            for d in &batch {
+                s.update(d)
+            };
+            s
+        };
+        let unambiguous = Sat{phi: any, op: one};
+        let ambiguous = Choice{v: vec![Sat{phi: any, op: one}, Sat{phi: any, op: two}]};
+        for body in [unambiguous, ambiguous] {
+            let mut par = par_backfill(Folds::fold(body.clone()), &batch, 4).unwrap();
+            let mut seq = sequential(body);
+            assert_eq!(par.output(), seq.output());
+            par.update(9);
+            seq.update(9);
+            assert_eq!(par.output(), seq.output())
+        }
+    }
+}
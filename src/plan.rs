@@ -0,0 +1,112 @@
+use super::QRE;
+use canon;
+use det;
+
+/// How `Solve` derives its residuals.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Backend {
+    /// The general one: every residual is derived, and the new generation
+    /// put in canonical form.
+    Interpreter,
+    /// A single residual derived in place, like a step of an automaton;
+    /// see `det`. Only for queries that are `det::deterministic`, and it
+    /// falls back to the interpreter on an update where that doesn't pan
+    /// out (a `Choice` turns out ambiguous).
+    Deterministic,
+}
+
+/// Updates between a `Planner`'s decisions.
+pub const WINDOW: u64 = 256;
+
+/// Picks a query's backend, and changes its mind as the query runs: the
+/// deterministic backend is cheaper when it works, and a waste of time
+/// when most updates fall back to the interpreter anyway (or never try it,
+/// the working set having grown past one residual).
+#[derive(Clone,Debug)]
+pub struct Planner {
+    eligible: bool,
+    forced: Option<Backend>,
+    current: Backend,
+    switches: u64,
+    // Windows the working set has to stay at one residual before going
+    // back to the deterministic backend, doubling each time it's given up
+    // on: a single residual doesn't mean `det::step` can handle it. And
+    // how many it's been so far.
+    patience: u64,
+    calm: u64,
+    // This window's updates, how many the interpreter did, and the widest
+    // the working set got.
+    updates: u64,
+    interpreted: u64,
+    widest: usize,
+}
+
+impl Planner {
+    pub fn new<D,C>(q: &QRE<D,C>) -> Self {
+        let eligible = det::deterministic(q) && !canon::has_hooks(q);
+        Planner {
+            eligible,
+            forced: None,
+            current: if eligible { Backend::Deterministic } else { Backend::Interpreter },
+            switches: 0,
+            patience: 1,
+            calm: 0,
+            updates: 0,
+            interpreted: 0,
+            widest: 0,
+        }
+    }
+
+    /// Whether the query can run on `Backend::Deterministic` at all.
+    pub fn eligible(&self) -> bool {
+        self.eligible
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.current
+    }
+
+    pub fn switches(&self) -> u64 {
+        self.switches
+    }
+
+    /// Pins the backend (`None` to let the planner choose again).
+    pub fn force(&mut self, backend: Option<Backend>) -> Result<(), String> {
+        if backend == Some(Backend::Deterministic) && !self.eligible {
+            return Err("the query isn't deterministic".to_string())
+        };
+        self.forced = backend;
+        if let Some(b) = backend {
+            self.current = b
+        };
+        Ok(())
+    }
+
+    /// Notes how an update went: whether the interpreter did it, and the
+    /// working set it left.
+    pub fn observe(&mut self, interpreted: bool, workingset: usize) {
+        self.updates += 1;
+        self.interpreted += interpreted as u64;
+        self.widest = self.widest.max(workingset);
+        if self.updates < WINDOW {
+            return
+        };
+        self.calm = if self.widest <= 1 { self.calm + 1 } else { 0 };
+        let next = match self.current {
+            Backend::Deterministic if self.interpreted > WINDOW / 2 => {
+                self.patience *= 2;
+                Backend::Interpreter
+            },
+            Backend::Interpreter if self.eligible && self.calm >= self.patience => Backend::Deterministic,
+            b => b
+        };
+        if self.forced.is_none() && next != self.current {
+            self.current = next;
+            self.switches += 1;
+            self.calm = 0
+        };
+        self.updates = 0;
+        self.interpreted = 0;
+        self.widest = 0
+    }
+}
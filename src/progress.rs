@@ -0,0 +1,162 @@
+use std::fmt::{self, Debug};
+use std::io::{self, BufRead, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::Solve;
+
+/// How many bytes have been read through a `Counted` reader so far.
+/// Clones share the count, so it can still be read once the reader has
+/// been handed off (to `Pattern::lines`, say).
+#[derive(Clone,Debug,Default)]
+pub struct Bytes(Arc<AtomicU64>);
+
+impl Bytes {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A reader that counts the bytes taken from it; see `counted`.
+pub struct Counted<R> {
+    inner: R,
+    bytes: Bytes,
+}
+
+/// Wraps `r` to count the bytes read from it.
+pub fn counted<R>(r: R) -> (Counted<R>, Bytes) {
+    let bytes = Bytes::default();
+    (Counted { inner: r, bytes: bytes.clone() }, bytes)
+}
+
+impl <R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.0.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl <R: BufRead> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.bytes.0.fetch_add(n as u64, Ordering::Relaxed);
+        self.inner.consume(n)
+    }
+}
+
+/// How far `run` has got.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Progress {
+    pub elements: u64,
+    /// Out of this many, if the source's size is known.
+    pub total_elements: Option<u64>,
+    /// Bytes read, if `Options::bytes` is counting them, and out of how
+    /// many.
+    pub bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    pub workingset: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// How much of the source is done, from 0 to 1, by bytes where they're
+    /// known (lines vary in length, bytes don't) and else by elements.
+    pub fn fraction(&self) -> Option<f64> {
+        let of = |n: u64, total: u64| if total == 0 { 1.0 } else { (n as f64 / total as f64).min(1.0) };
+        match (self.bytes, self.total_bytes, self.total_elements) {
+            (Some(n), Some(total), _) => Some(of(n, total)),
+            (_, _, Some(total)) => Some(of(self.elements, total)),
+            _ => None
+        }
+    }
+
+    /// The time left, if it's done at the same rate as so far.
+    pub fn eta(&self) -> Option<Duration> {
+        match self.fraction() {
+            Some(f) if f > 0.0 => Duration::try_from_secs_f64(self.elapsed.as_secs_f64() * (1.0 - f) / f).ok(),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} elements", self.elements)?;
+        if let Some(total) = self.total_elements {
+            write!(f, " of {}", total)?
+        };
+        if let Some(n) = self.bytes {
+            write!(f, ", {} bytes", n)?;
+            if let Some(total) = self.total_bytes {
+                write!(f, " of {}", total)?
+            }
+        };
+        write!(f, ", workingset = {}, elapsed = {:.1?}", self.workingset, self.elapsed)?;
+        if let (Some(done), Some(eta)) = (self.fraction(), self.eta()) {
+            write!(f, " ({:.1}%, eta {:.1?})", 100.0 * done, eta)?
+        };
+        Ok(())
+    }
+}
+
+pub struct Options {
+    /// How often to report, in wall-clock time; there's always a last
+    /// report at the end.
+    pub every: Duration,
+    /// The source's length, if `run` can't tell from its size hint (a
+    /// `Vec` can say, `Pattern::lines` can't).
+    pub elements: Option<u64>,
+    /// The count from `counted`, for sources read from one, and the total
+    /// to expect (a file's length, from its metadata).
+    pub bytes: Option<(Bytes, Option<u64>)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { every: Duration::from_secs(1), elements: None, bytes: None }
+    }
+}
+
+// The clock is only read every this many elements, which is plenty often
+// for reports a second or so apart.
+const CHECK_EVERY: u64 = 256;
+
+/// Feeds `s` all of `source`, as `update` would, calling `report` as it
+/// goes (see `Options`) so that a long backfill isn't silent. Returns
+/// the final progress, as also last reported.
+pub fn run<D,C,I,F>(s: &mut Solve<D,C>, source: I, opts: Options, mut report: F) -> Progress
+    where C: Clone + Debug + Send + Sync,
+          I: IntoIterator<Item = D>, F: FnMut(&Progress) {
+    let source = source.into_iter();
+    let total_elements = opts.elements.or(match source.size_hint() {
+        (lo, Some(hi)) if lo == hi => Some(hi as u64),
+        _ => None
+    });
+    let start = Instant::now();
+    let mut next = start + opts.every;
+    let progress = |s: &Solve<D,C>, elements| Progress {
+        elements,
+        total_elements,
+        bytes: opts.bytes.as_ref().map(|(b, _)| b.get()),
+        total_bytes: opts.bytes.as_ref().and_then(|(_, total)| *total),
+        workingset: s.state.len() as u64,
+        elapsed: start.elapsed(),
+    };
+    let mut n = 0;
+    for d in source {
+        s.update(d);
+        n += 1;
+        if n % CHECK_EVERY == 0 && Instant::now() >= next {
+            report(&progress(s, n));
+            next = Instant::now() + opts.every
+        }
+    };
+    let last = progress(s, n);
+    report(&last);
+    last
+}
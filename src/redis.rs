@@ -0,0 +1,231 @@
+use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+use super::Solve;
+use runtime::{Decode, Hook};
+
+/// Which stream to read, as which consumer of which group, and which
+/// field of each entry holds the element.
+#[derive(Clone,Debug)]
+pub struct Source {
+    pub stream: String,
+    pub group: String,
+    pub consumer: String,
+    pub field: String,
+    /// Entries to ask for at a time.
+    pub count: usize,
+}
+
+/// Where outputs go after each entry.
+#[derive(Clone,Debug)]
+#[allow(dead_code)]
+pub enum Output {
+    Discard,
+    /// `SET` this key to the latest output.
+    Key(String),
+    /// `XADD` each output to this stream, under the field `output`.
+    Stream(String),
+}
+
+/// Consumes `src` (creating the group if need be) and feeds `solver`,
+/// writing outputs to `out`. Entries are only acknowledged once they're
+/// safe: every `every` entries `checkpoint` is called (to persist
+/// `Solve::checkpoint`), and only then are the entries since the last one
+/// `XACK`ed. A consumer that dies in between gets them again: on start it
+/// first re-reads whatever it had been delivered and not acknowledged, so
+/// restore the solver from the last checkpoint before calling this.
+/// Without a checkpoint, entries are acknowledged as soon as they're fed.
+/// Delivery is at least once: dying after a checkpoint is saved but
+/// before its `XACK` goes through replays the entries it covers, so a
+/// solver restored from it counts them twice.
+/// Runs until the connection fails or Redis rejects a command.
+#[allow(dead_code)]
+pub fn consume<A,D,C>(addr: A, src: &Source, solver: Arc<Mutex<Solve<D,C>>>, decode: Decode<D>,
+                      out: &Output, mut checkpoint: Option<(usize, Hook<D,C>)>) -> io::Result<()>
+    where A: ToSocketAddrs, C: Clone + Debug + Send + Sync {
+    let mut conn = Conn::connect(addr)?;
+    match conn.call(&["XGROUP", "CREATE", &src.stream, &src.group, "$", "MKSTREAM"])? {
+        Reply::Error(e) if !e.starts_with("BUSYGROUP") => return Err(io::Error::other(e)),
+        _ => ()
+    };
+    let count = src.count.max(1).to_string();
+    let mut unacked: Vec<String> = Vec::new();
+    // An id re-reads our own pending entries after it; once they're done,
+    // ">" waits for new ones.
+    let mut from = "0".to_string();
+    loop {
+        let reply = conn.call(&["XREADGROUP", "GROUP", &src.group, &src.consumer, "COUNT", &count,
+                                "BLOCK", "0", "STREAMS", &src.stream, &from])?;
+        let entries = entries(reply)?;
+        if from != ">" {
+            from = match entries.last() {
+                Some((id, _)) => id.clone(),
+                None => ">".to_string()
+            }
+        };
+        for (id, fields) in entries {
+            let value = fields.iter().find(|(f, _)| *f == src.field).map(|(_, v)| v.as_str());
+            match value.ok_or_else(|| format!("no field {}", src.field)).and_then(|v| decode(v)) {
+                Ok(d) => {
+                    let mut s = solver.lock().unwrap();
+                    s.update(d);
+                    if let Some(c) = s.defined_output() {
+                        let c = format!("{:?}", c);
+                        match out {
+                            Output::Discard => (),
+                            Output::Key(k) => { conn.command(&["SET", k, &c])?; },
+                            Output::Stream(k) => { conn.command(&["XADD", k, "*", "output", &c])?; }
+                        }
+                    }
+                },
+                Err(e) => eprintln!("skipping entry {}: {}", id, e)
+            };
+            unacked.push(id)
+        };
+        let due = match checkpoint {
+            Some((every, ref mut hook)) if unacked.len() >= every => {
+                hook(&solver.lock().unwrap());
+                true
+            },
+            Some(_) => false,
+            None => true
+        };
+        if due && !unacked.is_empty() {
+            let mut args = vec!["XACK", &src.stream, &src.group];
+            args.extend(unacked.iter().map(|id| id.as_str()));
+            conn.command(&args)?;
+            unacked.clear()
+        }
+    }
+}
+
+type Entry = (String, Vec<(String, String)>);
+
+/// The entries in an `XREADGROUP` reply for one stream.
+fn entries(reply: Reply) -> io::Result<Vec<Entry>> {
+    let bad = || io::Error::new(io::ErrorKind::InvalidData, "unexpected XREADGROUP reply");
+    let streams = match reply {
+        Reply::Nil => return Ok(Vec::new()),
+        Reply::Error(e) => return Err(io::Error::other(e)),
+        Reply::Array(streams) => streams,
+        _ => return Err(bad())
+    };
+    let mut out = Vec::new();
+    for stream in streams {
+        let mut stream = stream.array().ok_or_else(bad)?;
+        let es = stream.pop().and_then(Reply::array).ok_or_else(bad)?;
+        for e in es {
+            let mut e = e.array().ok_or_else(bad)?.into_iter();
+            let id = e.next().and_then(Reply::string).ok_or_else(bad)?;
+            // An entry deleted since it was delivered comes back as nil.
+            let fields = e.next().and_then(Reply::array).unwrap_or_default();
+            let mut fs = Vec::new();
+            let mut fields = fields.into_iter().filter_map(Reply::string);
+            while let (Some(f), Some(v)) = (fields.next(), fields.next()) {
+                fs.push((f, v))
+            };
+            out.push((id, fs))
+        }
+    };
+    Ok(out)
+}
+
+enum Reply {
+    Nil,
+    /// Integer replies (to `XACK`, say) aren't needed, just read past.
+    Int,
+    Str(String),
+    Error(String),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn array(self) -> Option<Vec<Reply>> {
+        match self { Reply::Array(v) => Some(v), _ => None }
+    }
+
+    fn string(self) -> Option<String> {
+        match self { Reply::Str(s) => Some(s), _ => None }
+    }
+}
+
+/// The longest bulk string we'll read, and the most elements an array can
+/// claim; larger replies drop the connection.
+const MAX_BULK: i64 = 1 << 26;
+const MAX_ARRAY: i64 = 1 << 20;
+
+/// Just enough of the Redis protocol (RESP2) to send commands and read
+/// their replies.
+struct Conn {
+    r: BufReader<TcpStream>,
+    w: TcpStream,
+}
+
+impl Conn {
+    fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let w = TcpStream::connect(addr)?;
+        Ok(Conn { r: BufReader::new(w.try_clone()?), w })
+    }
+
+    fn call(&mut self, args: &[&str]) -> io::Result<Reply> {
+        let mut cmd = format!("*{}\r\n", args.len());
+        for a in args {
+            cmd.push_str(&format!("${}\r\n{}\r\n", a.len(), a))
+        };
+        self.w.write_all(cmd.as_bytes())?;
+        self.reply()
+    }
+
+    /// `call`, failing on an error reply.
+    fn command(&mut self, args: &[&str]) -> io::Result<Reply> {
+        match self.call(args)? {
+            Reply::Error(e) => Err(io::Error::other(e)),
+            r => Ok(r)
+        }
+    }
+
+    fn reply(&mut self) -> io::Result<Reply> {
+        let mut line = String::new();
+        if self.r.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+        };
+        let line = line.trim_end();
+        let bad = || io::Error::new(io::ErrorKind::InvalidData, "bad reply");
+        let n = || line[1..].parse::<i64>().map_err(|_| bad());
+        match line.as_bytes().first() {
+            Some(b'+') => Ok(Reply::Str(line[1..].to_string())),
+            Some(b'-') => Ok(Reply::Error(line[1..].to_string())),
+            Some(b':') => n().map(|_| Reply::Int),
+            Some(b'$') => {
+                let n = n()?;
+                if n < 0 {
+                    return Ok(Reply::Nil)
+                };
+                if n > MAX_BULK {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bulk string of {} bytes", n)))
+                };
+                // Read as it comes rather than allocated up front, in case
+                // the length is a lie.
+                let mut buf = Vec::new();
+                if (&mut self.r).take(n as u64 + 2).read_to_end(&mut buf)? as i64 != n + 2 {
+                    return Err(io::ErrorKind::UnexpectedEof.into())
+                };
+                buf.truncate(n as usize);
+                Ok(Reply::Str(String::from_utf8_lossy(&buf).into_owned()))
+            },
+            Some(b'*') => {
+                let n = n()?;
+                if n < 0 {
+                    return Ok(Reply::Nil)
+                };
+                if n > MAX_ARRAY {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("array of {} elements", n)))
+                };
+                (0..n).map(|_| self.reply()).collect::<io::Result<_>>().map(Reply::Array)
+            },
+            _ => Err(bad())
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use super::Solve;
+use checkpoint::Persist;
+
+type Checkpoint<D,C> = fn(&Solve<D,C>) -> Result<Vec<u8>, String>;
+
+/// The elements from one checkpoint to the next, and the ones before.
+/// Only the last segment of a journal grows, so the rest are shared by
+/// every clone of the solver.
+struct Segment<D> {
+    /// How many elements came before it.
+    start: u64,
+    /// `None` if checkpointing failed.
+    checkpoint: Option<Vec<u8>>,
+    elements: Vec<D>,
+    prev: Option<Arc<Segment<D>>>,
+}
+
+impl <D> Drop for Segment<D> {
+    // Unlinked one at a time: a long journal would overflow the stack
+    // dropping recursively.
+    fn drop(&mut self) {
+        let mut prev = self.prev.take();
+        while let Some(p) = prev {
+            prev = match Arc::try_unwrap(p) {
+                Ok(mut p) => p.prev.take(),
+                Err(_) => None
+            }
+        }
+    }
+}
+
+/// A `Segment<D>`, whatever `D` is. Sharing segments between threads
+/// needs `D: Sync`, which only `record` asks for; erased, they don't make
+/// every solver's elements need it.
+type Erased = Arc<dyn Any + Send + Sync>;
+
+type Push<D> = fn(&mut Erased, &D, fn(&D) -> D);
+
+/// What a solver has seen since `Solve::record`: every element, and a
+/// checkpoint every `interval` of them.
+pub struct Journal<D,C: 'static> {
+    last: Erased,
+    len: u64,
+    interval: u64,
+    // `Solve::checkpoint`, `D::clone`, `push` and `close`, which need
+    // bounds (`C: Persist`, `D: Clone + Sync + 'static`) that `update`
+    // doesn't ask for.
+    checkpoint: Checkpoint<D,C>,
+    copy: fn(&D) -> D,
+    push: Push<D>,
+    close: fn(&mut Erased, u64, Option<Vec<u8>>),
+}
+
+impl <D,C> Clone for Journal<D,C> {
+    fn clone(&self) -> Self {
+        Journal { last: self.last.clone(), ..*self }
+    }
+}
+
+fn segment<D: 'static>(last: &Erased) -> &Segment<D> {
+    last.downcast_ref().expect("journal segment of another type")
+}
+
+fn push<D: Send + Sync + 'static>(last: &mut Erased, d: &D, copy: fn(&D) -> D) {
+    if let Some(l) = Arc::get_mut(last).and_then(|l| l.downcast_mut::<Segment<D>>()) {
+        l.elements.push(copy(d));
+        return
+    };
+    // Shared with a clone: copy the part that grows, at most `interval`
+    // elements.
+    let l = segment::<D>(last);
+    let mut elements: Vec<D> = l.elements.iter().map(copy).collect();
+    elements.push(copy(d));
+    *last = Arc::new(Segment { start: l.start, checkpoint: l.checkpoint.clone(), elements, prev: l.prev.clone() })
+}
+
+fn close<D: Send + Sync + 'static>(last: &mut Erased, start: u64, checkpoint: Option<Vec<u8>>) {
+    let prev = last.clone().downcast::<Segment<D>>().ok();
+    *last = Arc::new(Segment::<D> { start, checkpoint, elements: Vec::new(), prev })
+}
+
+/// Journals `d`, which `s` has just been updated with (if `s` is
+/// recording), checkpointing if it's time.
+pub fn append<D,C: Clone>(s: &mut Solve<D,C>, d: &D) {
+    let cp = {
+        let j = match s.journal.as_mut() { Some(j) => j, None => return };
+        (j.push)(&mut j.last, d, j.copy);
+        j.len += 1;
+        let n = j.len;
+        if n.is_multiple_of(j.interval) { Some((n, j.checkpoint)) } else { None }
+    };
+    if let Some((n, checkpoint)) = cp {
+        // One that fails (mid-migration, or once the solver has stopped)
+        // is left out; seeks past it replay from the one before, which
+        // stop the same way.
+        let bytes = checkpoint(s).ok();
+        if let Some(j) = s.journal.as_mut() {
+            (j.close)(&mut j.last, n, bytes)
+        }
+    }
+}
+
+impl <D,C> Solve<D,C> where C: Persist + Clone + Debug + Send + Sync {
+    /// Starts journaling elements, checkpointing every `interval` of
+    /// them, so that `seek` can go back to any point since. Offsets count
+    /// from here. The journal lives in memory and is shared with clones of
+    /// the solver; one that's shared copies the elements since its last
+    /// checkpoint when it's next updated. `migrate` drops it.
+    pub fn record(&mut self, interval: u64) -> Result<(), String> where D: Clone + Send + Sync + 'static {
+        let first = Segment::<D> { start: 0, checkpoint: Some(self.checkpoint()?), elements: Vec::new(), prev: None };
+        self.journal = Some(Box::new(Journal {
+            last: Arc::new(first),
+            len: 0,
+            interval: interval.max(1),
+            checkpoint: Solve::checkpoint,
+            copy: D::clone,
+            push: push::<D>,
+            close: close::<D>,
+        }));
+        Ok(())
+    }
+
+    /// How many elements have been journaled since `record`.
+    #[allow(dead_code)]
+    pub fn recorded(&self) -> u64 {
+        self.journal.as_ref().map_or(0, |j| j.len)
+    }
+
+    /// A solver as this one was after the first `offset` recorded
+    /// elements, for asking what the output was back then: the nearest
+    /// checkpoint at or before `offset`, with the elements after it
+    /// replayed. It has no alerts or observers, so history isn't
+    /// re-announced, and it doesn't record.
+    pub fn seek(&self, offset: u64) -> Result<Solve<D,C>, String> where D: 'static {
+        let j = self.journal.as_ref().ok_or("not recording")?;
+        if offset > j.len {
+            return Err(format!("only {} elements recorded", j.len))
+        };
+        // The segments back to the checkpoint to start from, latest first.
+        let mut segments = Vec::new();
+        let mut seg = Some(segment::<D>(&j.last));
+        let bytes = loop {
+            let s = seg.ok_or("no checkpoint to start from")?;
+            if s.start <= offset {
+                segments.push(s);
+                if let Some(bytes) = &s.checkpoint {
+                    break bytes
+                }
+            };
+            seg = s.prev.as_deref()
+        };
+        let mut s = Solve::restore(self.query.clone(), bytes)?;
+        s.errors.policy = self.errors.policy;
+        s.isolation = self.isolation;
+        for seg in segments.iter().rev() {
+            let n = (offset - seg.start).min(seg.elements.len() as u64) as usize;
+            for d in &seg.elements[..n] {
+                s.update(d)
+            }
+        };
+        Ok(s)
+    }
+}
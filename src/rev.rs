@@ -0,0 +1,73 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+const BLOCK: u64 = 8 * 1024;
+
+/// The lines of a seekable source, last line first, read in blocks from
+/// the end so a recorded stream can be fed to a reverse `Solve` in one
+/// pass without loading it into memory.
+pub struct RevLines<R> {
+    r: R,
+    // Everything before `pos` is still unread.
+    pos: u64,
+    // Unread-but-buffered bytes, in file order.
+    buf: Vec<u8>,
+    // The last block hasn't been read yet.
+    at_end: bool,
+    done: bool,
+}
+
+impl <R: Read + Seek> RevLines<R> {
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let pos = r.seek(SeekFrom::End(0))?;
+        Ok(RevLines { r, pos, buf: Vec::new(), at_end: true, done: pos == 0 })
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let n = BLOCK.min(self.pos);
+        self.pos -= n;
+        self.r.seek(SeekFrom::Start(self.pos))?;
+        let mut chunk = vec![0; n as usize];
+        self.r.read_exact(&mut chunk)?;
+        chunk.append(&mut self.buf);
+        self.buf = chunk;
+        if self.at_end {
+            // A trailing newline ends the last line; it doesn't start a new one.
+            if self.buf.last() == Some(&b'\n') {
+                self.buf.pop();
+            };
+            self.at_end = false
+        };
+        Ok(())
+    }
+}
+
+fn decode(mut line: Vec<u8>) -> io::Result<String> {
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    };
+    String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl <R: Read + Seek> Iterator for RevLines<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        loop {
+            if let Some(i) = self.buf.iter().rposition(|b| *b == b'\n') {
+                let line = self.buf.split_off(i + 1);
+                self.buf.truncate(i);
+                return Some(decode(line))
+            };
+            if self.pos == 0 {
+                if self.done {
+                    return None
+                };
+                self.done = true;
+                return Some(decode(std::mem::take(&mut self.buf)))
+            };
+            if let Err(e) = self.fill() {
+                return Some(Err(e))
+            }
+        }
+    }
+}
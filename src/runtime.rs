@@ -0,0 +1,201 @@
+use std::fmt::Debug;
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{Solve, QRE};
+
+/// Turns one record of a text source (a line, a message, an event's
+/// payload) into an element.
+#[allow(dead_code)]
+pub type Decode<D> = Arc<dyn Fn(&str) -> Result<D, String> + Send + Sync>;
+
+/// How long to wait before reconnecting a source: `initial`, doubling up
+/// to `max` with each failed attempt in a row. `retries` bounds how many
+/// failures in a row to put up with (`None` for no bound).
+#[derive(Clone,Copy,Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub retries: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff { initial: Duration::from_millis(100), max: Duration::from_secs(30), retries: None }
+    }
+}
+
+/// Runs `session` over and over, waiting between attempts as `backoff`
+/// says, until it gives up. A session sets its flag once it's connected;
+/// the backoff starts afresh after one that did, however it ended.
+#[allow(dead_code)]
+pub fn reconnecting<F>(backoff: Backoff, mut session: F) -> io::Result<()>
+    where F: FnMut(&mut bool) -> io::Result<()> {
+    let mut delay = backoff.initial;
+    let mut failures = 0;
+    loop {
+        let mut connected = false;
+        let result = session(&mut connected);
+        if connected {
+            delay = backoff.initial;
+            failures = 0
+        }
+        else if let Err(e) = result {
+            failures += 1;
+            if backoff.retries.is_some_and(|n| failures > n) {
+                return Err(e)
+            }
+        };
+        thread::sleep(delay);
+        delay = (delay * 2).min(backoff.max)
+    }
+}
+
+/// A background thread emitting a solver's output on a timer; see `every`.
+/// Dropping it stops the thread too, just without waiting for it.
+pub struct Periodic {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Periodic {
+    /// Stops emitting and waits for the thread to finish.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
+
+/// Calls `emit` every `interval` of wall-clock time with `solver`'s current
+/// output, or `default` while it's undefined, whether or not any elements
+/// arrived in between. Ticks are scheduled from the start time, so a slow
+/// `emit` doesn't make them drift.
+pub fn every<D,C,F>(solver: Arc<Mutex<Solve<D,C>>>, interval: Duration, default: C, mut emit: F) -> Periodic
+    where D: Send + 'static, C: Clone + Debug + Send + Sync + 'static, F: FnMut(C) + Send + 'static {
+    let (stop, stopped) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let mut next = Instant::now() + interval;
+        loop {
+            let wait = next.saturating_duration_since(Instant::now());
+            match stopped.recv_timeout(wait) {
+                Err(RecvTimeoutError::Timeout) => (),
+                _ => return
+            };
+            let c = solver.lock().unwrap().defined_output();
+            emit(c.unwrap_or_else(|| default.clone()));
+            next += interval
+        }
+    });
+    Periodic { stop, handle }
+}
+
+/// Called with the solver on `Options::checkpoint`'s timer and once more at
+/// the end, e.g. to write `Solve::checkpoint` somewhere durable.
+pub type Hook<D,C> = Box<dyn FnMut(&Solve<D,C>) + Send>;
+
+pub struct Options<D,C: 'static> {
+    /// How many elements can be buffered between the source and the
+    /// solver; a full buffer blocks the source.
+    pub capacity: usize,
+    pub checkpoint: Option<(Duration, Hook<D,C>)>,
+}
+
+impl <D,C> Default for Options<D,C> {
+    fn default() -> Self {
+        Options { capacity: 1024, checkpoint: None }
+    }
+}
+
+enum Msg<D> {
+    Elem(D),
+    // The source ran dry, or `shutdown` was called.
+    End,
+}
+
+/// A query running on its own threads; see `spawn`.
+pub struct Task<D,C> {
+    tx: SyncSender<Msg<D>>,
+    solver: JoinHandle<Result<C, String>>,
+}
+
+impl <D,C> Task<D,C> {
+    /// Stops taking elements from the source, finishes the ones already
+    /// buffered, runs the checkpoint hook a last time and returns the final
+    /// output. Signals aren't something std can catch, so it's up to the
+    /// caller to call this on SIGTERM.
+    #[allow(dead_code)]
+    pub fn shutdown(self) -> Result<C, String> {
+        let _ = self.tx.send(Msg::End);
+        self.join()
+    }
+
+    /// Waits for the source to run dry, then finishes up as `shutdown`
+    /// does.
+    pub fn join(self) -> Result<C, String> {
+        drop(self.tx);
+        self.solver.join().unwrap_or_else(|_| Err("solver thread panicked".to_string()))
+    }
+}
+
+/// Runs `query` over `source` on a background thread, passing `sink` the
+/// output after every element. The source is read on a thread of its own
+/// through a bounded channel, so a slow query pushes back on it rather than
+/// buffering without limit.
+pub fn spawn<D,C,I,F>(query: QRE<D,C>, source: I, mut sink: F, opts: Options<D,C>) -> Task<D,C>
+    where D: Send + 'static,
+          C: Clone + Debug + Send + Sync + 'static,
+          I: IntoIterator<Item = D> + Send + 'static,
+          F: FnMut(Result<C, String>) + Send + 'static {
+    let (tx, rx) = mpsc::sync_channel(opts.capacity);
+    let source_tx = tx.clone();
+    thread::spawn(move || {
+        for d in source {
+            // Fails once the solver's finished, after a `shutdown`.
+            if source_tx.send(Msg::Elem(d)).is_err() {
+                return
+            }
+        };
+        let _ = source_tx.send(Msg::End);
+    });
+    let mut checkpoint = opts.checkpoint;
+    let solver = thread::spawn(move || {
+        let mut s = Solve::new(query);
+        let mut next = checkpoint.as_ref().map(|(every, _)| Instant::now() + *every);
+        loop {
+            // Checked before every element, since under steady input the
+            // channel never times out.
+            if let (Some(t), Some((every, hook))) = (next, checkpoint.as_mut()) {
+                let now = Instant::now();
+                if now >= t {
+                    hook(&s);
+                    // A hook that overran its interval doesn't get
+                    // called back to back to catch up.
+                    next = Some(if t + *every > now { t + *every } else { now + *every })
+                }
+            };
+            let msg = match next {
+                Some(t) => match rx.recv_timeout(t.saturating_duration_since(Instant::now())) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => Msg::End
+                },
+                None => rx.recv().unwrap_or(Msg::End)
+            };
+            match msg {
+                Msg::Elem(d) => {
+                    s.update(d);
+                    sink(s.defined_output().ok_or_else(|| "undefined".to_string()))
+                },
+                Msg::End => break
+            }
+        };
+        if let Some((_, ref mut hook)) = checkpoint {
+            hook(&s)
+        };
+        s.defined_output().ok_or_else(|| "undefined".to_string())
+    });
+    Task { tx, solver }
+}
@@ -0,0 +1,170 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::{Solve, QRE};
+use ops::{Count, Sum};
+
+/// Aggregates that add up their elements' contributions (sums, counts),
+/// so a sample's can be scaled up to an estimate of the whole stream's.
+pub trait Linear {
+    fn total(&self) -> f64;
+}
+
+impl Linear for f64 {
+    fn total(&self) -> f64 {
+        *self
+    }
+}
+
+impl Linear for Count {
+    fn total(&self) -> f64 {
+        self.0 as f64
+    }
+}
+
+impl Linear for Sum<f64> {
+    fn total(&self) -> f64 {
+        self.0
+    }
+}
+
+impl Linear for Sum<i64> {
+    fn total(&self) -> f64 {
+        self.0 as f64
+    }
+}
+
+/// Given an element, the chance of sampling it.
+pub type Scheme<D> = Box<dyn FnMut(&D) -> f64 + Send>;
+
+/// Every element with the same chance, `rate`.
+pub fn uniform<D>(rate: f64) -> Scheme<D> {
+    Box::new(move |_| rate)
+}
+
+/// Per stratum (`key`): the first `min` elements of each, then the rest
+/// at `rate`, so that rare keys aren't missed altogether. Remembers every
+/// key it's seen.
+pub fn stratified<D,K,F>(key: F, min: u64, rate: f64) -> Scheme<D>
+    where K: Eq + Hash + Send + 'static, F: Fn(&D) -> K + Send + 'static {
+    let mut seen = HashMap::new();
+    Box::new(move |d| {
+        let n = seen.entry(key(d)).or_insert(0u64);
+        *n += 1;
+        if *n <= min { 1.0 } else { rate }
+    })
+}
+
+/// A `Sampled` query's scaled-up output.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    /// Standard error, and the 95% confidence interval it gives.
+    pub stderr: f64,
+    pub low: f64,
+    pub high: f64,
+    pub sampled: u64,
+    pub seen: u64,
+}
+
+/// A query run on a random sample of a stream, for exploring streams too
+/// fast to run it on all of: elements go to the solver with the chance
+/// the `Scheme` gives them. For a `Linear` aggregate (see `linear`), the
+/// contribution of each sampled element is weighted by one over its
+/// chance, which estimates the whole stream's total without bias.
+pub struct Sampled<D,C: 'static> {
+    solver: Solve<D,C>,
+    scheme: Scheme<D>,
+    // `Linear::total`, for a query built with `linear`.
+    total: Option<fn(&C) -> f64>,
+    rng: u64,
+    seen: u64,
+    sampled: u64,
+    // The total as of the last sampled element, and the running
+    // estimate and its variance.
+    last: f64,
+    estimate: f64,
+    variance: f64,
+}
+
+impl <D,C> Sampled<D,C> where C: Clone + Debug + Send + Sync {
+    /// Sampling `q`'s input as `scheme` says, with the random choices
+    /// made from `seed`, so a run can be repeated.
+    pub fn new(q: QRE<D,C>, scheme: Scheme<D>, seed: u64) -> Self {
+        Sampled {
+            solver: Solve::new(q),
+            scheme,
+            total: None,
+            rng: seed,
+            seen: 0,
+            sampled: 0,
+            last: 0.0,
+            estimate: 0.0,
+            variance: 0.0,
+        }
+    }
+
+    /// `new`, for a query whose output `estimate` can scale up.
+    pub fn linear(q: QRE<D,C>, scheme: Scheme<D>, seed: u64) -> Self where C: Linear {
+        Sampled { total: Some(C::total), ..Sampled::new(q, scheme, seed) }
+    }
+
+    pub fn update<B: Borrow<D>>(&mut self, d: B) {
+        let d = d.borrow();
+        self.seen += 1;
+        let p = (self.scheme)(d).clamp(0.0, 1.0);
+        if p < 1.0 && self.next_f64() >= p {
+            return
+        };
+        self.sampled += 1;
+        self.solver.update(d);
+        // An element contributes whatever it added to the total.
+        if let Some(total) = self.total {
+            if let Some(now) = self.solver.defined_output().map(|c| total(&c)) {
+                let x = now - self.last;
+                self.last = now;
+                self.estimate += x / p;
+                self.variance += (1.0 - p) / (p * p) * x * x
+            }
+        }
+    }
+
+    /// The query's output on the sample, as is.
+    #[allow(dead_code)]
+    pub fn output(&self) -> Result<C, String> {
+        self.solver.output()
+    }
+
+    /// The estimated output on the whole stream; fails unless the query
+    /// was built with `linear`.
+    pub fn estimate(&self) -> Result<Estimate, String> {
+        if self.total.is_none() {
+            return Err("not a linear aggregate".to_string())
+        };
+        let stderr = self.variance.sqrt();
+        Ok(Estimate {
+            value: self.estimate,
+            stderr,
+            low: self.estimate - 1.96 * stderr,
+            high: self.estimate + 1.96 * stderr,
+            sampled: self.sampled,
+            seen: self.seen,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn solver(&self) -> &Solve<D,C> {
+        &self.solver
+    }
+
+    /// Uniform in [0, 1), by splitmix64.
+    fn next_f64(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        ((z ^ (z >> 31)) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
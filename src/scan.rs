@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use super::QRE;
+use super::QRE::*;
+
+/// State that takes in a stream of numbers one at a time, e.g. `Anomaly`,
+/// `Forecast`, `Filter` or `Spectrum`. As a cost type it goes through
+/// `scan`, whose body produces each element as a `sample` and `step`s it
+/// into the state so far.
+pub trait Scan: Sized {
+    /// Just `x`, as the body produces it: nothing but `latest` is read
+    /// off a sample.
+    fn sample(x: f64) -> Self;
+    /// The element most recently pushed.
+    fn latest(&self) -> f64;
+    fn push(&mut self, x: f64);
+}
+
+/// An `Iter` op pushing the sample `p` into `acc`.
+pub fn step<S: Scan>(mut acc: S, p: S) -> S {
+    acc.push(p.latest());
+    acc
+}
+
+fn sample<S: Scan>(x: &f64) -> S {
+    S::sample(*x)
+}
+
+fn any(_: &f64) -> bool {
+    true
+}
+
+/// Pushes every element into `init`, so the output is its state after the
+/// whole stream.
+pub fn scan<S: Scan>(init: S) -> QRE<f64,S> {
+    Iter{
+        init: Arc::new(Eps{c: init}),
+        body: Arc::new(Sat{phi: any, op: sample::<S>}),
+        op: step::<S>
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt::{self, Debug};
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
+
+/// A vector that stores up to two elements inline and only spills to the
+/// heap beyond that. `epsilon` results almost always have one or two
+/// entries, so this keeps them off the allocator.
+#[derive(Clone)]
+pub enum SmallVec<T> {
+    Empty,
+    One([T; 1]),
+    Two([T; 2]),
+    Heap(Vec<T>),
+}
+
+impl <T> SmallVec<T> {
+    pub fn new() -> Self {
+        SmallVec::Empty
+    }
+
+    pub fn push(&mut self, x: T) {
+        let old = std::mem::replace(self, SmallVec::Empty);
+        *self = match old {
+            SmallVec::Empty => SmallVec::One([x]),
+            SmallVec::One([a]) => SmallVec::Two([a, x]),
+            SmallVec::Two([a, b]) => SmallVec::Heap(vec![a, b, x]),
+            SmallVec::Heap(mut v) => { v.push(x); SmallVec::Heap(v) }
+        }
+    }
+}
+
+impl <T> Default for SmallVec<T> {
+    fn default() -> Self {
+        SmallVec::new()
+    }
+}
+
+impl <T> Deref for SmallVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            SmallVec::Empty => &[],
+            SmallVec::One(a) => &a[..],
+            SmallVec::Two(a) => &a[..],
+            SmallVec::Heap(v) => &v[..],
+        }
+    }
+}
+
+impl <T> DerefMut for SmallVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            SmallVec::Empty => &mut [],
+            SmallVec::One(a) => &mut a[..],
+            SmallVec::Two(a) => &mut a[..],
+            SmallVec::Heap(v) => &mut v[..],
+        }
+    }
+}
+
+impl <T: Debug> Debug for SmallVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl <T> Extend<T> for SmallVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for x in iter {
+            self.push(x)
+        }
+    }
+}
+
+impl <T> FromIterator<T> for SmallVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut v = SmallVec::new();
+        v.extend(iter);
+        v
+    }
+}
+
+pub enum IntoIter<T> {
+    Inline(std::iter::Chain<std::option::IntoIter<T>, std::option::IntoIter<T>>),
+    Heap(std::vec::IntoIter<T>),
+}
+
+impl <T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            IntoIter::Inline(it) => it.next(),
+            IntoIter::Heap(it) => it.next(),
+        }
+    }
+}
+
+impl <T> IntoIterator for SmallVec<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        let (a, b) = match self {
+            SmallVec::Empty => (None, None),
+            SmallVec::One([a]) => (Some(a), None),
+            SmallVec::Two([a, b]) => (Some(a), Some(b)),
+            SmallVec::Heap(v) => return IntoIter::Heap(v.into_iter()),
+        };
+        IntoIter::Inline(a.into_iter().chain(b))
+    }
+}
+
+impl <'a, T> IntoIterator for &'a SmallVec<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> std::slice::Iter<'a, T> {
+        self.iter()
+    }
+}
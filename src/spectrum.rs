@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use checkpoint::{Persist, Reader};
+use scan::Scan;
+
+/// What the spectrum of the latest window looks like.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Features {
+    /// The frequency of the strongest component other than DC, in Hz.
+    pub dominant: f64,
+    /// Its share of the window's energy, in [0, 1].
+    pub dominance: f64,
+    /// Mean squared amplitude of the window, DC removed.
+    pub energy: f64,
+}
+
+/// Buffers the last `size` samples (a power of two) and takes their FFT
+/// every `hop` samples, as a `Scan`. Read `features` off the output, e.g.
+/// to feed another query or to gate one on `dominant` being in some band.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Spectrum {
+    size: usize,
+    hop: usize,
+    rate: f64,
+    samples: VecDeque<f64>,
+    // Samples since the last transform.
+    since: usize,
+    features: Option<Features>,
+    latest: f64,
+}
+
+impl Spectrum {
+    /// `rate` is the sample rate in Hz.
+    pub fn new(size: usize, hop: usize, rate: f64) -> Self {
+        assert!(size.is_power_of_two() && size >= 2, "Spectrum needs a power-of-two window");
+        Spectrum {
+            size, rate,
+            hop: hop.max(1),
+            samples: VecDeque::with_capacity(size),
+            since: 0,
+            features: None,
+            latest: 0.0
+        }
+    }
+
+    /// As of the latest transform; `None` until the window first fills.
+    pub fn features(&self) -> Option<Features> {
+        self.features
+    }
+
+    fn transform(&self) -> Features {
+        let n = self.size;
+        let mean = self.samples.iter().sum::<f64>() / n as f64;
+        // A Hann window keeps a component between bins from smearing
+        // across the whole spectrum.
+        let mut re: Vec<f64> = self.samples.iter().enumerate()
+            .map(|(i, x)| (x - mean) * (0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos()))
+            .collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+        let power: Vec<f64> = (0..n / 2 + 1).map(|k| re[k] * re[k] + im[k] * im[k]).collect();
+        let total: f64 = power[1..].iter().sum();
+        let (peak, p) = power.iter().enumerate().skip(1)
+            .fold((0, 0.0), |best, (k, p)| if *p > best.1 { (k, *p) } else { best });
+        Features {
+            dominant: peak as f64 * self.rate / n as f64,
+            dominance: if total > 0.0 { p / total } else { 0.0 },
+            energy: self.samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n as f64
+        }
+    }
+}
+
+impl Scan for Spectrum {
+    fn sample(x: f64) -> Self {
+        Spectrum { size: 0, hop: 0, rate: 0.0, samples: VecDeque::new(), since: 0, features: None, latest: x }
+    }
+
+    fn latest(&self) -> f64 {
+        self.latest
+    }
+
+    /// Buffers `x`, and takes the transform when it's due.
+    fn push(&mut self, x: f64) {
+        self.latest = x;
+        self.samples.push_back(x);
+        if self.samples.len() > self.size {
+            self.samples.pop_front();
+        };
+        self.since += 1;
+        if self.samples.len() == self.size && (self.features.is_none() || self.since >= self.hop) {
+            self.features = Some(self.transform());
+            self.since = 0
+        }
+    }
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1
+        };
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j)
+        }
+    };
+    let mut len = 2;
+    while len <= n {
+        let (wr, wi) = ((2.0 * PI / len as f64).cos(), -(2.0 * PI / len as f64).sin());
+        for start in (0..n).step_by(len) {
+            let (mut cr, mut ci) = (1.0, 0.0);
+            for k in 0..len / 2 {
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * cr - im[b] * ci;
+                let ti = re[b] * ci + im[b] * cr;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+                let c = cr * wr - ci * wi;
+                ci = cr * wi + ci * wr;
+                cr = c
+            }
+        };
+        len <<= 1
+    }
+}
+
+impl Persist for Features {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.dominant.write(w);
+        self.dominance.write(w);
+        self.energy.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        Ok(Features { dominant: f64::read(r)?, dominance: f64::read(r)?, energy: f64::read(r)? })
+    }
+}
+
+impl Persist for Spectrum {
+    fn write(&self, w: &mut Vec<u8>) {
+        self.size.write(w);
+        self.hop.write(w);
+        self.rate.write(w);
+        self.samples.write(w);
+        self.since.write(w);
+        self.features.write(w);
+        self.latest.write(w)
+    }
+    fn read(r: &mut Reader) -> Result<Self, String> {
+        let sp = Spectrum {
+            size: usize::read(r)?,
+            hop: usize::read(r)?,
+            rate: f64::read(r)?,
+            samples: VecDeque::read(r)?,
+            since: usize::read(r)?,
+            features: Option::read(r)?,
+            latest: f64::read(r)?
+        };
+        if !sp.size.is_power_of_two() || sp.size < 2 {
+            return Err(format!("Spectrum: a window of {}, not a power of two", sp.size))
+        };
+        if sp.hop == 0 || sp.samples.len() > sp.size {
+            return Err(format!("Spectrum: {} samples in a window of {}, hop {}", sp.samples.len(), sp.size, sp.hop))
+        };
+        Ok(sp)
+    }
+}
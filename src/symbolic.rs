@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::QRE;
+use super::QRE::*;
+use complexity;
+use fallible;
+
+/// A range of numbers, each end open or closed.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+    pub lo_open: bool,
+    pub hi_open: bool,
+}
+
+impl Interval {
+    pub fn all() -> Self {
+        Interval { lo: f64::NEG_INFINITY, hi: f64::INFINITY, lo_open: true, hi_open: true }
+    }
+
+    #[allow(dead_code)]
+    pub fn lt(x: f64) -> Self {
+        Interval { hi: x, hi_open: true, ..Interval::all() }
+    }
+
+    #[allow(dead_code)]
+    pub fn le(x: f64) -> Self {
+        Interval { hi: x, hi_open: false, ..Interval::all() }
+    }
+
+    #[allow(dead_code)]
+    pub fn gt(x: f64) -> Self {
+        Interval { lo: x, lo_open: true, ..Interval::all() }
+    }
+
+    #[allow(dead_code)]
+    pub fn ge(x: f64) -> Self {
+        Interval { lo: x, lo_open: false, ..Interval::all() }
+    }
+
+    #[allow(dead_code)]
+    pub fn eq(x: f64) -> Self {
+        Interval { lo: x, hi: x, lo_open: false, hi_open: false }
+    }
+
+    /// `lo <= x <= hi`.
+    #[allow(dead_code)]
+    pub fn between(lo: f64, hi: f64) -> Self {
+        Interval { lo, hi, lo_open: false, hi_open: false }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lo > self.hi || (self.lo == self.hi && (self.lo_open || self.hi_open))
+    }
+
+    fn meet(&self, o: &Interval) -> Interval {
+        let (lo, lo_open) = if self.lo > o.lo || (self.lo == o.lo && self.lo_open) {
+            (self.lo, self.lo_open)
+        } else {
+            (o.lo, o.lo_open)
+        };
+        let (hi, hi_open) = if self.hi < o.hi || (self.hi == o.hi && self.hi_open) {
+            (self.hi, self.hi_open)
+        } else {
+            (o.hi, o.hi_open)
+        };
+        Interval { lo, hi, lo_open, hi_open }
+    }
+
+    /// Everything outside it, as up to two intervals.
+    fn complement(&self) -> Vec<Interval> {
+        let mut v = Vec::new();
+        if self.lo > f64::NEG_INFINITY {
+            v.push(Interval { hi: self.lo, hi_open: !self.lo_open, ..Interval::all() })
+        };
+        if self.hi < f64::INFINITY {
+            v.push(Interval { lo: self.hi, lo_open: !self.hi_open, ..Interval::all() })
+        };
+        v
+    }
+}
+
+/// Past this many clauses, a `Pred` built by `and` or `not` gives up and
+/// becomes `tt`, which is always a safe answer: it only makes the engine
+/// prove less.
+const MAX_CLAUSES: usize = 256;
+
+/// A predicate the engine can reason about: a disjunction of clauses,
+/// each bounding some named numeric values (record fields, or `""` for a
+/// stream of plain numbers) to an interval apiece.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Pred {
+    clauses: Vec<Vec<(&'static str, Interval)>>,
+}
+
+impl Pred {
+    /// `name` is in `i`.
+    #[allow(dead_code)]
+    pub fn var(name: &'static str, i: Interval) -> Self {
+        Pred { clauses: vec![vec![(name, i)]] }.normal()
+    }
+
+    pub fn tt() -> Self {
+        Pred { clauses: vec![Vec::new()] }
+    }
+
+    pub fn ff() -> Self {
+        Pred { clauses: Vec::new() }
+    }
+
+    pub fn or(&self, o: &Pred) -> Pred {
+        let mut clauses = self.clauses.clone();
+        clauses.extend(o.clauses.iter().cloned());
+        Pred { clauses }.capped()
+    }
+
+    pub fn and(&self, o: &Pred) -> Pred {
+        let mut clauses = Vec::new();
+        for a in &self.clauses {
+            for b in &o.clauses {
+                clauses.push(a.iter().chain(b).cloned().collect())
+            }
+        };
+        Pred { clauses }.normal()
+    }
+
+    pub fn not(&self) -> Pred {
+        self.clauses.iter().fold(Pred::tt(), |acc, clause| {
+            let neg = Pred {
+                clauses: clause.iter().flat_map(|(n, i)| i.complement().into_iter().map(move |c| vec![(*n, c)])).collect()
+            };
+            acc.and(&neg)
+        })
+    }
+
+    pub fn satisfiable(&self) -> bool {
+        !self.clauses.is_empty()
+    }
+
+    /// Whether every element satisfying `self` satisfies `o`.
+    #[allow(dead_code)]
+    pub fn implies(&self, o: &Pred) -> bool {
+        !self.and(&o.not()).satisfiable()
+    }
+
+    /// Whether no element satisfies both.
+    pub fn disjoint(&self, o: &Pred) -> bool {
+        !self.and(o).satisfiable()
+    }
+
+    /// Each clause with one interval per name, and the empty ones dropped.
+    fn normal(self) -> Pred {
+        let clauses = self.clauses.into_iter().filter_map(|clause| {
+            let mut merged: Vec<(&'static str, Interval)> = Vec::new();
+            for (n, i) in clause {
+                match merged.iter_mut().find(|(m, _)| *m == n) {
+                    Some((_, j)) => *j = j.meet(&i),
+                    None => merged.push((n, i))
+                }
+            };
+            if merged.iter().any(|(_, i)| i.is_empty()) { None } else { Some(merged) }
+        }).collect();
+        Pred { clauses }.capped()
+    }
+
+    fn capped(self) -> Pred {
+        if self.clauses.len() > MAX_CLAUSES { Pred::tt() } else { self }
+    }
+}
+
+/// Symbolic forms of predicates, by function address. Only `sat` and
+/// `try_sat` add to it, keyed by the pointer they put in the node, so a
+/// lookup with a pointer copied out of a query finds it whatever address
+/// the compiler would have given `phi` elsewhere.
+fn declared_preds() -> &'static Mutex<HashMap<usize, Arc<Pred>>> {
+    static DECLARED: OnceLock<Mutex<HashMap<usize, Arc<Pred>>>> = OnceLock::new();
+    DECLARED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn declare<D>(phi: fn(&D) -> bool, pred: Pred) {
+    declared_preds().lock().unwrap().insert(phi as usize, Arc::new(pred));
+}
+
+/// `Sat{phi, op}`, telling the engine that `phi` checks `pred`. Nothing
+/// checks that it's true, and a wrong declaration does more than make
+/// `validate` and `complexity` wrong: solvers drop the parts of a query
+/// it shows can never match (see `prune`), so their matches are silently
+/// lost.
+#[allow(dead_code)]
+pub fn sat<D,C>(phi: fn(&D) -> bool, op: fn(&D) -> C, pred: Pred) -> QRE<D,C> {
+    declare(phi, pred);
+    Sat{phi, op}
+}
+
+/// `TrySat{phi, op}`, declared as for `sat`.
+#[allow(dead_code)]
+pub fn try_sat<D,C>(phi: fn(&D) -> bool, op: fn(&D) -> Result<C, fallible::Error>, pred: Pred) -> QRE<D,C> {
+    declare(phi, pred);
+    TrySat{phi, op}
+}
+
+pub fn declared<D>(phi: fn(&D) -> bool) -> Option<Arc<Pred>> {
+    declared_preds().lock().unwrap().get(&(phi as usize)).cloned()
+}
+
+/// What the first element of a non-empty match of `q` has to satisfy, as
+/// far as the declared predicates tell (undeclared ones could be
+/// anything).
+pub fn first<D,C>(q: &QRE<D,C>) -> Pred {
+    match q {
+        Bot | Eps{..} => Pred::ff(),
+        Sat{phi, ..} | TrySat{phi, ..} => declared(*phi).map_or(Pred::tt(), |p| (*p).clone()),
+        Choice{v} => v.iter().fold(Pred::ff(), |acc, q| acc.or(&first(q))),
+        Split{f, g, ..} => if nullable(f) { first(f).or(&first(g)) } else { first(f) },
+        Iter{init, body, ..} => if nullable(init) { first(init).or(&first(body)) } else { first(init) },
+        App{f, ..} | Tag{f, ..} | Cap{f, ..} | Trigger{body: f, ..} => first(f),
+        // Both sides match the same elements.
+        Combine{f, g, ..} | TryCombine{f, g, ..} => first(f).and(&first(g)),
+    }
+}
+
+fn nullable<D,C>(q: &QRE<D,C>) -> bool {
+    complexity::lengths(q).is_some_and(|(lo, _)| lo == 0)
+}
+
+/// Whether no element can start a match of more than one of `v`, and none
+/// of them matches the empty stream: then `v`'s `Choice` is unambiguous
+/// wherever its branches are.
+pub fn disjoint_branches<D,C>(v: &[QRE<D,C>]) -> bool {
+    if v.iter().any(nullable) {
+        return false
+    };
+    let firsts: Vec<Pred> = v.iter().map(first).collect();
+    firsts.iter().enumerate().all(|(i, a)| firsts[i + 1..].iter().all(|b| a.disjoint(b)))
+}
+
+/// Why, if it's so, the declared predicates show `q` itself can never
+/// match: a predicate no element satisfies, or a `Combine` whose sides
+/// can't start on the same element.
+pub fn dead<D,C>(q: &QRE<D,C>) -> Option<&'static str> {
+    match q {
+        Sat{phi, ..} | TrySat{phi, ..} if declared(*phi).is_some_and(|p| !p.satisfiable()) => {
+            Some("predicate is unsatisfiable")
+        },
+        Combine{f, g, ..} | TryCombine{f, g, ..} => {
+            if !(nullable(f) && nullable(g)) && first(f).disjoint(&first(g)) {
+                Some("its two sides never start on the same element")
+            }
+            else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+/// `q` with every node `dead` finds replaced by `Bot`, children first, so
+/// a `Combine` is judged on its sides as pruned; see `canon::prune`.
+pub fn prune<D,C: Clone>(q: &QRE<D,C>) -> QRE<D,C> {
+    let child = |q: &Arc<QRE<D,C>>| Arc::new(prune(q));
+    let q = match q {
+        Choice{v} => Choice{v: v.iter().map(prune).collect()},
+        Split{f, g, op} => Split{f: child(f), g: child(g), op: *op},
+        Combine{f, g, op} => Combine{f: child(f), g: child(g), op: *op},
+        TryCombine{f, g, op} => TryCombine{f: child(f), g: child(g), op: *op},
+        Iter{init, body, op} => Iter{init: child(init), body: child(body), op: *op},
+        App{f, op} => App{f: child(f), op: op.clone()},
+        Tag{name, f} => Tag{name, f: child(f)},
+        Cap{f, caps, later} => Cap{f: child(f), caps: caps.clone(), later: *later},
+        Trigger{body, action} => Trigger{body: child(body), action: action.clone()},
+        q => q.clone()
+    };
+    if dead(&q).is_some() { Bot } else { q }
+}
@@ -0,0 +1,329 @@
+use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{TcpListener, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::Solve;
+
+/// A log entry, from syslog or the journal. Fields the source left out
+/// are `None`.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct LogRecord {
+    pub facility: u8,
+    /// 0 (emergency) to 7 (debug).
+    pub severity: u8,
+    /// Seconds since the Unix epoch.
+    pub time: Option<f64>,
+    pub host: Option<String>,
+    pub app: Option<String>,
+    pub procid: Option<String>,
+    pub msgid: Option<String>,
+    /// Structured data, as (SD-ID, param, value).
+    pub params: Params,
+    pub message: String,
+}
+
+impl LogRecord {
+    /// Severity 3 (error) or worse.
+    pub fn is_error(&self) -> bool {
+        self.severity <= 3
+    }
+
+    #[allow(dead_code)]
+    pub fn param(&self, id: &str, name: &str) -> Option<&str> {
+        self.params.iter().find(|(i, n, _)| i == id && n == name).map(|(_, _, v)| v.as_str())
+    }
+}
+
+/// Parses an RFC 5424 message:
+/// `<PRI>1 TIMESTAMP HOST APP PROCID MSGID [SD...] MSG`.
+pub fn parse_rfc5424(line: &str) -> Result<LogRecord, String> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let rest = line.strip_prefix('<').ok_or("no PRI")?;
+    let (pri, rest) = rest.split_once('>').ok_or("no PRI")?;
+    let pri: u8 = pri.parse().map_err(|_| format!("bad PRI {}", pri))?;
+    let (version, rest) = rest.split_once(' ').ok_or("truncated header")?;
+    if version != "1" {
+        return Err(format!("not RFC 5424 (version {})", version))
+    };
+    let mut fields = rest.splitn(6, ' ');
+    let mut field = || -> Result<Option<String>, String> {
+        match fields.next() {
+            Some("-") => Ok(None),
+            Some(f) => Ok(Some(f.to_string())),
+            None => Err("truncated header".to_string())
+        }
+    };
+    let time = field()?.map(|t| parse_rfc3339(&t)).transpose()?;
+    let (host, app, procid, msgid) = (field()?, field()?, field()?, field()?);
+    let rest = fields.next().unwrap_or("");
+    let (params, msg) = structured_data(rest)?;
+    Ok(LogRecord {
+        facility: pri >> 3,
+        severity: pri & 7,
+        time, host, app, procid, msgid, params,
+        message: msg.trim_start_matches('\u{feff}').to_string()
+    })
+}
+
+type Params = Vec<(String, String, String)>;
+
+/// Splits `[id k="v" ...][...] MSG` (or `- MSG`) into the parameters and
+/// the message.
+fn structured_data(s: &str) -> Result<(Params, &str), String> {
+    let mut params = Vec::new();
+    if let Some(rest) = s.strip_prefix('-') {
+        return Ok((params, rest.strip_prefix(' ').unwrap_or(rest)))
+    };
+    let mut rest = s;
+    while let Some(r) = rest.strip_prefix('[') {
+        let end = r.find([' ', ']']).ok_or("unterminated SD-ELEMENT")?;
+        let id = r[..end].to_string();
+        rest = &r[end..];
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if let Some(r) = rest.strip_prefix(']') {
+                rest = r;
+                break
+            };
+            let (name, r) = rest.split_once("=\"").ok_or("bad SD-PARAM")?;
+            let mut value = String::new();
+            let mut chars = r.char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c @ ('"' | '\\' | ']'))) => value.push(c),
+                        Some((_, c)) => { value.push('\\'); value.push(c) },
+                        None => return Err("unterminated SD-PARAM".to_string())
+                    },
+                    Some((i, '"')) => break i + 1,
+                    Some((_, c)) => value.push(c),
+                    None => return Err("unterminated SD-PARAM".to_string())
+                }
+            };
+            params.push((id.clone(), name.to_string(), value));
+            rest = &r[end..]
+        }
+    };
+    Ok((params, rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+/// `2003-10-11T22:14:15.003Z` or with a `+hh:mm` offset, as seconds
+/// since the Unix epoch.
+fn parse_rfc3339(t: &str) -> Result<f64, String> {
+    let bad = || format!("bad timestamp {}", t);
+    let num = |s: &str| s.parse::<i64>().map_err(|_| bad());
+    // Sliced by byte below, which only lines up with chars in ASCII.
+    if !t.is_ascii() || t.len() < 20 || &t[4..5] != "-" || &t[7..8] != "-" || &t[10..11] != "T" {
+        return Err(bad())
+    };
+    let (y, m, d) = (num(&t[..4])?, num(&t[5..7])?, num(&t[8..10])?);
+    let (hh, mm, ss) = (num(&t[11..13])?, num(&t[14..16])?, num(&t[17..19])?);
+    let mut rest = &t[19..];
+    let mut frac = 0.0;
+    if rest.starts_with('.') {
+        let end = rest[1..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len(), |i| i + 1);
+        frac = rest[..end].parse::<f64>().map_err(|_| bad())?;
+        rest = &rest[end..]
+    };
+    let offset = match rest {
+        "Z" => 0,
+        _ if rest.len() == 6 => {
+            let sign = match &rest[..1] { "+" => 1, "-" => -1, _ => return Err(bad()) };
+            sign * (num(&rest[1..3])? * 3600 + num(&rest[4..6])? * 60)
+        },
+        _ => return Err(bad())
+    };
+    // Days since 1970-01-01 in the proleptic Gregorian calendar.
+    let (y, m) = if m <= 2 { (y - 1, m + 9) } else { (y, m - 3) };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Ok((days * 86400 + hh * 3600 + mm * 60 + ss - offset) as f64 + frac)
+}
+
+/// The longest binary journal field `JournalEntries` will read.
+pub const MAX_FIELD: u64 = 1 << 26;
+
+/// Reads `journalctl -o export` output, one record per entry.
+pub struct JournalEntries<R> {
+    r: R,
+}
+
+impl <R: BufRead> JournalEntries<R> {
+    #[allow(dead_code)]
+    pub fn new(r: R) -> Self {
+        JournalEntries { r }
+    }
+
+    fn entry(&mut self) -> io::Result<Option<LogRecord>> {
+        let mut rec = LogRecord { severity: 6, facility: 1, ..LogRecord::default() };
+        let mut any = false;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.r.read_until(b'\n', &mut line)? == 0 {
+                return Ok(if any { Some(rec) } else { None })
+            };
+            if line == b"\n" {
+                if any { return Ok(Some(rec)) } else { continue }
+            };
+            any = true;
+            let text = String::from_utf8_lossy(&line);
+            let (name, value) = match text.trim_end_matches('\n').split_once('=') {
+                Some((n, v)) => (n.to_string(), v.to_string()),
+                None => {
+                    // Binary-safe field: the name alone, then a 64-bit
+                    // little-endian length, the data and a newline.
+                    let name = text.trim_end_matches('\n').to_string();
+                    let mut n = [0; 8];
+                    self.r.read_exact(&mut n)?;
+                    let n = u64::from_le_bytes(n);
+                    if n > MAX_FIELD {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("journal field of {} bytes", n)))
+                    };
+                    // Read as it comes rather than allocated up front, in
+                    // case the length is a lie.
+                    let mut data = Vec::new();
+                    if (&mut self.r).take(n + 1).read_to_end(&mut data)? as u64 != n + 1 {
+                        return Err(io::ErrorKind::UnexpectedEof.into())
+                    };
+                    data.pop();
+                    (name, String::from_utf8_lossy(&data).into_owned())
+                }
+            };
+            match name.as_str() {
+                "MESSAGE" => rec.message = value,
+                "PRIORITY" => rec.severity = value.parse().unwrap_or(rec.severity),
+                "SYSLOG_FACILITY" => rec.facility = value.parse().unwrap_or(rec.facility),
+                "_HOSTNAME" => rec.host = Some(value),
+                "SYSLOG_IDENTIFIER" => rec.app = Some(value),
+                "_PID" => rec.procid = Some(value),
+                "__REALTIME_TIMESTAMP" => rec.time = value.parse::<f64>().ok().map(|us| us / 1e6),
+                _ => ()
+            }
+        }
+    }
+}
+
+impl <R: BufRead> Iterator for JournalEntries<R> {
+    type Item = io::Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entry().transpose()
+    }
+}
+
+fn feed<C>(solver: &Mutex<Solve<LogRecord,C>>, msg: &str) where C: Clone + Debug + Send + Sync {
+    match parse_rfc5424(msg) {
+        Ok(rec) => solver.lock().unwrap().update(rec),
+        Err(e) => eprintln!("skipping syslog message: {}", e)
+    }
+}
+
+/// Feeds `solver` the syslog messages sent to `addr` over UDP, one per
+/// datagram. Runs until the socket fails.
+#[allow(dead_code)]
+pub fn listen_udp<A,C>(addr: A, solver: Arc<Mutex<Solve<LogRecord,C>>>) -> io::Result<()>
+    where A: ToSocketAddrs, C: Clone + Debug + Send + Sync {
+    let socket = UdpSocket::bind(addr)?;
+    let mut buf = vec![0; 65536];
+    loop {
+        let n = socket.recv(&mut buf)?;
+        feed(&solver, &String::from_utf8_lossy(&buf[..n]))
+    }
+}
+
+/// The longest message `listen_tcp` accepts, as large as a UDP datagram
+/// can be; RFC 5425 only asks receivers to take 2048 octets.
+pub const MAX_FRAME: u64 = 65536;
+
+/// Feeds `solver` the syslog messages sent to `addr` over TCP, framed
+/// either by octet counting (`LEN MSG`) or by newlines (RFC 6587), one
+/// thread per connection. A connection sending a frame longer than
+/// `MAX_FRAME` is dropped. Runs until the listener fails.
+#[allow(dead_code)]
+pub fn listen_tcp<A,C>(addr: A, solver: Arc<Mutex<Solve<LogRecord,C>>>) -> io::Result<()>
+    where A: ToSocketAddrs, C: Clone + Debug + Send + Sync + 'static {
+    let listener = TcpListener::bind(addr)?;
+    for conn in listener.incoming() {
+        let mut r = BufReader::new(conn?);
+        let solver = solver.clone();
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                let octet_counted = match r.fill_buf()?.first() {
+                    None => return Ok(()),
+                    Some(b) => b.is_ascii_digit()
+                };
+                let too_long = || io::Error::new(io::ErrorKind::InvalidData, "frame too long");
+                let mut msg = Vec::new();
+                if octet_counted {
+                    (&mut r).take(20).read_until(b' ', &mut msg)?;
+                    let n: u64 = String::from_utf8_lossy(&msg).trim().parse()
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad frame length"))?;
+                    if n > MAX_FRAME {
+                        return Err(too_long())
+                    };
+                    msg = vec![0; n as usize];
+                    r.read_exact(&mut msg)?
+                }
+                else if (&mut r).take(MAX_FRAME + 1).read_until(b'\n', &mut msg)? as u64 > MAX_FRAME
+                    && msg.last() != Some(&b'\n') {
+                    return Err(too_long())
+                };
+                feed(&solver, &String::from_utf8_lossy(&msg))
+            }
+        });
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rfc5424() {
+        let r = parse_rfc5424("<165>1 2003-10-11T22:14:15.003Z host app 42 ID47 [ex@1 a=\"x\\\"y\"] hi\n").unwrap();
+        assert_eq!((r.facility, r.severity), (20, 5));
+        assert_eq!(r.time, Some(1065910455.003));
+        assert_eq!((r.host.as_deref(), r.procid.as_deref()), (Some("host"), Some("42")));
+        assert_eq!(r.param("ex@1", "a"), Some("x\"y"));
+        assert_eq!(r.message, "hi");
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_rfc3339("2003-10-11T22:14:15+01:00"), Ok(1065906855.0));
+    }
+
+    #[test]
+    fn reads_journal_export() {
+        let mut export = b"__REALTIME_TIMESTAMP=1500000000000000\nPRIORITY=3\nMESSAGE\n".to_vec();
+        export.extend_from_slice(&5u64.to_le_bytes());
+        export.extend_from_slice(b"a\nb=c\n\n_PID=7\n");
+        let recs: Vec<LogRecord> = JournalEntries::new(&export[..]).map(|r| r.unwrap()).collect();
+        assert_eq!(recs.len(), 2);
+        assert_eq!((recs[0].time, recs[0].severity, recs[0].message.as_str()), (Some(1.5e9), 3, "a\nb=c"));
+        assert_eq!(recs[1].procid.as_deref(), Some("7"));
+    }
+
+    #[test]
+    fn rejects_huge_journal_fields() {
+        for n in [u64::MAX, MAX_FIELD + 1, 100] {
+            let mut export = b"MESSAGE\n".to_vec();
+            export.extend_from_slice(&n.to_le_bytes());
+            export.extend_from_slice(b"short\n");
+            assert!(JournalEntries::new(&export[..]).next().unwrap().is_err())
+        }
+    }
+
+    #[test]
+    fn rejects_non_ascii_timestamps() {
+        assert!(parse_rfc5424("<13>1 200\u{e9}-10-11T22:14:15Z h a p m - msg").is_err());
+        assert!(parse_rfc3339("2003-10-11T22:14:15+01:0\u{e9}").is_err());
+    }
+}
@@ -0,0 +1,21 @@
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use super::QRE;
+
+/// `f`, with every value it produces during evaluation also passed to
+/// `callback` under `name`: a `Trigger`, so that includes values on
+/// branches the query as a whole later rules out, and a tap sees what `f`
+/// computed, not just what ended up in the output.
+pub fn tap<D,C,F>(name: &'static str, f: QRE<D,C>, callback: F) -> QRE<D,C>
+    where F: Fn(&'static str, &C) + Send + Sync + 'static {
+    QRE::Trigger{body: Arc::new(f), action: Arc::new(move |c: &C| callback(name, c))}
+}
+
+/// A `tap` sending `f`'s values down a channel, e.g. to a thread consuming
+/// them as a second output. Values sent after the receiver hangs up are
+/// dropped.
+pub fn tap_to<D,C>(name: &'static str, f: QRE<D,C>, tx: Sender<(&'static str, C)>) -> QRE<D,C>
+    where C: Clone + Send + 'static {
+    tap(name, f, move |name, c: &C| { let _ = tx.send((name, c.clone())); })
+}
@@ -0,0 +1,71 @@
+use std::fmt::{self, Debug};
+
+use super::{Solve, QRE};
+
+/// A stream on which a solver's output isn't the reference's.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Counterexample<D,C> {
+    pub stream: Vec<D>,
+    /// `None` where the query should be undefined.
+    pub expected: Option<C>,
+    pub got: Option<C>,
+    /// Whether it was the solver reading the stream back to front (see
+    /// `Solve::new_reverse`).
+    pub reverse: bool,
+}
+
+impl <D: Debug, C: Debug> fmt::Display for Counterexample<D,C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "on {:?}{}: expected {:?}, got {:?}", self.stream, if self.reverse { " (reversed)" } else { "" },
+               self.expected, self.got)
+    }
+}
+
+/// Runs `q` on every stream of up to `max_len` elements of `alphabet`,
+/// read both ways, and checks its output on each against `reference`,
+/// which gives the value `q` should have on a stream (`None` where it
+/// should be undefined: no match, or matches with different values).
+/// Returns how many streams it checked, or the first that failed; streams
+/// are checked shortest first, so that's a shortest counterexample. Meant
+/// for trusting `deriv` and `epsilon` (and `rderiv`) on a new combinator:
+/// a handful of symbols up to length 6 or so covers the corner cases, and
+/// the count grows as `alphabet.len()` to the `max_len`.
+pub fn exhaustive<D,C,F>(q: &QRE<D,C>, alphabet: &[D], max_len: usize, mut reference: F)
+    -> Result<u64, Counterexample<D,C>>
+    where D: Clone, C: Clone + Debug + PartialEq + Send + Sync + 'static, F: FnMut(&[D]) -> Option<C> {
+    let mut checked = 0;
+    for reverse in [false, true] {
+        let s = if reverse { Solve::new_reverse(q.clone()) } else { Solve::new(q.clone()) };
+        // Iterative deepening: the streams sharing a prefix (a suffix,
+        // reversed) share the solver that's read it, and each length is
+        // done before the next.
+        for len in 0..=max_len {
+            let mut stream = Vec::with_capacity(len);
+            check(&s, alphabet, len, reverse, &mut stream, &mut reference, &mut checked)?
+        }
+    };
+    Ok(checked)
+}
+
+/// Checks every stream of `len` more elements extending `stream`, which
+/// `s` has read (`stream` is kept back to front for a reverse solver).
+fn check<D,C,F>(s: &Solve<D,C>, alphabet: &[D], len: usize, reverse: bool, stream: &mut Vec<D>, reference: &mut F,
+                checked: &mut u64) -> Result<(), Counterexample<D,C>>
+    where D: Clone, C: Clone + Debug + PartialEq + Send + Sync + 'static, F: FnMut(&[D]) -> Option<C> {
+    if len == 0 {
+        if !reverse {
+            *checked += 1
+        };
+        let input: Vec<D> = if reverse { stream.iter().rev().cloned().collect() } else { stream.clone() };
+        let (expected, got) = (reference(&input), s.defined_output());
+        return if expected == got { Ok(()) } else { Err(Counterexample { stream: input, expected, got, reverse }) }
+    };
+    for d in alphabet {
+        let mut next = s.clone();
+        next.update(d);
+        stream.push(d.clone());
+        check(&next, alphabet, len - 1, reverse, stream, reference, checked)?;
+        stream.pop();
+    };
+    Ok(())
+}
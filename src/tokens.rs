@@ -0,0 +1,143 @@
+use std::io::{self, BufRead};
+
+use lines::Pattern;
+
+/// A piece of text, and where it starts in the source, in bytes.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub offset: usize,
+}
+
+impl Token {
+    /// Its length in chars.
+    pub fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+/// How text is cut into tokens, so queries over text can be written over
+/// words (say) rather than chars.
+#[derive(Clone,Debug)]
+pub enum Tokenizer {
+    /// Runs of anything but whitespace.
+    #[allow(dead_code)]
+    Whitespace,
+    /// Runs of letters and digits, keeping apostrophes between them
+    /// (`don't`); punctuation is dropped.
+    Words,
+    /// User-perceived characters: a char with the combining marks,
+    /// variation selectors and skin-tone modifiers after it, emoji joined
+    /// by zero-width joiners, flags (pairs of regional indicators), and
+    /// `\r\n`. An approximation of Unicode's extended grapheme clusters,
+    /// without its tables.
+    Graphemes,
+    /// Each match of the pattern, leftmost first.
+    #[allow(dead_code)]
+    Pattern(Pattern),
+}
+
+impl Tokenizer {
+    /// The tokens of `text`.
+    pub fn split(&self, text: &str) -> Vec<Token> {
+        self.split_at(text, 0)
+    }
+
+    /// The tokens of `r`, read a line at a time; no token spans lines.
+    /// Offsets count from the start of `r`.
+    pub fn read<R: BufRead>(&self, r: R) -> Tokens<'_, R> {
+        Tokens { tokenizer: self, r, offset: 0, pending: Vec::new() }
+    }
+
+    fn split_at(&self, text: &str, base: usize) -> Vec<Token> {
+        let token = |a: usize, b: usize| Token { text: text[a..b].to_string(), offset: base + a };
+        let runs = |keep: &dyn Fn(&[char], usize) -> bool| -> Vec<Token> {
+            let chars: Vec<(usize, char)> = text.char_indices().collect();
+            let cs: Vec<char> = chars.iter().map(|(_, c)| *c).collect();
+            let mut out = Vec::new();
+            let mut start = None;
+            for i in 0..=cs.len() {
+                match (start, i < cs.len() && keep(&cs, i)) {
+                    (None, true) => start = Some(i),
+                    (Some(s), false) => {
+                        let end = chars.get(i).map_or(text.len(), |(b, _)| *b);
+                        out.push(token(chars[s].0, end));
+                        start = None
+                    },
+                    _ => ()
+                }
+            };
+            out
+        };
+        match self {
+            Tokenizer::Whitespace => runs(&|cs, i| !cs[i].is_whitespace()),
+            Tokenizer::Words => runs(&|cs, i| {
+                cs[i].is_alphanumeric()
+                    || (cs[i] == '\'' && i > 0 && cs[i - 1].is_alphanumeric()
+                        && cs.get(i + 1).is_some_and(|c| c.is_alphanumeric()))
+            }),
+            Tokenizer::Graphemes => {
+                let chars: Vec<(usize, char)> = text.char_indices().collect();
+                let mut out = Vec::new();
+                let mut i = 0;
+                while i < chars.len() {
+                    let start = i;
+                    i += 1;
+                    while i < chars.len() && continues(chars[i - 1].1, chars[i].1, i - start) {
+                        i += 1
+                    };
+                    let end = chars.get(i).map_or(text.len(), |(b, _)| *b);
+                    out.push(token(chars[start].0, end))
+                };
+                out
+            },
+            Tokenizer::Pattern(p) => p.spans(text).into_iter().map(|(a, b)| token(a, b)).collect()
+        }
+    }
+}
+
+/// Whether `c`, after `prev`, belongs to the same grapheme, whose first
+/// `len` chars have been seen.
+fn continues(prev: char, c: char, len: usize) -> bool {
+    let regional = |c: char| ('\u{1F1E6}'..='\u{1F1FF}').contains(&c);
+    matches!(c, '\u{0300}'..='\u{036F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}'
+             | '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}' | '\u{FE00}'..='\u{FE0F}'
+             | '\u{1F3FB}'..='\u{1F3FF}' | '\u{200D}')
+        || prev == '\u{200D}'
+        || (prev == '\r' && c == '\n')
+        || (regional(prev) && regional(c) && len == 1)
+}
+
+/// The tokens of a reader; see `Tokenizer::read`.
+pub struct Tokens<'a, R> {
+    tokenizer: &'a Tokenizer,
+    r: R,
+    offset: usize,
+    // The rest of the current line's tokens, last first.
+    pending: Vec<Token>,
+}
+
+impl <'a, R: BufRead> Iterator for Tokens<'a, R> {
+    type Item = io::Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            match self.r.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(n) => {
+                    self.pending = self.tokenizer.split_at(&line, self.offset);
+                    self.pending.reverse();
+                    self.offset += n
+                },
+                Err(e) => return Some(Err(e))
+            }
+        };
+        self.pending.pop().map(Ok)
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::QRE;
+use super::QRE::*;
+use complexity;
+
+/// A value an aggregating node (`Iter`, `Combine`, `TryCombine`) produced:
+/// an `Iter`'s accumulator after another iteration, say.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Step<C> {
+    /// Which element (counting from 0) produced it.
+    pub element: u64,
+    /// The node, as in `complexity`.
+    pub path: String,
+    pub value: C,
+}
+
+impl <C: Debug> fmt::Display for Step<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} {}: {:?}", self.element, self.path, self.value)
+    }
+}
+
+/// Where a traced query's `Trigger`s write; see `Solve::new_traced`.
+pub struct Trace<C> {
+    steps: Arc<Mutex<Vec<Step<C>>>>,
+    elements: Arc<AtomicU64>,
+}
+
+impl <C> Clone for Trace<C> {
+    fn clone(&self) -> Self {
+        Trace { steps: self.steps.clone(), elements: self.elements.clone() }
+    }
+}
+
+impl <C: Clone> Trace<C> {
+    pub fn steps(&self) -> Vec<Step<C>> {
+        self.steps.lock().unwrap().clone()
+    }
+
+    /// Moves on to the next element.
+    pub fn advance(&self) {
+        self.elements.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// `q` with each aggregating node wrapped in a `Trigger` recording its
+/// values in the returned `Trace`.
+pub fn traced<D,C>(q: &QRE<D,C>) -> (QRE<D,C>, Trace<C>) where C: Clone + Send + 'static {
+    let trace = Trace { steps: Arc::new(Mutex::new(Vec::new())), elements: Arc::new(AtomicU64::new(0)) };
+    (wrap(q, complexity::kind(q).to_string(), &trace), trace)
+}
+
+fn wrap<D,C>(q: &QRE<D,C>, path: String, trace: &Trace<C>) -> QRE<D,C> where C: Clone + Send + 'static {
+    let child = |name: &str, q: &QRE<D,C>| Arc::new(wrap(q, format!("{}.{}/{}", path, name, complexity::kind(q)), trace));
+    let q = match q {
+        Choice{v} => Choice{v: v.iter().enumerate().map(|(i, q)| {
+            wrap(q, format!("{}[{}]/{}", path, i, complexity::kind(q)), trace)
+        }).collect()},
+        Split{f, g, op} => Split{f: child("f", f), g: child("g", g), op: *op},
+        Combine{f, g, op} => Combine{f: child("f", f), g: child("g", g), op: *op},
+        TryCombine{f, g, op} => TryCombine{f: child("f", f), g: child("g", g), op: *op},
+        Iter{init, body, op} => Iter{init: child("init", init), body: child("body", body), op: *op},
+        App{f, op} => App{f: child("f", f), op: op.clone()},
+        Tag{name, f} => Tag{name, f: child("f", f)},
+        Cap{f, caps, later} => Cap{f: child("f", f), caps: caps.clone(), later: *later},
+        Trigger{body, action} => Trigger{body: child("body", body), action: action.clone()},
+        q => q.clone()
+    };
+    match q {
+        Iter{..} | Combine{..} | TryCombine{..} => {
+            let (steps, elements) = (trace.steps.clone(), trace.elements.clone());
+            Trigger{body: Arc::new(q), action: Arc::new(move |c: &C| {
+                let element = elements.load(Ordering::Relaxed);
+                steps.lock().unwrap().push(Step { element, path: path.clone(), value: c.clone() })
+            })}
+        },
+        q => q
+    }
+}
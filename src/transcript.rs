@@ -0,0 +1,190 @@
+use std::fmt::{Debug, Write as _};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use super::{AppOp, QRE};
+use super::QRE::*;
+use plan;
+
+/// How `Solve::trace_to` writes its account of each update.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Format {
+    /// A few indented lines per update, for reading.
+    Text,
+    /// One JSON object per line, for tools.
+    Json,
+}
+
+/// Past this many chars a residual is cut short with `...`; residuals
+/// share subtrees, and written out as trees they can get big.
+const MAX_WIDTH: usize = 1000;
+
+/// One update, as `trace_to` writes it.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Entry {
+    /// Counting from 0 at the `trace_to` call.
+    pub element: u64,
+    pub input: String,
+    pub backend: plan::Backend,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    /// The values the residuals would give if the stream stopped here.
+    pub epsilons: Vec<String>,
+}
+
+/// Where a solver's updates are written; see `Solve::trace_to`.
+pub struct Transcript<D> {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    format: Format,
+    // `Debug::fmt` for the elements, taken where `D: Debug`.
+    show: fn(&D) -> String,
+    elements: u64,
+}
+
+impl <D> Clone for Transcript<D> {
+    fn clone(&self) -> Self {
+        Transcript { out: self.out.clone(), format: self.format, show: self.show, elements: self.elements }
+    }
+}
+
+fn show<D: Debug>(d: &D) -> String {
+    format!("{:?}", d)
+}
+
+impl <D> Transcript<D> {
+    pub fn new<W: Write + Send + 'static>(w: W, format: Format) -> Self where D: Debug {
+        Transcript { out: Arc::new(Mutex::new(Box::new(w))), format, show, elements: 0 }
+    }
+
+    /// Writes the entry for `d`, the next element.
+    pub fn write(&mut self, d: &D, backend: plan::Backend, before: Vec<String>, after: Vec<String>,
+                 epsilons: Vec<String>) -> std::io::Result<()> {
+        let e = Entry { element: self.elements, input: (self.show)(d), backend, before, after, epsilons };
+        self.elements += 1;
+        let text = match self.format {
+            Format::Text => text(&e),
+            Format::Json => json(&e),
+        };
+        let mut out = self.out.lock().unwrap();
+        out.write_all(text.as_bytes())?;
+        out.flush()
+    }
+}
+
+fn text(e: &Entry) -> String {
+    let mut s = format!("element #{}: {} ({})\n", e.element, e.input, format!("{:?}", e.backend).to_lowercase());
+    for (label, qs) in [("before", &e.before), ("after", &e.after)] {
+        let mut label = format!("residuals {}:", label);
+        if qs.is_empty() {
+            let _ = writeln!(s, "  {:<18} none", label);
+        };
+        for q in qs {
+            let _ = writeln!(s, "  {:<18} {}", label, q);
+            label.clear()
+        }
+    };
+    let _ = writeln!(s, "  {:<18} [{}]", "epsilon values:", e.epsilons.join(", "));
+    s
+}
+
+fn json(e: &Entry) -> String {
+    let list = |xs: &[String]| format!("[{}]", xs.iter().map(|x| quote(x)).collect::<Vec<_>>().join(","));
+    format!("{{\"element\":{},\"input\":{},\"backend\":{},\"before\":{},\"after\":{},\"epsilons\":{}}}\n",
+            e.element, quote(&e.input), quote(&format!("{:?}", e.backend).to_lowercase()),
+            list(&e.before), list(&e.after), list(&e.epsilons))
+}
+
+/// `s` as a JSON string.
+fn quote(s: &str) -> String {
+    let mut q = String::with_capacity(s.len() + 2);
+    q.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => q.push_str("\\\""),
+            '\\' => q.push_str("\\\\"),
+            '\n' => q.push_str("\\n"),
+            '\r' => q.push_str("\\r"),
+            '\t' => q.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(q, "\\u{:04x}", c as u32); },
+            c => q.push(c)
+        }
+    };
+    q.push('"');
+    q
+}
+
+/// Each residual in `qs` written out, e.g. `Iter(Eps(3.0), Sat)`.
+/// Functions have no names to show, so only constants (`Eps`, and the
+/// values partial applications hold) and tags say more than the node's
+/// kind: `App(op(3.0, _), Eps(4.0))` is an op applied to 3.0 and `f`'s
+/// value, whatever the op is.
+pub fn residuals<D,C: Debug>(qs: &[QRE<D,C>]) -> Vec<String> {
+    qs.iter().map(|q| {
+        let mut s = String::new();
+        if render(q, &mut s).is_err() {
+            s.truncate(s.floor_char_boundary(MAX_WIDTH));
+            s.push_str("...")
+        };
+        s
+    }).collect()
+}
+
+/// Fails once `s` is past `MAX_WIDTH`.
+fn render<D,C: Debug>(q: &QRE<D,C>, s: &mut String) -> Result<(), ()> {
+    if s.len() > MAX_WIDTH {
+        return Err(())
+    };
+    let args = |s: &mut String, name: &str, qs: &[&QRE<D,C>]| {
+        s.push_str(name);
+        s.push('(');
+        for (i, q) in qs.iter().enumerate() {
+            if i > 0 { s.push_str(", ") };
+            render(q, s)?
+        };
+        s.push(')');
+        Ok(())
+    };
+    match q {
+        Bot => s.push_str("Bot"),
+        Eps{c} => { let _ = write!(s, "Eps({:?})", c); },
+        Sat{..} => s.push_str("Sat"),
+        TrySat{..} => s.push_str("TrySat"),
+        Choice{v} => {
+            s.push_str("Choice[");
+            for (i, q) in v.iter().enumerate() {
+                if i > 0 { s.push_str(", ") };
+                render(q, s)?
+            };
+            s.push(']')
+        },
+        Split{f, g, ..} => args(s, "Split", &[f, g])?,
+        Combine{f, g, ..} => args(s, "Combine", &[f, g])?,
+        TryCombine{f, g, ..} => args(s, "TryCombine", &[f, g])?,
+        Iter{init, body, ..} => args(s, "Iter", &[init, body])?,
+        App{f, op} => {
+            s.push_str("App(");
+            render_op(op, s);
+            s.push_str(", ");
+            render(f, s)?;
+            s.push(')')
+        },
+        Tag{name, f} => args(s, &format!("Tag {}", name), &[f])?,
+        Cap{f, ..} => args(s, "Cap", &[f])?,
+        Trigger{body, ..} => args(s, "Trigger", &[body])?,
+    };
+    Ok(())
+}
+
+fn render_op<C: Debug>(op: &AppOp<C>, s: &mut String) {
+    match op {
+        AppOp::Fn(_) => s.push_str("fn"),
+        AppOp::Try(_) => s.push_str("try fn"),
+        AppOp::Left(_, c) => { let _ = write!(s, "op({:?}, _)", c); },
+        AppOp::Right(_, c) => { let _ = write!(s, "op(_, {:?})", c); },
+        AppOp::Then(first, then) => {
+            render_op(first, s);
+            s.push_str(" then ");
+            render_op(then, s)
+        },
+    }
+}
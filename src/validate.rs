@@ -0,0 +1,193 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use super::QRE;
+use super::QRE::*;
+use complexity;
+use isolate;
+use lines::{Field, Line};
+#[cfg(feature = "symbolic")]
+use symbolic;
+
+/// What a stream's elements can look like, for `QRE::validate`.
+pub struct Schema<D> {
+    examples: Vec<D>,
+    // Whether `examples` is every element there can be, so a predicate
+    // none of them satisfy is certainly impossible.
+    exhaustive: bool,
+}
+
+/// Beyond this many combinations of field values, `Schema::fields` takes
+/// a spread of them instead.
+const MAX_EXAMPLES: usize = 4096;
+
+impl <D> Schema<D> {
+    /// A finite domain: the stream only ever holds these values.
+    pub fn alphabet(symbols: Vec<D>) -> Self {
+        Schema { examples: symbols, exhaustive: true }
+    }
+
+    /// Records with these fields, each taking one of its `Values`.
+    pub fn fields(fields: Vec<(&'static str, Values)>) -> Self where D: Record {
+        let sizes: Vec<usize> = fields.iter().map(|(_, v)| v.len().max(1)).collect();
+        let all = sizes.iter().try_fold(1usize, |n, k| n.checked_mul(*k)).filter(|n| *n <= MAX_EXAMPLES);
+        let n = all.unwrap_or(*sizes.iter().max().unwrap_or(&1));
+        let examples = (0..n).map(|i| {
+            // Every combination, or else the ith value of each field.
+            let mut rest = i;
+            let values: Vec<(&'static str, Field)> = fields.iter().zip(&sizes).map(|((name, v), k)| {
+                let j = if all.is_some() { let j = rest % k; rest /= k; j } else { i % k };
+                (*name, v.get(j))
+            }).collect();
+            D::example(&values)
+        }).collect();
+        Schema { examples, exhaustive: false }
+    }
+}
+
+/// The values a field of a `Schema::fields` record can take.
+#[derive(Clone,Debug)]
+pub enum Values {
+    Num(Vec<f64>),
+    Text(Vec<String>),
+}
+
+impl Values {
+    /// A spread of numbers: signs, small counts, the usual status codes,
+    /// and large.
+    pub fn num() -> Self {
+        Values::Num(vec![-1e6, -1.0, 0.0, 0.5, 1.0, 2.0, 10.0, 100.0, 200.0, 404.0, 500.0, 503.0, 1e3, 1e6])
+    }
+
+    /// Text taking one of `alternatives`.
+    pub fn text(alternatives: &[&str]) -> Self {
+        Values::Text(alternatives.iter().map(|s| s.to_string()).collect())
+    }
+
+    fn len(&self) -> usize {
+        match self { Values::Num(v) => v.len(), Values::Text(v) => v.len() }
+    }
+
+    fn get(&self, i: usize) -> Field {
+        match self {
+            Values::Num(v) => Field::Num(v.get(i).cloned().unwrap_or(0.0)),
+            Values::Text(v) => Field::Text(v.get(i).cloned().unwrap_or_default())
+        }
+    }
+}
+
+/// Record types `Schema::fields` can make examples of.
+pub trait Record {
+    fn example(fields: &[(&'static str, Field)]) -> Self;
+}
+
+impl Record for Line {
+    fn example(fields: &[(&'static str, Field)]) -> Self {
+        Line { line: String::new(), fields: fields.iter().map(|(n, f)| (n.to_string(), f.clone())).collect() }
+    }
+}
+
+thread_local! {
+    // Projections that came up empty while `validate` was running.
+    static MISSES: RefCell<Option<HashSet<String>>> = const { RefCell::new(None) };
+}
+
+/// Notes a projection that found no field `name` of the type it wanted,
+/// if `validate` is running; record accessors call this.
+pub fn missed(name: &str, wanted: &str) {
+    MISSES.with(|m| {
+        if let Some(m) = m.borrow_mut().as_mut() {
+            m.insert(format!("looks up {} field `{}`, which the schema doesn't have", wanted, name));
+        }
+    })
+}
+
+impl <D,C> QRE<D,C> {
+    /// Checks the query against `schema` before it sees any data: each
+    /// predicate should hold for some element, ops shouldn't fail or panic
+    /// on the elements their predicates accept, projections should find
+    /// fields of the type they expect, and the query should be able to
+    /// match something at all. Returns every problem found, each starting
+    /// with where it is in the query (as in `complexity`). Predicates and
+    /// ops are all that can be run without values; ops combining values
+    /// aren't checked. With the `symbolic` feature, predicates declared
+    /// through `symbolic::sat` are also reasoned about directly, which
+    /// finds ones that can never hold and `Combine`s whose sides can never
+    /// match the same elements, whatever the schema.
+    pub fn validate(&self, schema: &Schema<D>) -> Result<(), Vec<String>> where C: Clone {
+        let mut problems = Vec::new();
+        let mut dead = HashSet::new();
+        complexity::each(self, &mut |path, q| {
+            #[cfg(feature = "symbolic")]
+            if let Some(why) = symbolic::dead(q) {
+                dead.insert(q as *const QRE<D,C>);
+                problems.push(format!("{}: {}", path, why));
+                return
+            };
+            let accepted = match q {
+                Sat{phi, op} => check(path, *phi, &|d| { op(d); Ok(()) }, schema, &mut problems),
+                TrySat{phi, op} => {
+                    check(path, *phi, &|d| op(d).map(|_| ()).map_err(|e| e.to_string()), schema, &mut problems)
+                },
+                _ => return
+            };
+            if !accepted {
+                dead.insert(q as *const QRE<D,C>);
+                problems.push(format!("{}: predicate holds for {} element", path,
+                                      if schema.exhaustive { "no" } else { "no example" }))
+            }
+        });
+        if complexity::lengths(&prune(self, &dead)).is_none() {
+            problems.push("the query can never match".to_string())
+        };
+        if problems.is_empty() { Ok(()) } else { Err(problems) }
+    }
+}
+
+/// Runs the predicate at `path`, and its op where it holds, on every
+/// example; returns whether the predicate held for any.
+fn check<D>(path: &str, phi: fn(&D) -> bool, op: &dyn Fn(&D) -> Result<(), String>,
+            schema: &Schema<D>, problems: &mut Vec<String>) -> bool {
+    MISSES.with(|m| *m.borrow_mut() = Some(HashSet::new()));
+    let mut accepted = false;
+    let mut failed = None;
+    for (i, d) in schema.examples.iter().enumerate() {
+        match isolate::catch(|| phi(d)) {
+            Some(true) => {
+                accepted = true;
+                let r = isolate::catch(|| op(d)).unwrap_or_else(|| Err("panicked".to_string()));
+                if let (Err(e), None) = (r, &failed) {
+                    failed = Some(format!("{}: op fails on example {}: {}", path, i, e))
+                }
+            },
+            Some(false) => (),
+            None => if failed.is_none() {
+                failed = Some(format!("{}: predicate panics on example {}", path, i))
+            }
+        }
+    };
+    let mut misses: Vec<String> = MISSES.with(|m| m.borrow_mut().take()).unwrap_or_default().into_iter().collect();
+    misses.sort();
+    problems.extend(misses.into_iter().map(|m| format!("{}: {}", path, m)));
+    problems.extend(failed);
+    accepted
+}
+
+/// `q` with the nodes in `dead` replaced by `Bot`.
+fn prune<D,C: Clone>(q: &QRE<D,C>, dead: &HashSet<*const QRE<D,C>>) -> QRE<D,C> {
+    let p = |a: &Arc<QRE<D,C>>| Arc::new(prune(a, dead));
+    match q {
+        _ if dead.contains(&(q as *const QRE<D,C>)) => Bot,
+        Choice{v} => Choice{v: v.iter().map(|q| prune(q, dead)).collect()},
+        Split{f, g, op} => Split{f: p(f), g: p(g), op: *op},
+        Combine{f, g, op} => Combine{f: p(f), g: p(g), op: *op},
+        TryCombine{f, g, op} => TryCombine{f: p(f), g: p(g), op: *op},
+        Iter{init, body, op} => Iter{init: p(init), body: p(body), op: *op},
+        App{f, op} => App{f: p(f), op: op.clone()},
+        Tag{name, f} => Tag{name, f: p(f)},
+        Cap{f, caps, later} => Cap{f: p(f), caps: caps.clone(), later: *later},
+        Trigger{body, action} => Trigger{body: p(body), action: action.clone()},
+        q => q.clone()
+    }
+}
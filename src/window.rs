@@ -0,0 +1,131 @@
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+
+use super::{one, One, Pool, QRE};
+use ehist::ExpHistogram;
+use ops::{Group, Monoid};
+
+/// Aggregates `body`'s value on each of the last `size` elements. `body`
+/// is matched against one element at a time; when an element falls out
+/// of the window its contribution is retracted from the running total,
+/// so an update is O(1) in the window size.
+pub struct Window<D,G: 'static> {
+    body: QRE<D,G>,
+    size: usize,
+    // One entry per element in the window, oldest first; `None` if `body`
+    // was ambiguous on that element.
+    contribs: VecDeque<Option<G>>,
+    undefined: usize,
+    acc: G,
+    pool: Pool<D,G>,
+    buf: Vec<QRE<D,G>>,
+}
+
+impl <D,G> Window<D,G> where G: Group + Clone + Debug + Send + Sync {
+    pub fn new(body: QRE<D,G>, size: usize) -> Self {
+        Self {
+            body,
+            size,
+            contribs: VecDeque::with_capacity(size + 1),
+            undefined: 0,
+            acc: G::unit(),
+            pool: Pool::new(),
+            buf: Vec::new()
+        }
+    }
+
+    /// Elements `body` doesn't match contribute the unit.
+    pub fn update<B: Borrow<D>>(&mut self, d: B) {
+        let c = match one(&self.body, d.borrow(), &mut self.pool, &mut self.buf) {
+            One::NoMatch => Some(G::unit()),
+            One::Value(c) => Some(c),
+            One::Ambiguous => None
+        };
+        match c {
+            Some(ref c) => self.acc = G::combine(self.acc.clone(), c.clone()),
+            None => self.undefined += 1
+        };
+        self.contribs.push_back(c);
+        if self.contribs.len() > self.size {
+            self.expire()
+        }
+    }
+
+    /// Drops the oldest element from the window early, e.g. when it's
+    /// aged out of a time-based window.
+    pub fn expire(&mut self) {
+        match self.contribs.pop_front() {
+            Some(Some(c)) => self.acc = G::retract(self.acc.clone(), c),
+            Some(None) => self.undefined -= 1,
+            None => ()
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.contribs.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.contribs.is_empty()
+    }
+
+    pub fn output(&self) -> Result<G, String> {
+        if self.undefined == 0 {
+            Ok(self.acc.clone())
+        }
+        else {
+            Err("undefined".to_string())
+        }
+    }
+}
+
+/// Like `Window`, but for aggregates that can't be retracted (max,
+/// distinct count): the elements are kept in an `ExpHistogram`, so the
+/// result is over the last `size` elements plus at most `size / k` older
+/// ones, in O(k log size) space.
+pub struct ApproxWindow<D,M: 'static> {
+    body: QRE<D,M>,
+    hist: ExpHistogram<M>,
+    // Elements since `body` was last ambiguous, if it ever was.
+    since_ambiguous: Option<u64>,
+    pool: Pool<D,M>,
+    buf: Vec<QRE<D,M>>,
+}
+
+impl <D,M> ApproxWindow<D,M> where M: Monoid + Clone + Debug + Send + Sync {
+    pub fn new(body: QRE<D,M>, size: u64, k: usize) -> Self {
+        Self {
+            body,
+            hist: ExpHistogram::new(size, k),
+            since_ambiguous: None,
+            pool: Pool::new(),
+            buf: Vec::new()
+        }
+    }
+
+    /// Elements `body` doesn't match contribute the unit.
+    pub fn update<B: Borrow<D>>(&mut self, d: B) {
+        self.since_ambiguous = self.since_ambiguous.map(|n| n + 1);
+        let c = match one(&self.body, d.borrow(), &mut self.pool, &mut self.buf) {
+            One::NoMatch => M::unit(),
+            One::Value(c) => c,
+            One::Ambiguous => { self.since_ambiguous = Some(0); M::unit() }
+        };
+        self.hist.push(c)
+    }
+
+    pub fn output(&self) -> Result<M, String> {
+        match self.since_ambiguous {
+            Some(n) if n < self.hist.coverage() => Err("undefined".to_string()),
+            _ => Ok(self.hist.query())
+        }
+    }
+
+    /// How many of the most recent elements `output` is over.
+    pub fn coverage(&self) -> u64 {
+        self.hist.coverage()
+    }
+}
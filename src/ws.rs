@@ -0,0 +1,284 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Solve;
+use runtime::{reconnecting, Backoff, Decode};
+
+/// Subscribes to the WebSocket at `url` (`ws://host[:port][/path]`) and
+/// feeds `solver` each text message, as decoded by `decode`; messages it
+/// rejects are reported on stderr and skipped. When the connection drops
+/// or the server closes it, reconnects according to `backoff`, and only
+/// returns once that gives up. `wss://` needs TLS, which this crate
+/// doesn't have.
+#[allow(dead_code)]
+pub fn subscribe<D,C>(url: &str, solver: Arc<Mutex<Solve<D,C>>>, decode: Decode<D>, backoff: Backoff) -> io::Result<()>
+    where C: Clone + Debug + Send + Sync {
+    let (host, path) = parse_url(url)?;
+    reconnecting(backoff, |connected| session(&host, &path, &solver, &decode, connected))
+}
+
+fn parse_url(url: &str) -> io::Result<(String, String)> {
+    let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+    if url.starts_with("wss://") {
+        return Err(bad("wss:// needs TLS, which this build doesn't have"))
+    };
+    let rest = url.strip_prefix("ws://").ok_or_else(|| bad("not a ws:// URL"))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/")
+    };
+    let host = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    Ok((host, path.to_string()))
+}
+
+/// How often to ping the server. It has to answer, so silence for twice
+/// this long means the connection's gone, whether or not TCP has noticed.
+const PING_EVERY: Duration = Duration::from_secs(30);
+
+/// The longest message we'll take, over all its frames; a longer one
+/// closes the connection with status 1009 (too big).
+const MAX_MESSAGE: u64 = 1 << 20;
+
+/// One connection, from the handshake until the server closes it (`Ok`)
+/// or it fails.
+fn session<D,C>(host: &str, path: &str, solver: &Mutex<Solve<D,C>>, decode: &Decode<D>,
+                connected: &mut bool) -> io::Result<()>
+    where C: Clone + Debug + Send + Sync {
+    let conn = TcpStream::connect(host)?;
+    conn.set_read_timeout(Some(PING_EVERY * 2))?;
+    let mut w = conn.try_clone()?;
+    let mut r = BufReader::new(conn);
+    let key = base64(&nonce());
+    write!(w, "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+               Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+           path, host, key)?;
+    let mut line = String::new();
+    r.read_line(&mut line)?;
+    if line.split_whitespace().nth(1) != Some("101") {
+        return Err(io::Error::other(format!("handshake refused: {}", line.trim())))
+    };
+    // The server proves it read our key (and isn't, say, a cache replaying
+    // an old upgrade) by hashing it into the accept header.
+    let mut accepted = false;
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break
+        };
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-accept") {
+                accepted = value.trim() == accept(&key)
+            }
+        }
+    };
+    if !accepted {
+        return Err(io::Error::other("handshake without a matching Sec-WebSocket-Accept"))
+    };
+    *connected = true;
+    // Frames are written whole under the lock, so pings don't land in the
+    // middle of a pong. The pinger stops once the socket's shut down below.
+    let w = Arc::new(Mutex::new(w));
+    let pinger = w.clone();
+    thread::spawn(move || {
+        loop {
+            thread::sleep(PING_EVERY);
+            if write_frame(&mut *pinger.lock().unwrap(), OP_PING, &[]).is_err() {
+                return
+            }
+        }
+    });
+    let result = receive(&mut r, &w, solver, decode);
+    let _ = w.lock().unwrap().shutdown(Shutdown::Both);
+    result
+}
+
+/// Feeds `solver` messages until the server closes the connection.
+fn receive<R,D,C>(r: &mut R, w: &Mutex<TcpStream>, solver: &Mutex<Solve<D,C>>, decode: &Decode<D>) -> io::Result<()>
+    where R: Read, C: Clone + Debug + Send + Sync {
+    // A message can come in several frames.
+    let mut msg = Vec::new();
+    loop {
+        let (fin, opcode, len, mask) = read_header(r)?;
+        // Control frames are at most 125 bytes; data frames count toward
+        // their message.
+        let room = if opcode >= OP_CLOSE { 125 } else { MAX_MESSAGE - msg.len() as u64 };
+        if len > room {
+            let _ = write_frame(&mut *w.lock().unwrap(), OP_CLOSE, &TOO_BIG.to_be_bytes());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message over {} bytes", MAX_MESSAGE)))
+        };
+        let payload = read_payload(r, len, mask)?;
+        match opcode {
+            OP_CONT | OP_TEXT | OP_BINARY => {
+                msg.extend_from_slice(&payload);
+                if fin {
+                    let text = String::from_utf8_lossy(&msg);
+                    match decode(&text) {
+                        Ok(d) => solver.lock().unwrap().update(d),
+                        Err(e) => eprintln!("skipping message: {}", e)
+                    };
+                    msg.clear()
+                }
+            },
+            OP_PING => write_frame(&mut *w.lock().unwrap(), OP_PONG, &payload)?,
+            OP_CLOSE => {
+                let _ = write_frame(&mut *w.lock().unwrap(), OP_CLOSE, &payload);
+                return Ok(())
+            },
+            _ => ()
+        }
+    }
+}
+
+const OP_CONT: u8 = 0;
+const OP_TEXT: u8 = 1;
+const OP_BINARY: u8 = 2;
+const OP_CLOSE: u8 = 8;
+const OP_PING: u8 = 9;
+const OP_PONG: u8 = 10;
+
+/// The close status for a message too big to process.
+const TOO_BIG: u16 = 1009;
+
+/// A frame's fin bit, opcode, payload length and mask.
+fn read_header<R: Read>(r: &mut R) -> io::Result<(bool, u8, u64, Option<[u8; 4]>)> {
+    let mut h = [0; 2];
+    r.read_exact(&mut h)?;
+    let len = match h[1] & 0x7f {
+        126 => { let mut b = [0; 2]; r.read_exact(&mut b)?; u16::from_be_bytes(b) as u64 },
+        127 => { let mut b = [0; 8]; r.read_exact(&mut b)?; u64::from_be_bytes(b) },
+        n => n as u64
+    };
+    // Servers aren't meant to mask, but unmasking costs nothing.
+    let mut mask = None;
+    if h[1] & 0x80 != 0 {
+        let mut m = [0; 4];
+        r.read_exact(&mut m)?;
+        mask = Some(m)
+    };
+    Ok((h[0] & 0x80 != 0, h[0] & 0x0f, len, mask))
+}
+
+fn read_payload<R: Read>(r: &mut R, len: u64, mask: Option<[u8; 4]>) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    r.take(len).read_to_end(&mut payload)?;
+    if (payload.len() as u64) < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame"))
+    };
+    if let Some(m) = mask {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= m[i % 4]
+        }
+    };
+    Ok(payload)
+}
+
+/// Writes a control frame. Clients have to mask what they send.
+fn write_frame<W: Write>(w: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let m = nonce();
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len().min(125) as u8];
+    frame.extend_from_slice(&m[..4]);
+    frame.extend(payload.iter().take(125).enumerate().map(|(i, b)| b ^ m[i % 4]));
+    w.write_all(&frame)
+}
+
+/// 16 bytes that differ from call to call; WebSocket keys and masks only
+/// need to be unpredictable to intermediaries, not cryptographically.
+fn nonce() -> [u8; 16] {
+    let mut out = [0; 16];
+    for (i, chunk) in out.chunks_mut(8).enumerate() {
+        let mut h = DefaultHasher::new();
+        (Instant::now(), thread::current().id(), i).hash(&mut h);
+        chunk.copy_from_slice(&h.finish().to_le_bytes())
+    };
+    out
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char)
+            }
+            else {
+                out.push('=')
+            }
+        }
+    };
+    out
+}
+
+/// The `Sec-WebSocket-Accept` a server should answer `key` with.
+fn accept(key: &str) -> String {
+    base64(&sha1(format!("{}258EAFA5-E914-47DA-95CA-C5AB0DC85B11", key).as_bytes()))
+}
+
+/// SHA-1, which the handshake needs; nothing here relies on it being
+/// collision-resistant.
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut padded = bytes.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0)
+    };
+    padded.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1], block[4 * i + 2], block[4 * i + 3]])
+        };
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1)
+        };
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5a827999),
+                1 => (b ^ c ^ d, 0x6ed9eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6)
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t
+        };
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e]) {
+            *x = x.wrapping_add(y)
+        }
+    };
+    let mut out = [0; 20];
+    for (i, x) in h.iter().enumerate() {
+        out[4 * i..4 * i + 4].copy_from_slice(&x.to_be_bytes())
+    };
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_rfc_example() {
+        // RFC 6455, section 1.3.
+        assert_eq!(accept("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    }
+
+    #[test]
+    fn hashes_across_blocks() {
+        let digest: String = sha1(&[b'a'; 1000]).iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(digest, "291e9a6c66994949b57ba5e650361e98fc36b1ba")
+    }
+}